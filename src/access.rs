@@ -0,0 +1,214 @@
+use std::{
+    future::{ready, Ready},
+    path::Path,
+    rc::Rc,
+};
+
+use actix_identity::Identity;
+use actix_web::{
+    body::{BoxBody, MessageBody},
+    dev::{forward_ready, Service, ServiceRequest, ServiceResponse, Transform},
+    http::StatusCode,
+    web, Error, HttpResponse,
+};
+use futures::future::LocalBoxFuture;
+
+use crate::{
+    model::{Role, User},
+    State,
+};
+
+/// Guards everything under `/@{username}/{name}`: private repositories look
+/// like they don't exist to users without at least the read role, and requests
+/// for a repository that was renamed or transferred are redirected to where it
+/// is now. Repository directories are named after the repository alone, so a
+/// name that exists on disk but not for this owner is not found either.
+///
+/// Handlers still check the role needed for changes themselves.
+pub struct RepositoryAccess;
+
+impl<S, B> Transform<S, ServiceRequest> for RepositoryAccess
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    B: MessageBody + 'static,
+{
+    type Response = ServiceResponse<BoxBody>;
+    type Error = Error;
+    type Transform = RepositoryAccessMiddleware<S>;
+    type InitError = ();
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(RepositoryAccessMiddleware {
+            service: Rc::new(service),
+        }))
+    }
+}
+
+pub struct RepositoryAccessMiddleware<S> {
+    service: Rc<S>,
+}
+
+impl<S, B> Service<ServiceRequest> for RepositoryAccessMiddleware<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    B: MessageBody + 'static,
+{
+    type Response = ServiceResponse<BoxBody>;
+    type Error = Error;
+    type Future = LocalBoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    forward_ready!(service);
+
+    fn call(&self, mut req: ServiceRequest) -> Self::Future {
+        let service = Rc::clone(&self.service);
+        Box::pin(async move {
+            let state = req.app_data::<web::Data<State>>().cloned();
+            let target = split_path(req.path());
+            let (Some(state), Some((username, name, rest))) = (state, target) else {
+                return service.call(req).await.map(|res| res.map_into_boxed_body());
+            };
+
+            let identity = match req.extract::<Option<Identity>>().await {
+                Ok(Some(identity)) => match identity.id() {
+                    Ok(id) => state.database.find_user_from_id(&id).await,
+                    Err(_) => None,
+                },
+                _ => None,
+            };
+            let user = state.database.find_user(&username).await;
+            let query = req.query_string().to_owned();
+            match state.database.find_repository(user.as_ref(), &name).await {
+                Some(repository) if !repository.can(&user, &identity, Role::Read) => {
+                    return Ok(req.into_response(HttpResponse::NotFound().finish()));
+                }
+                Some(_) => {}
+                // The directory is someone else's repository, which handlers
+                // opening it by name must never reach through this owner.
+                None if Path::new(&name).exists() => {
+                    let response =
+                        match find_redirect(&state, &identity, user.as_ref(), &name, &rest, &query)
+                            .await
+                        {
+                            Some(location) => HttpResponse::MovedPermanently()
+                                .insert_header(("Location", location))
+                                .finish(),
+                            None => HttpResponse::NotFound().finish(),
+                        };
+                    return Ok(req.into_response(response));
+                }
+                None => {}
+            }
+
+            let response = service.call(req).await?;
+            if response.status() != StatusCode::NOT_FOUND {
+                return Ok(response.map_into_boxed_body());
+            }
+            match find_redirect(&state, &identity, user.as_ref(), &name, &rest, &query).await {
+                Some(location) => Ok(response.into_response(
+                    HttpResponse::MovedPermanently()
+                        .insert_header(("Location", location))
+                        .finish(),
+                )),
+                None => Ok(response.map_into_boxed_body()),
+            }
+        })
+    }
+}
+
+/// The owner, repository name and rest of a `/@{username}/{name}/…` path.
+fn split_path(path: &str) -> Option<(String, String, String)> {
+    let (username, rest) = path.strip_prefix("/@")?.split_once('/')?;
+    let (name, rest) = rest.split_once('/').unwrap_or((rest, ""));
+    if name.is_empty() {
+        return None;
+    }
+    Some((username.to_owned(), name.to_owned(), rest.to_owned()))
+}
+
+/// Where the repository that used to be `user`/`name` is now, if it was
+/// renamed or transferred and the signed in user may see it.
+async fn find_redirect(
+    state: &State,
+    identity: &Option<User>,
+    user: Option<&User>,
+    name: &str,
+    rest: &str,
+    query: &str,
+) -> Option<String> {
+    let repository = state.database.find_redirect(user?, name).await?;
+    let owner = state
+        .database
+        .find_user_from_id(&repository.user_id.to_string())
+        .await;
+    if !repository.can(&owner, identity, Role::Read) {
+        return None;
+    }
+    let owner = owner?;
+
+    let mut location = format!("/@{}/{}", owner.username, repository.name);
+    if !rest.is_empty() {
+        location = format!("{location}/{rest}");
+    }
+    if !query.is_empty() {
+        location = format!("{location}?{query}");
+    }
+    Some(location)
+}
+
+/// Signs suspended users out, so they lose access on their next request rather
+/// than when their session expires.
+pub struct SignOutSuspended;
+
+impl<S, B> Transform<S, ServiceRequest> for SignOutSuspended
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    B: MessageBody + 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type Transform = SignOutSuspendedMiddleware<S>;
+    type InitError = ();
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(SignOutSuspendedMiddleware {
+            service: Rc::new(service),
+        }))
+    }
+}
+
+pub struct SignOutSuspendedMiddleware<S> {
+    service: Rc<S>,
+}
+
+impl<S, B> Service<ServiceRequest> for SignOutSuspendedMiddleware<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    B: MessageBody + 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type Future = LocalBoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    forward_ready!(service);
+
+    fn call(&self, mut req: ServiceRequest) -> Self::Future {
+        let service = Rc::clone(&self.service);
+        Box::pin(async move {
+            let state = req.app_data::<web::Data<State>>().cloned();
+            if let (Some(state), Ok(Some(identity))) =
+                (state, req.extract::<Option<Identity>>().await)
+            {
+                let user = match identity.id() {
+                    Ok(id) => state.database.find_user_from_id(&id).await,
+                    Err(_) => None,
+                };
+                if user.is_some_and(|user| user.suspended) {
+                    identity.logout();
+                }
+            }
+            service.call(req).await
+        })
+    }
+}
@@ -0,0 +1,512 @@
+use std::{collections::HashMap, path::Path};
+
+use actix_identity::Identity;
+use actix_web::{web, HttpResponse, Responder};
+use askama::Template;
+use askama_actix::TemplateToResponse;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    credentials, disk,
+    model::{AuditEntry, Event, Invite, Registration, Repository, SiteSettings, User},
+    State,
+};
+
+/// Rows on each page of the users, repositories and audit log lists.
+const PER_PAGE: u64 = 50;
+
+/// The signed in user if they are a site administrator. Everyone else gets a
+/// 404, as if there was no admin area.
+async fn admin(state: &State, identity: Option<Identity>) -> Result<User, HttpResponse> {
+    let user = match identity.map(|identity| identity.id()) {
+        Some(Ok(id)) => state.database.find_user_from_id(&id).await,
+        _ => {
+            return Err(HttpResponse::SeeOther()
+                .insert_header(("Location", "/login"))
+                .finish())
+        }
+    };
+    user.filter(|user| user.admin)
+        .ok_or_else(|| HttpResponse::NotFound().finish())
+}
+
+/// Position in a paginated list, with the query strings of its neighbours.
+pub struct Pages {
+    pub page: u64,
+    pub pages: u64,
+    pub previous: Option<String>,
+    pub next: Option<String>,
+}
+
+impl Pages {
+    fn new<Q: Serialize>(page: u64, total: u64, query: impl Fn(u64) -> Q) -> Self {
+        let pages = total.div_ceil(PER_PAGE).max(1);
+        let link = |page| serde_urlencoded::to_string(query(page)).unwrap_or_default();
+        Self {
+            page,
+            pages,
+            previous: (page > 1).then(|| link(page - 1)),
+            next: (page < pages).then(|| link(page + 1)),
+        }
+    }
+}
+
+fn first_page() -> u64 {
+    1
+}
+
+/// Number of rows to skip to show `page`.
+fn skip(page: u64) -> u64 {
+    (page.max(1) - 1) * PER_PAGE
+}
+
+#[derive(Template)]
+#[template(path = "admin/index.html")]
+struct IndexTemplate<'a> {
+    title: &'a str,
+    identity: &'a Option<User>,
+    users: u64,
+    repositories: u64,
+    settings: &'a SiteSettings,
+    invites: &'a [Invite],
+}
+
+pub async fn index(state: web::Data<State>, identity: Option<Identity>) -> impl Responder {
+    let identity = match admin(&state, identity).await {
+        Ok(identity) => identity,
+        Err(response) => return response,
+    };
+    let (_, users) = state.database.find_users("", 0, 1).await;
+    let (_, repositories) = state.database.find_repositories("", 0, 1).await;
+    let settings = state.database.site_settings().await;
+    let invites = state.database.find_invites().await;
+
+    IndexTemplate {
+        title: "admin",
+        identity: &Some(identity),
+        users,
+        repositories,
+        settings: &settings,
+        invites: &invites,
+    }
+    .to_response()
+}
+
+#[derive(Debug, Deserialize)]
+pub struct RegistrationForm {
+    registration: String,
+}
+
+/// Sets who can sign up.
+pub async fn registration(
+    state: web::Data<State>,
+    identity: Option<Identity>,
+    form: web::Form<RegistrationForm>,
+) -> impl Responder {
+    let identity = match admin(&state, identity).await {
+        Ok(identity) => identity,
+        Err(response) => return response,
+    };
+
+    let Some(registration) = Registration::parse(&form.registration) else {
+        return HttpResponse::BadRequest()
+            .body(format!("unknown registration mode '{}'", form.registration));
+    };
+
+    state.database.set_registration(registration).await;
+    state
+        .database
+        .add_user_log(
+            &identity,
+            Event::AdminRegistration,
+            Some(registration.as_str().to_owned()),
+        )
+        .await;
+    redirect_to_index()
+}
+
+fn redirect_to_index() -> HttpResponse {
+    HttpResponse::SeeOther()
+        .insert_header(("Location", "/admin"))
+        .finish()
+}
+
+/// Creates an invite code for someone to sign up with.
+pub async fn new_invite(state: web::Data<State>, identity: Option<Identity>) -> impl Responder {
+    let identity = match admin(&state, identity).await {
+        Ok(identity) => identity,
+        Err(response) => return response,
+    };
+
+    let invite = state.database.new_invite(&identity).await;
+    state
+        .database
+        .add_user_log(&identity, Event::AdminInviteCreate, Some(invite._id))
+        .await;
+    redirect_to_index()
+}
+
+#[derive(Debug, Deserialize)]
+pub struct InviteForm {
+    code: String,
+}
+
+/// Withdraws an invite nobody used yet.
+pub async fn delete_invite(
+    state: web::Data<State>,
+    identity: Option<Identity>,
+    form: web::Form<InviteForm>,
+) -> impl Responder {
+    let identity = match admin(&state, identity).await {
+        Ok(identity) => identity,
+        Err(response) => return response,
+    };
+    if !state.database.delete_invite(&form.code).await {
+        return HttpResponse::NotFound().body("there is no such unused invite");
+    }
+
+    state
+        .database
+        .add_user_log(&identity, Event::AdminInviteDelete, Some(form.code.clone()))
+        .await;
+    redirect_to_index()
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SearchQuery {
+    #[serde(default)]
+    q: String,
+    #[serde(default = "first_page")]
+    page: u64,
+}
+
+#[derive(Template)]
+#[template(path = "admin/users.html")]
+struct UsersTemplate<'a> {
+    title: &'a str,
+    identity: &'a Option<User>,
+    query: &'a str,
+    users: &'a [User],
+    pages: &'a Pages,
+}
+
+pub async fn users(
+    state: web::Data<State>,
+    identity: Option<Identity>,
+    query: web::Query<SearchQuery>,
+) -> impl Responder {
+    let identity = match admin(&state, identity).await {
+        Ok(identity) => identity,
+        Err(response) => return response,
+    };
+    let (users, total) = state
+        .database
+        .find_users(query.q.trim(), skip(query.page), PER_PAGE as i64)
+        .await;
+    let pages = Pages::new(query.page, total, |page| SearchQuery {
+        page,
+        ..query.0.clone()
+    });
+
+    UsersTemplate {
+        title: "users",
+        identity: &Some(identity),
+        query: &query.q,
+        users: &users,
+        pages: &pages,
+    }
+    .to_response()
+}
+
+/// A repository as listed in the admin area.
+pub struct RepositoryRow {
+    pub owner: String,
+    pub repository: Repository,
+    pub disk_usage: String,
+}
+
+#[derive(Template)]
+#[template(path = "admin/repositories.html")]
+struct RepositoriesTemplate<'a> {
+    title: &'a str,
+    identity: &'a Option<User>,
+    query: &'a str,
+    repositories: &'a [RepositoryRow],
+    pages: &'a Pages,
+}
+
+pub async fn repositories(
+    state: web::Data<State>,
+    identity: Option<Identity>,
+    query: web::Query<SearchQuery>,
+) -> impl Responder {
+    let identity = match admin(&state, identity).await {
+        Ok(identity) => identity,
+        Err(response) => return response,
+    };
+    let (repositories, total) = state
+        .database
+        .find_repositories(query.q.trim(), skip(query.page), PER_PAGE as i64)
+        .await;
+    let pages = Pages::new(query.page, total, |page| SearchQuery {
+        page,
+        ..query.0.clone()
+    });
+
+    let mut owners: HashMap<_, String> = HashMap::new();
+    let mut rows = Vec::new();
+    for repository in repositories {
+        let owner = match owners.get(&repository.user_id) {
+            Some(owner) => owner.clone(),
+            None => {
+                let owner = state
+                    .database
+                    .find_user_from_id(&repository.user_id.to_string())
+                    .await
+                    .map(|owner| owner.username)
+                    .unwrap_or_default();
+                owners.insert(repository.user_id, owner.clone());
+                owner
+            }
+        };
+        let size = disk::usage(Path::new(&repository.name));
+        rows.push(RepositoryRow {
+            owner,
+            disk_usage: humansize::format_size(size, humansize::DECIMAL.decimal_places(1)),
+            repository,
+        });
+    }
+
+    RepositoriesTemplate {
+        title: "repositories",
+        identity: &Some(identity),
+        query: &query.q,
+        repositories: &rows,
+        pages: &pages,
+    }
+    .to_response()
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LogQuery {
+    #[serde(default)]
+    user: String,
+    /// Prefix of the events to show, such as `admin.` or `repository.delete`.
+    #[serde(default)]
+    event: String,
+    #[serde(default = "first_page")]
+    page: u64,
+}
+
+#[derive(Template)]
+#[template(path = "admin/log.html")]
+struct LogTemplate<'a> {
+    title: &'a str,
+    identity: &'a Option<User>,
+    query: &'a LogQuery,
+    entries: &'a [AuditEntry],
+    pages: &'a Pages,
+}
+
+/// The log entries of every user, newest first.
+pub async fn log(
+    state: web::Data<State>,
+    identity: Option<Identity>,
+    query: web::Query<LogQuery>,
+) -> impl Responder {
+    let identity = match admin(&state, identity).await {
+        Ok(identity) => identity,
+        Err(response) => return response,
+    };
+    let (entries, total) = state
+        .database
+        .find_audit_log(
+            query.user.trim().trim_start_matches('@'),
+            query.event.trim(),
+            skip(query.page),
+            PER_PAGE as i64,
+        )
+        .await;
+    let pages = Pages::new(query.page, total, |page| LogQuery {
+        page,
+        ..query.0.clone()
+    });
+
+    LogTemplate {
+        title: "audit log",
+        identity: &Some(identity),
+        query: &query,
+        entries: &entries,
+        pages: &pages,
+    }
+    .to_response()
+}
+
+/// The admin and the user named in the path, who must be someone else.
+async fn load(
+    state: &State,
+    identity: Option<Identity>,
+    username: &str,
+) -> Result<(User, User), HttpResponse> {
+    let identity = admin(state, identity).await?;
+    let Some(user) = state.database.find_user(username).await else {
+        return Err(HttpResponse::NotFound().body(format!("no user named '{username}'")));
+    };
+    if user._id == identity._id {
+        return Err(HttpResponse::BadRequest().body("you can't do this to your own account"));
+    }
+    Ok((identity, user))
+}
+
+fn redirect_to_users() -> HttpResponse {
+    HttpResponse::SeeOther()
+        .insert_header(("Location", "/admin/users"))
+        .finish()
+}
+
+#[derive(Debug, Deserialize)]
+pub struct SuspendForm {
+    suspended: bool,
+}
+
+/// Suspends a user, who is signed out and can't sign in again, or lifts the
+/// suspension.
+pub async fn suspend(
+    path: web::Path<String>,
+    state: web::Data<State>,
+    identity: Option<Identity>,
+    form: web::Form<SuspendForm>,
+) -> impl Responder {
+    let username = path.into_inner();
+    let (identity, user) = match load(&state, identity, &username).await {
+        Ok(loaded) => loaded,
+        Err(response) => return response,
+    };
+
+    state.database.set_suspended(&user, form.suspended).await;
+    let event = if form.suspended {
+        Event::AdminUserSuspend
+    } else {
+        Event::AdminUserUnsuspend
+    };
+    state
+        .database
+        .add_user_log(&identity, event, Some(user.username))
+        .await;
+    redirect_to_users()
+}
+
+#[derive(Debug, Deserialize)]
+pub struct AdminForm {
+    admin: bool,
+}
+
+/// Makes a user a site administrator or takes it away.
+pub async fn promote(
+    path: web::Path<String>,
+    state: web::Data<State>,
+    identity: Option<Identity>,
+    form: web::Form<AdminForm>,
+) -> impl Responder {
+    let username = path.into_inner();
+    let (identity, user) = match load(&state, identity, &username).await {
+        Ok(loaded) => loaded,
+        Err(response) => return response,
+    };
+    if user.is_organization() {
+        return HttpResponse::BadRequest().body("organizations can't be administrators");
+    }
+
+    state.database.set_admin(&user, form.admin).await;
+    let event = if form.admin {
+        Event::AdminGrant
+    } else {
+        Event::AdminRevoke
+    };
+    state
+        .database
+        .add_user_log(&identity, event, Some(user.username))
+        .await;
+    redirect_to_users()
+}
+
+#[derive(Template)]
+#[template(path = "admin/password.html")]
+struct PasswordTemplate<'a> {
+    title: &'a str,
+    identity: &'a Option<User>,
+    username: &'a str,
+    password: &'a str,
+}
+
+/// Replaces the password of a user with a random one, shown once.
+pub async fn reset_password(
+    path: web::Path<String>,
+    state: web::Data<State>,
+    identity: Option<Identity>,
+) -> impl Responder {
+    let username = path.into_inner();
+    let (identity, user) = match load(&state, identity, &username).await {
+        Ok(loaded) => loaded,
+        Err(response) => return response,
+    };
+    if user.is_organization() {
+        return HttpResponse::BadRequest().body("organizations have no password");
+    }
+
+    let password = credentials::random_string(20);
+    let (hash, salt) = credentials::create_password(&password);
+    state.database.set_password(&user, &hash, &salt).await;
+    state
+        .database
+        .add_user_log(
+            &identity,
+            Event::AdminPasswordReset,
+            Some(user.username.clone()),
+        )
+        .await;
+
+    PasswordTemplate {
+        title: "password reset",
+        identity: &Some(identity),
+        username: &user.username,
+        password: &password,
+    }
+    .to_response()
+}
+
+#[derive(Debug, Deserialize)]
+pub struct DeleteForm {
+    /// The username, typed to confirm.
+    confirm: String,
+}
+
+/// Deletes a user with their repositories.
+pub async fn delete(
+    path: web::Path<String>,
+    state: web::Data<State>,
+    identity: Option<Identity>,
+    form: web::Form<DeleteForm>,
+) -> impl Responder {
+    let username = path.into_inner();
+    let (identity, user) = match load(&state, identity, &username).await {
+        Ok(loaded) => loaded,
+        Err(response) => return response,
+    };
+    if form.confirm.trim() != user.username {
+        return HttpResponse::BadRequest().body("type the username to confirm");
+    }
+
+    let deleted = state.database.delete_user(&user).await;
+    for (repository, redirects) in &deleted {
+        if !state.database.directory_in_use(&repository.name).await {
+            disk::remove_repository(&repository.name, redirects);
+        }
+    }
+    let description = format!("{}:{} repositories", user.username, deleted.len());
+    state
+        .database
+        .add_user_log(&identity, Event::AdminUserDelete, Some(description))
+        .await;
+    redirect_to_users()
+}
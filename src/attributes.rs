@@ -0,0 +1,248 @@
+use std::path::Path;
+
+/// Longest pattern that is considered; longer lines are ignored.
+const MAX_PATTERN_LENGTH: usize = 256;
+/// Most lines of a `.gitattributes` file that are considered.
+const MAX_RULES: usize = 1000;
+
+/// Attributes declared in the `.gitattributes` file at the root of a tree.
+///
+/// libgit2 only reads attributes from the working directory or the index, so
+/// for bare repositories the file is parsed from the tree being browsed.
+/// Nested `.gitattributes` files and macros are not supported.
+#[derive(Debug, Default)]
+pub struct Attributes {
+    rules: Vec<Rule>,
+}
+
+#[derive(Debug)]
+struct Rule {
+    pattern: Vec<Token>,
+    /// Whether the pattern is matched against the whole path rather than the
+    /// file name.
+    anchored: bool,
+    attributes: Vec<(String, String)>,
+}
+
+impl Attributes {
+    pub fn from_tree(repo: &git2::Repository, tree: &git2::Tree) -> Self {
+        let Ok(entry) = tree.get_path(Path::new(".gitattributes")) else {
+            return Self::default();
+        };
+        let Ok(blob) = repo.find_blob(entry.id()) else {
+            return Self::default();
+        };
+        Self::parse(&String::from_utf8_lossy(blob.content()))
+    }
+
+    pub fn parse(input: &str) -> Self {
+        let rules = input
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty() && !line.starts_with('#'))
+            .take(MAX_RULES)
+            .filter_map(|line| {
+                let mut fields = line.split_whitespace();
+                let pattern = fields.next()?.trim_start_matches('/');
+                if pattern.len() > MAX_PATTERN_LENGTH {
+                    return None;
+                }
+                let attributes = fields
+                    .map(|field| {
+                        if let Some((name, value)) = field.split_once('=') {
+                            (name.to_owned(), value.to_owned())
+                        } else if let Some(name) = field.strip_prefix('-') {
+                            (name.to_owned(), "false".to_owned())
+                        } else if let Some(name) = field.strip_prefix('!') {
+                            (name.to_owned(), String::new())
+                        } else {
+                            (field.to_owned(), "true".to_owned())
+                        }
+                    })
+                    .collect();
+                Some(Rule {
+                    pattern: tokenize(pattern.as_bytes()),
+                    anchored: pattern.contains('/'),
+                    attributes,
+                })
+            })
+            .collect();
+        Self { rules }
+    }
+
+    /// Value of attribute `name` for `path`; the last matching line wins and
+    /// `!name` unsets it again.
+    pub fn get(&self, path: &str, name: &str) -> Option<&str> {
+        let mut value = None;
+        for rule in &self.rules {
+            if !rule.matches(path) {
+                continue;
+            }
+            for (attribute, attribute_value) in &rule.attributes {
+                if attribute == name {
+                    value = Some(attribute_value.as_str()).filter(|inner| !inner.is_empty());
+                }
+            }
+        }
+        value
+    }
+
+    pub fn is_set(&self, path: &str, name: &str) -> bool {
+        matches!(self.get(path, name), Some(value) if value != "false")
+    }
+}
+
+impl Rule {
+    fn matches(&self, path: &str) -> bool {
+        if self.anchored {
+            glob_match(&self.pattern, path.as_bytes())
+        } else {
+            let file_name = path.rsplit('/').next().unwrap_or(path);
+            glob_match(&self.pattern, file_name.as_bytes())
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Token {
+    Byte(u8),
+    /// `?`, any byte but `/`.
+    Any,
+    /// `*`, any run of bytes without `/`.
+    Star,
+    /// `**`, any run of bytes.
+    DoubleStar,
+    /// `**/`, nothing or any run of bytes ending in `/`.
+    Directories,
+}
+
+fn tokenize(pattern: &[u8]) -> Vec<Token> {
+    let mut tokens = Vec::new();
+    let mut rest = pattern;
+    while let Some((c, tail)) = rest.split_first() {
+        rest = tail;
+        let token = match c {
+            b'*' if rest.starts_with(b"*/") => {
+                rest = &rest[2..];
+                Token::Directories
+            }
+            b'*' if rest.starts_with(b"*") => {
+                rest = &rest[1..];
+                Token::DoubleStar
+            }
+            b'*' => Token::Star,
+            b'?' => Token::Any,
+            c => Token::Byte(*c),
+        };
+        tokens.push(token);
+    }
+    tokens
+}
+
+/// Matches `*`, `?` and `**` the way gitignore-style patterns do: a single
+/// star stops at `/`, a double star crosses directories.
+///
+/// Every position the pattern could be at is tracked at once, so the time
+/// taken is bounded by the pattern length times the text length however many
+/// stars there are.
+fn glob_match(pattern: &[Token], text: &[u8]) -> bool {
+    let mut states = vec![false; pattern.len() + 1];
+    // Positions partway through a `**/`, which can only be left after a `/`.
+    let mut within = vec![false; pattern.len() + 1];
+    states[0] = true;
+    skip_stars(pattern, &mut states);
+    for c in text {
+        let mut next = vec![false; pattern.len() + 1];
+        let mut next_within = vec![false; pattern.len() + 1];
+        for (index, token) in pattern.iter().enumerate() {
+            if !states[index] && !within[index] {
+                continue;
+            }
+            match token {
+                Token::Byte(byte) if byte == c => next[index + 1] = true,
+                Token::Any if *c != b'/' => next[index + 1] = true,
+                Token::Star if *c != b'/' => next[index] = true,
+                Token::DoubleStar => next[index] = true,
+                Token::Directories => {
+                    next_within[index] = true;
+                    if *c == b'/' {
+                        next[index + 1] = true;
+                    }
+                }
+                _ => {}
+            }
+        }
+        skip_stars(pattern, &mut next);
+        if !next.contains(&true) && !next_within.contains(&true) {
+            return false;
+        }
+        states = next;
+        within = next_within;
+    }
+    states[pattern.len()]
+}
+
+/// Adds the positions reached by letting stars match nothing.
+fn skip_stars(pattern: &[Token], states: &mut [bool]) {
+    for (index, token) in pattern.iter().enumerate() {
+        let is_star = matches!(token, Token::Star | Token::DoubleStar | Token::Directories);
+        if states[index] && is_star {
+            states[index + 1] = true;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn matches(pattern: &str, path: &str) -> bool {
+        let attributes = Attributes::parse(&format!("{pattern} diff"));
+        attributes.is_set(path, "diff")
+    }
+
+    #[test]
+    fn file_names() {
+        assert!(matches("*.rs", "src/main.rs"));
+        assert!(matches("Cargo.lock", "Cargo.lock"));
+        assert!(matches("?.txt", "docs/a.txt"));
+        assert!(!matches("?.txt", "docs/ab.txt"));
+        assert!(!matches("*.rs", "src/main.rsx"));
+    }
+
+    #[test]
+    fn paths() {
+        assert!(matches("src/*.rs", "src/main.rs"));
+        assert!(!matches("src/*.rs", "src/bin/main.rs"));
+        assert!(matches("/src/*.rs", "src/main.rs"));
+        assert!(matches("src/**", "src/bin/main.rs"));
+        assert!(matches("**/generated/*", "generated/a.rs"));
+        assert!(matches("**/generated/*", "src/deep/generated/a.rs"));
+        assert!(!matches("**/generated/*", "src/regenerated/a.rs"));
+        assert!(matches("src/**/*.rs", "src/main.rs"));
+        assert!(matches("src/**/*.rs", "src/a/b/main.rs"));
+    }
+
+    #[test]
+    fn last_rule_wins() {
+        let attributes = Attributes::parse("*.rs diff\nmain.rs -diff\nlib.rs !diff");
+        assert!(attributes.is_set("src/a.rs", "diff"));
+        assert!(!attributes.is_set("src/main.rs", "diff"));
+        assert_eq!(attributes.get("src/main.rs", "diff"), Some("false"));
+        assert_eq!(attributes.get("src/lib.rs", "diff"), None);
+    }
+
+    #[test]
+    fn many_stars() {
+        let path = "a".repeat(60);
+        assert!(!matches("*a*a*a*a*a*a*a*a*b", &path));
+        assert!(!matches("**a**a**a**a**a**a**a**a**b", &path));
+        assert!(matches("*a*a*a*a*a*a*a*a*", &path));
+    }
+
+    #[test]
+    fn long_patterns_are_ignored() {
+        let pattern = "*".repeat(MAX_PATTERN_LENGTH + 1);
+        assert!(!matches(&pattern, "a"));
+    }
+}
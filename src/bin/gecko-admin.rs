@@ -0,0 +1,304 @@
+//! Administration from the command line, for bootstrapping a new site and for
+//! when the web UI isn't an option. Run it from the directory the server runs
+//! in, where the repositories are.
+
+use std::{collections::HashSet, process::ExitCode};
+
+use gecko::{
+    credentials,
+    database::{Database, Error},
+    disk, migrations,
+    model::{Event, User},
+    validation,
+};
+use mongodb::Client;
+
+const USAGE: &str = "\
+usage: gecko-admin [--uri <uri>] [--database <name>] <command>
+
+commands:
+    user create <username> <email> [--admin]
+                                  create a user with a random password
+    user password <username>      reset a password to a random one
+    user admin <username> [--revoke]
+                                  grant or revoke site administration
+    repo list [<query>]           list repositories
+    repo delete <owner> <name>    delete a repository and its directory
+    sync [--init] [--adopt <username>]
+                                  compare repositories on disk with the database;
+                                  --init creates missing directories and
+                                  --adopt gives directories no repository
+                                  claims to a user
+    migrate [--list]              apply pending schema migrations";
+
+/// Describes the actions taken from here in the log of the users they affect.
+const ACTOR: &str = "gecko-admin";
+
+#[actix_web::main]
+async fn main() -> ExitCode {
+    let mut uri = gecko::DEFAULT_DATABASE_URI.to_owned();
+    let mut name = gecko::DATABASE_NAME.to_owned();
+    let mut command = Vec::new();
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--uri" => uri = args.next().unwrap_or_default(),
+            "--database" => name = args.next().unwrap_or_default(),
+            "-h" | "--help" => {
+                println!("{USAGE}");
+                return ExitCode::SUCCESS;
+            }
+            _ => command.push(arg),
+        }
+    }
+
+    let client = match Client::with_uri_str(&uri).await {
+        Ok(client) => client,
+        Err(error) => return fail(&format!("can't connect to {uri}: {error}")),
+    };
+    let database = Database::new(&client, &name);
+
+    let command: Vec<&str> = command.iter().map(String::as_str).collect();
+    let result = match command.as_slice() {
+        ["user", "create", username, email, flags @ ..] => {
+            create_user(&database, username, email, flags.contains(&"--admin")).await
+        }
+        ["user", "password", username] => reset_password(&database, username).await,
+        ["user", "admin", username, flags @ ..] => {
+            grant_admin(&database, username, !flags.contains(&"--revoke")).await
+        }
+        ["repo", "list"] => list_repositories(&database, "").await,
+        ["repo", "list", query] => list_repositories(&database, query).await,
+        ["repo", "delete", owner, name] => delete_repository(&database, owner, name).await,
+        ["sync", flags @ ..] => sync(&database, flags).await,
+        ["migrate"] => migrate(&database, false).await,
+        ["migrate", "--list"] => migrate(&database, true).await,
+        _ => Err(USAGE.to_owned()),
+    };
+    match result {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(message) => fail(&message),
+    }
+}
+
+fn fail(message: &str) -> ExitCode {
+    eprintln!("{message}");
+    ExitCode::FAILURE
+}
+
+async fn find_user(database: &Database, username: &str) -> Result<User, String> {
+    database
+        .find_user(username)
+        .await
+        .ok_or_else(|| format!("no user named '{username}'"))
+}
+
+async fn create_user(
+    database: &Database,
+    username: &str,
+    email: &str,
+    admin: bool,
+) -> Result<(), String> {
+    validation::check_username(username)?;
+    validation::check_email(email)?;
+    if database.email_taken(email, None).await {
+        return Err(format!("there is already an account for {email}"));
+    }
+    let password = credentials::random_string(20);
+    let user = database
+        .new_user(email, username, &password, admin)
+        .await
+        .map_err(|_| format!("the name '{username}' is already taken"))?;
+    if admin {
+        let description = Some(format!("{}:{ACTOR}", user.username));
+        database
+            .add_user_log(&user, Event::AdminGrant, description)
+            .await;
+    }
+    println!("created @{username} with password {password}");
+    Ok(())
+}
+
+async fn reset_password(database: &Database, username: &str) -> Result<(), String> {
+    let user = find_user(database, username).await?;
+    if user.is_organization() {
+        return Err("organizations have no password".to_owned());
+    }
+    let password = credentials::random_string(20);
+    let (hash, salt) = credentials::create_password(&password);
+    database.set_password(&user, &hash, &salt).await;
+    let description = Some(format!("{}:{ACTOR}", user.username));
+    database
+        .add_user_log(&user, Event::AdminPasswordReset, description)
+        .await;
+    println!("the new password of @{username} is {password}");
+    Ok(())
+}
+
+async fn grant_admin(database: &Database, username: &str, admin: bool) -> Result<(), String> {
+    let user = find_user(database, username).await?;
+    if user.is_organization() {
+        return Err("organizations can't be administrators".to_owned());
+    }
+    database.set_admin(&user, admin).await;
+    let event = if admin {
+        Event::AdminGrant
+    } else {
+        Event::AdminRevoke
+    };
+    let description = Some(format!("{}:{ACTOR}", user.username));
+    database.add_user_log(&user, event, description).await;
+    Ok(())
+}
+
+async fn list_repositories(database: &Database, query: &str) -> Result<(), String> {
+    // MongoDB takes a limit of 0 as no limit.
+    let (repositories, _) = database.find_repositories(query, 0, 0).await;
+    for repository in repositories {
+        let owner = database
+            .find_user_from_id(&repository.user_id.to_string())
+            .await
+            .map(|owner| owner.username)
+            .unwrap_or_else(|| "?".to_owned());
+        let size = disk::usage(std::path::Path::new(&repository.name));
+        println!(
+            "{owner}/{}\t{}{}\t{}",
+            repository.name,
+            repository.visibility,
+            if repository.archived {
+                ", archived"
+            } else {
+                ""
+            },
+            humansize::format_size(size, humansize::DECIMAL.decimal_places(1)),
+        );
+    }
+    Ok(())
+}
+
+async fn delete_repository(database: &Database, owner: &str, name: &str) -> Result<(), String> {
+    let owner = find_user(database, owner).await?;
+    let Some(repository) = database.find_repository(Some(&owner), name).await else {
+        return Err(format!(
+            "@{} has no repository named '{name}'",
+            owner.username
+        ));
+    };
+    let redirects = database.find_redirects(&repository).await;
+    match database.delete_repository(&Some(owner.clone()), name).await {
+        Ok(()) => {}
+        Err(Error::NotFound) => return Err(format!("'{name}' was deleted already")),
+        Err(error) => return Err(format!("can't delete '{name}': {error:?}")),
+    }
+    if database.directory_in_use(name).await {
+        println!("kept the directory '{name}', another repository uses it");
+    } else {
+        disk::remove_repository(name, &redirects);
+    }
+    let description = Some(format!("{name}:{ACTOR}"));
+    database
+        .add_user_log(&owner, Event::RepositoryDelete, description)
+        .await;
+    println!("deleted {}/{name}", owner.username);
+    Ok(())
+}
+
+/// Reports repositories without a directory and directories without a
+/// repository, fixing them when asked to.
+async fn sync(database: &Database, flags: &[&str]) -> Result<(), String> {
+    let mut init = false;
+    let mut adopt = None;
+    let mut flags = flags.iter();
+    while let Some(flag) = flags.next() {
+        match *flag {
+            "--init" => init = true,
+            "--adopt" => match flags.next() {
+                Some(username) => adopt = Some(find_user(database, username).await?),
+                None => return Err(USAGE.to_owned()),
+            },
+            _ => return Err(USAGE.to_owned()),
+        }
+    }
+
+    let repositories = database.all_repositories().await;
+    let names: HashSet<&str> = repositories
+        .iter()
+        .map(|repository| repository.name.as_str())
+        .collect();
+    let directories = disk::repositories();
+
+    for repository in &repositories {
+        if directories.contains(&repository.name) {
+            continue;
+        }
+        if !init {
+            println!("missing directory: {}", repository.name);
+            continue;
+        }
+        let branch = if repository.default_branch.is_empty() {
+            disk::INITIAL_BRANCH
+        } else {
+            &repository.default_branch
+        };
+        match disk::init_repository(&repository.name, branch) {
+            Ok(()) => println!("created directory: {}", repository.name),
+            Err(error) => println!("can't create {}: {}", repository.name, error.message()),
+        }
+    }
+
+    for directory in &directories {
+        if names.contains(directory.as_str()) {
+            continue;
+        }
+        let Some(owner) = adopt.as_ref() else {
+            println!("no repository for directory: {directory}");
+            continue;
+        };
+        // Look again, in case a repository took the name since the listing.
+        if let Some(repository) = database.find_repository(None, directory).await {
+            let claimant = database
+                .find_user_from_id(&repository.user_id.to_string())
+                .await
+                .map(|claimant| claimant.username)
+                .unwrap_or_else(|| "?".to_owned());
+            println!("not adopting {directory}: it belongs to {claimant}/{directory}");
+            continue;
+        }
+        let result = database
+            .new_repository(Some(owner), directory, None, "private", "")
+            .await;
+        match result {
+            Ok(()) => {
+                let description = Some(format!("{directory}:{ACTOR}"));
+                database
+                    .add_user_log(owner, Event::RepositoryCreate, description)
+                    .await;
+                println!("adopted {directory} as {}/{directory}", owner.username);
+            }
+            Err(_) => println!("can't adopt {directory}"),
+        }
+    }
+    Ok(())
+}
+
+async fn migrate(database: &Database, list: bool) -> Result<(), String> {
+    let applied = migrations::applied(database)
+        .await
+        .map_err(|error| error.to_string())?;
+    for (name, description) in migrations::MIGRATIONS {
+        let done = applied.iter().any(|inner| inner == name);
+        if list {
+            let status = if done { "applied" } else { "pending" };
+            println!("{name}\t{status}\t{description}");
+            continue;
+        }
+        if done {
+            continue;
+        }
+        let changed = migrations::run(database, name)
+            .await
+            .map_err(|error| format!("{name} failed: {error}"))?;
+        println!("{name}: {changed} documents changed");
+    }
+    Ok(())
+}
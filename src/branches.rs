@@ -0,0 +1,436 @@
+use std::collections::HashSet;
+
+use actix_identity::Identity;
+use actix_web::{web, HttpResponse, Responder};
+use askama::Template;
+use askama_actix::TemplateToResponse;
+use git2::{BranchType, Oid, Signature};
+use time::OffsetDateTime;
+
+use crate::{
+    model::{Event, Role, User},
+    refs,
+    repository::Context,
+    time_utils, State,
+};
+
+/// Deleted branches are recorded in the reflog of this ref, which is never
+/// created itself: the entries say which branch pointed where and who deleted
+/// it.
+const DELETED_BRANCHES_LOG: &str = "refs/gecko/deleted-branches";
+/// Each deleted head is also kept under this prefix, named by its commit id,
+/// so `git gc` can't prune it while it can still be restored.
+const DELETED_REFS_PREFIX: &str = "refs/gecko/deleted/";
+const DELETED_MESSAGE_PREFIX: &str = "deleted branch ";
+/// Deletions older than this are no longer offered for restoring.
+const RESTORE_WINDOW_SECONDS: i64 = 30 * 24 * 60 * 60;
+const MAX_RESTORABLE_BRANCHES: usize = 20;
+
+struct Branch {
+    name: String,
+    /// Name escaped for use as a path segment.
+    rev: String,
+    commit_id: String,
+    summary: String,
+    /// Commit time of the head, for sorting.
+    seconds: i64,
+    relative_time: String,
+    datetime: String,
+    /// Commits on this branch but not on the default branch.
+    ahead: usize,
+    /// Commits on the default branch but not on this branch.
+    behind: usize,
+    is_default: bool,
+    protected: bool,
+    /// Whether the default branch contains every commit of this branch.
+    merged: bool,
+}
+
+struct DeletedBranch {
+    name: String,
+    commit_id: String,
+    deleted_by: String,
+    relative_time: String,
+    datetime: String,
+}
+
+#[derive(Template)]
+#[template(path = "repository/branches.html")]
+struct BranchesTemplate<'a> {
+    title: &'a str,
+    identity: &'a Option<User>,
+    username: &'a str,
+    name: &'a str,
+    default_branch: &'a str,
+    branches: &'a [Branch],
+    deleted: &'a [DeletedBranch],
+    /// Whether the signed in user may create, delete and restore branches.
+    can_write: bool,
+    /// Whether the signed in user may protect branches.
+    can_maintain: bool,
+}
+
+fn is_protected(context: &Context, branch: &str) -> bool {
+    context.default_branch().as_deref() == Some(branch)
+        || context
+            .repository
+            .protected_branches
+            .iter()
+            .any(|inner| inner == branch)
+}
+
+fn redirect(username: &str, name: &str) -> HttpResponse {
+    HttpResponse::SeeOther()
+        .insert_header(("Location", format!("/@{username}/{name}/branches")))
+        .finish()
+}
+
+pub async fn index(
+    path: web::Path<(String, String)>,
+    state: web::Data<State>,
+    identity: Option<Identity>,
+) -> impl Responder {
+    let (username, name) = path.into_inner();
+    let context = match Context::load(&state, &username, &name, identity).await {
+        Ok(context) => context,
+        Err(response) => return response,
+    };
+    let repo = &context.repo;
+
+    let default_branch = context.default_branch().unwrap_or_default();
+    let default_tip = repo
+        .find_branch(&default_branch, BranchType::Local)
+        .ok()
+        .and_then(|branch| branch.get().target());
+
+    let mut branches = Vec::new();
+    for (branch, _) in repo
+        .branches(Some(BranchType::Local))
+        .into_iter()
+        .flatten()
+        .flatten()
+    {
+        let Some(name) = branch.name().ok().flatten().map(str::to_owned) else {
+            continue;
+        };
+        let Ok(commit) = branch.get().peel_to_commit() else {
+            continue;
+        };
+        let (ahead, behind) = default_tip
+            .and_then(|default_tip| repo.graph_ahead_behind(commit.id(), default_tip).ok())
+            .unwrap_or_default();
+        let merged = default_tip.is_some_and(|default_tip| {
+            default_tip == commit.id()
+                || repo
+                    .graph_descendant_of(default_tip, commit.id())
+                    .unwrap_or(false)
+        });
+        let seconds = commit.time().seconds();
+        branches.push(Branch {
+            rev: name.replace('/', "%2F"),
+            commit_id: commit.id().to_string(),
+            summary: commit.summary().unwrap_or_default().to_owned(),
+            seconds,
+            relative_time: time_utils::to_relative_time(seconds),
+            datetime: time_utils::to_datetime(
+                OffsetDateTime::from_unix_timestamp(seconds).unwrap(),
+                Some(commit.time().offset_minutes()),
+            ),
+            ahead,
+            behind,
+            is_default: name == default_branch,
+            protected: is_protected(&context, &name),
+            merged,
+            name,
+        });
+    }
+    branches.sort_by(|a, b| {
+        b.is_default
+            .cmp(&a.is_default)
+            .then_with(|| b.seconds.cmp(&a.seconds))
+    });
+
+    let deleted = deleted_branches(repo);
+    let title = format!("@{username}/{name}/branches");
+
+    BranchesTemplate {
+        title: &title,
+        identity: &context.identity,
+        username: &username,
+        name: &name,
+        default_branch: &default_branch,
+        branches: &branches,
+        deleted: &deleted,
+        can_write: context.can(Role::Write) && !context.repository.archived,
+        can_maintain: context.can(Role::Maintain) && !context.repository.archived,
+    }
+    .to_response()
+}
+
+fn deleted_ref(commit: Oid) -> String {
+    format!("{DELETED_REFS_PREFIX}{commit}")
+}
+
+/// Recently deleted branches that don't exist again and whose head is still
+/// kept, newest first.
+fn deleted_branches(repo: &git2::Repository) -> Vec<DeletedBranch> {
+    let Ok(reflog) = repo.reflog(DELETED_BRANCHES_LOG) else {
+        return Vec::new();
+    };
+    let now = OffsetDateTime::now_utc().unix_timestamp();
+    let mut seen = HashSet::new();
+    let mut deleted = Vec::new();
+    for entry in reflog.iter() {
+        let Some(name) = entry
+            .message()
+            .and_then(|message| message.strip_prefix(DELETED_MESSAGE_PREFIX))
+        else {
+            continue;
+        };
+        let committer = entry.committer();
+        let seconds = committer.when().seconds();
+        if now - seconds > RESTORE_WINDOW_SECONDS {
+            break;
+        }
+        if !seen.insert(name.to_owned())
+            || repo.find_branch(name, BranchType::Local).is_ok()
+            || repo.find_reference(&deleted_ref(entry.id_new())).is_err()
+        {
+            continue;
+        }
+        deleted.push(DeletedBranch {
+            name: name.to_owned(),
+            commit_id: entry.id_new().to_string(),
+            deleted_by: committer.name().unwrap_or_default().to_owned(),
+            relative_time: time_utils::to_relative_time(seconds),
+            datetime: time_utils::to_datetime(
+                OffsetDateTime::from_unix_timestamp(seconds).unwrap(),
+                Some(committer.when().offset_minutes()),
+            ),
+        });
+        if deleted.len() == MAX_RESTORABLE_BRANCHES {
+            break;
+        }
+    }
+    deleted
+}
+
+#[derive(Debug, serde::Deserialize)]
+pub struct CreateForm {
+    branch: String,
+    /// Branch, tag or commit the new branch starts from.
+    from: String,
+}
+
+pub async fn create(
+    path: web::Path<(String, String)>,
+    state: web::Data<State>,
+    identity: Option<Identity>,
+    form: web::Form<CreateForm>,
+) -> impl Responder {
+    let (username, name) = path.into_inner();
+    let context = match Context::load(&state, &username, &name, identity).await {
+        Ok(context) => context,
+        Err(response) => return response,
+    };
+    let user = match context.writer() {
+        Ok(user) => user,
+        Err(response) => return response,
+    };
+
+    let branch = form.branch.trim();
+    if !git2::Reference::is_valid_name(&format!("refs/heads/{branch}")) {
+        return HttpResponse::BadRequest().body(format!("'{branch}' is not a valid branch name"));
+    }
+    if context.repo.find_branch(branch, BranchType::Local).is_ok() {
+        return HttpResponse::Conflict().body(format!("branch '{branch}' already exists"));
+    }
+    let commit = match refs::resolve(&context.repo, form.from.trim()) {
+        Ok(commit) => commit,
+        Err(error) => return HttpResponse::NotFound().body(error),
+    };
+    if let Err(error) = context.repo.branch(branch, &commit, false) {
+        return HttpResponse::InternalServerError().body(error.message().to_owned());
+    }
+
+    let description = format!("{name}:{branch}");
+    state
+        .database
+        .add_user_log(user, Event::BranchCreate, Some(description))
+        .await;
+    redirect(&username, &name)
+}
+
+#[derive(Debug, serde::Deserialize)]
+pub struct BranchForm {
+    branch: String,
+}
+
+/// Deletes a branch that is fully merged into the default branch, recording
+/// its head so it can be restored.
+pub async fn delete(
+    path: web::Path<(String, String)>,
+    state: web::Data<State>,
+    identity: Option<Identity>,
+    form: web::Form<BranchForm>,
+) -> impl Responder {
+    let (username, name) = path.into_inner();
+    let context = match Context::load(&state, &username, &name, identity).await {
+        Ok(context) => context,
+        Err(response) => return response,
+    };
+    let user = match context.writer() {
+        Ok(user) => user,
+        Err(response) => return response,
+    };
+    let repo = &context.repo;
+
+    let branch_name = form.branch.as_str();
+    let Ok(mut branch) = repo.find_branch(branch_name, BranchType::Local) else {
+        return HttpResponse::NotFound().body(format!("no branch named '{branch_name}'"));
+    };
+    if is_protected(&context, branch_name) {
+        return HttpResponse::Forbidden().body(format!("branch '{branch_name}' is protected"));
+    }
+    let Some(tip) = branch.get().target() else {
+        return HttpResponse::InternalServerError().finish();
+    };
+    let default_tip = context
+        .default_branch()
+        .and_then(|default_branch| repo.find_branch(&default_branch, BranchType::Local).ok())
+        .and_then(|default_branch| default_branch.get().target());
+    let merged = default_tip.is_some_and(|default_tip| {
+        default_tip == tip || repo.graph_descendant_of(default_tip, tip).unwrap_or(false)
+    });
+    if !merged {
+        return HttpResponse::Conflict().body(format!(
+            "branch '{branch_name}' is not merged into the default branch"
+        ));
+    }
+
+    if let Err(error) = record_deletion(repo, branch_name, tip, user) {
+        return HttpResponse::InternalServerError().body(error.message().to_owned());
+    }
+    if let Err(error) = branch.delete() {
+        return HttpResponse::InternalServerError().body(error.message().to_owned());
+    }
+
+    let description = format!("{name}:{branch_name}");
+    state
+        .database
+        .add_user_log(user, Event::BranchDelete, Some(description))
+        .await;
+    redirect(&username, &name)
+}
+
+fn record_deletion(
+    repo: &git2::Repository,
+    branch: &str,
+    tip: Oid,
+    user: &User,
+) -> Result<(), git2::Error> {
+    let signature = Signature::now(&user.username, &user.email)?;
+    let message = format!("{DELETED_MESSAGE_PREFIX}{branch}");
+    repo.reference(&deleted_ref(tip), tip, true, &message)?;
+    let mut reflog = repo.reflog(DELETED_BRANCHES_LOG)?;
+    reflog.append(tip, &signature, Some(&message))?;
+    reflog.write()
+}
+
+#[derive(Debug, serde::Deserialize)]
+pub struct RestoreForm {
+    branch: String,
+    commit: String,
+}
+
+/// Recreates a branch at the head recorded when it was deleted.
+pub async fn restore(
+    path: web::Path<(String, String)>,
+    state: web::Data<State>,
+    identity: Option<Identity>,
+    form: web::Form<RestoreForm>,
+) -> impl Responder {
+    let (username, name) = path.into_inner();
+    let context = match Context::load(&state, &username, &name, identity).await {
+        Ok(context) => context,
+        Err(response) => return response,
+    };
+    let user = match context.writer() {
+        Ok(user) => user,
+        Err(response) => return response,
+    };
+    let repo = &context.repo;
+
+    let Some(deleted) = deleted_branches(repo)
+        .into_iter()
+        .find(|deleted| deleted.name == form.branch && deleted.commit_id == form.commit)
+    else {
+        return HttpResponse::NotFound().body(format!(
+            "no recent deletion of '{}' at {} to restore",
+            form.branch, form.commit
+        ));
+    };
+    let commit = match Oid::from_str(&deleted.commit_id).and_then(|oid| repo.find_commit(oid)) {
+        Ok(commit) => commit,
+        Err(error) => return HttpResponse::InternalServerError().body(error.message().to_owned()),
+    };
+    if let Err(error) = repo.branch(&deleted.name, &commit, false) {
+        return HttpResponse::Conflict().body(error.message().to_owned());
+    }
+    // The branch keeps the commit now, unless another deleted branch still
+    // needs the ref.
+    let shared = deleted_branches(repo)
+        .iter()
+        .any(|other| other.commit_id == deleted.commit_id);
+    if !shared {
+        if let Ok(mut reference) = repo.find_reference(&deleted_ref(commit.id())) {
+            let removed = reference.delete();
+            debug_assert!(removed.is_ok());
+        }
+    }
+
+    let description = format!("{name}:{}", deleted.name);
+    state
+        .database
+        .add_user_log(user, Event::BranchRestore, Some(description))
+        .await;
+    redirect(&username, &name)
+}
+
+#[derive(Debug, serde::Deserialize)]
+pub struct ProtectForm {
+    branch: String,
+    protected: bool,
+}
+
+pub async fn protect(
+    path: web::Path<(String, String)>,
+    state: web::Data<State>,
+    identity: Option<Identity>,
+    form: web::Form<ProtectForm>,
+) -> impl Responder {
+    let (username, name) = path.into_inner();
+    let context = match Context::load(&state, &username, &name, identity).await {
+        Ok(context) => context,
+        Err(response) => return response,
+    };
+    if let Err(response) = context.writer() {
+        return response;
+    }
+    if let Err(response) = context.require(Role::Maintain) {
+        return response;
+    }
+    // Unprotecting is allowed for branches deleted outside the web UI.
+    let exists = context
+        .repo
+        .find_branch(&form.branch, BranchType::Local)
+        .is_ok();
+    if form.protected && !exists {
+        return HttpResponse::NotFound().body(format!("no branch named '{}'", form.branch));
+    }
+    state
+        .database
+        .set_branch_protection(&context.repository, &form.branch, form.protected)
+        .await;
+    redirect(&username, &name)
+}
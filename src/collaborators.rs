@@ -0,0 +1,324 @@
+use std::str::FromStr;
+
+use actix_identity::Identity;
+use actix_web::{web, HttpResponse, Responder};
+use askama::Template;
+use askama_actix::TemplateToResponse;
+use bson::oid::ObjectId;
+
+use crate::{
+    model::{self, Event, Role, User},
+    repository::Context,
+    State,
+};
+
+/// A collaborator as listed in the repository settings.
+pub struct Member {
+    pub username: String,
+    pub role: Role,
+    pub accepted: bool,
+}
+
+/// The collaborators of `repository`, skipping users that no longer exist.
+pub async fn members(state: &State, repository: &model::Repository) -> Vec<Member> {
+    let mut members = Vec::new();
+    for collaborator in &repository.collaborators {
+        let user = state
+            .database
+            .find_user_from_id(&collaborator.user_id.to_string())
+            .await;
+        if let Some(user) = user {
+            members.push(Member {
+                username: user.username,
+                role: collaborator.role,
+                accepted: collaborator.accepted,
+            });
+        }
+    }
+    members.sort_by(|a, b| a.username.cmp(&b.username));
+    members
+}
+
+#[derive(Debug, serde::Deserialize)]
+pub struct CollaboratorForm {
+    username: String,
+    #[serde(default)]
+    role: String,
+}
+
+/// Loads the repository for an admin and the user named in the form.
+async fn load(
+    state: &State,
+    username: &str,
+    name: &str,
+    identity: Option<Identity>,
+    form: &CollaboratorForm,
+) -> Result<(Context, User), HttpResponse> {
+    let context = Context::load(state, username, name, identity).await?;
+    context.require(Role::Admin)?;
+    let collaborator = form.username.trim().trim_start_matches('@');
+    let user = state
+        .database
+        .find_user(collaborator)
+        .await
+        .filter(|user| !user.is_organization());
+    let Some(user) = user else {
+        return Err(HttpResponse::NotFound().body(format!("no user named '{collaborator}'")));
+    };
+    Ok((context, user))
+}
+
+fn parse_role(role: &str) -> Result<Role, HttpResponse> {
+    Role::parse(role)
+        .ok_or_else(|| HttpResponse::BadRequest().body(format!("unknown role '{role}'")))
+}
+
+fn redirect(username: &str, name: &str) -> HttpResponse {
+    HttpResponse::SeeOther()
+        .insert_header(("Location", format!("/@{username}/{name}/settings")))
+        .finish()
+}
+
+/// Invites a user, who becomes a collaborator once they accept.
+pub async fn invite(
+    path: web::Path<(String, String)>,
+    state: web::Data<State>,
+    identity: Option<Identity>,
+    form: web::Form<CollaboratorForm>,
+) -> impl Responder {
+    let (username, name) = path.into_inner();
+    let (context, user) = match load(&state, &username, &name, identity, &form).await {
+        Ok(loaded) => loaded,
+        Err(response) => return response,
+    };
+    let role = match parse_role(&form.role) {
+        Ok(role) => role,
+        Err(response) => return response,
+    };
+    if user._id == context.repository.user_id {
+        return HttpResponse::BadRequest().body(format!("@{} owns this repository", user.username));
+    }
+    if state
+        .database
+        .invite_collaborator(&context.repository, &user, role)
+        .await
+        .is_err()
+    {
+        return HttpResponse::Conflict().body(format!(
+            "@{} is already a collaborator or invited",
+            user.username
+        ));
+    }
+
+    if let Some(identity) = context.identity.as_ref() {
+        let description = format!("{name}:{}:{}", user.username, role.as_str());
+        state
+            .database
+            .add_user_log(identity, Event::CollaboratorInvite, Some(description))
+            .await;
+    }
+    redirect(&username, &name)
+}
+
+/// Changes the role of a collaborator or of a pending invitation.
+pub async fn update(
+    path: web::Path<(String, String)>,
+    state: web::Data<State>,
+    identity: Option<Identity>,
+    form: web::Form<CollaboratorForm>,
+) -> impl Responder {
+    let (username, name) = path.into_inner();
+    let (context, user) = match load(&state, &username, &name, identity, &form).await {
+        Ok(loaded) => loaded,
+        Err(response) => return response,
+    };
+    let role = match parse_role(&form.role) {
+        Ok(role) => role,
+        Err(response) => return response,
+    };
+
+    state
+        .database
+        .set_collaborator_role(&context.repository, &user, role)
+        .await;
+    if let Some(identity) = context.identity.as_ref() {
+        let description = format!("{name}:{}:{}", user.username, role.as_str());
+        state
+            .database
+            .add_user_log(identity, Event::CollaboratorUpdate, Some(description))
+            .await;
+    }
+    redirect(&username, &name)
+}
+
+/// Removes a collaborator or withdraws an invitation.
+pub async fn remove(
+    path: web::Path<(String, String)>,
+    state: web::Data<State>,
+    identity: Option<Identity>,
+    form: web::Form<CollaboratorForm>,
+) -> impl Responder {
+    let (username, name) = path.into_inner();
+    let (context, user) = match load(&state, &username, &name, identity, &form).await {
+        Ok(loaded) => loaded,
+        Err(response) => return response,
+    };
+
+    state
+        .database
+        .remove_collaborator(&context.repository, &user)
+        .await;
+    if let Some(identity) = context.identity.as_ref() {
+        let description = format!("{name}:{}", user.username);
+        state
+            .database
+            .add_user_log(identity, Event::CollaboratorRemove, Some(description))
+            .await;
+    }
+    redirect(&username, &name)
+}
+
+pub struct Invitation {
+    pub id: String,
+    /// `owner/name` of the repository.
+    pub repository: String,
+    pub role: Role,
+}
+
+#[derive(Template)]
+#[template(path = "user/invitations.html")]
+struct InvitationsTemplate<'a> {
+    title: &'a str,
+    identity: &'a Option<User>,
+    invitations: &'a [Invitation],
+}
+
+async fn signed_in(state: &State, identity: Option<Identity>) -> Result<User, HttpResponse> {
+    let user = match identity.map(|identity| identity.id()) {
+        Some(Ok(id)) => state.database.find_user_from_id(&id).await,
+        _ => None,
+    };
+    user.ok_or_else(|| {
+        HttpResponse::SeeOther()
+            .insert_header(("Location", "/login"))
+            .finish()
+    })
+}
+
+/// Invitations waiting for the signed in user.
+pub async fn invitations(state: web::Data<State>, identity: Option<Identity>) -> impl Responder {
+    let user = match signed_in(&state, identity).await {
+        Ok(user) => user,
+        Err(response) => return response,
+    };
+
+    let mut invitations = Vec::new();
+    for repository in state.database.find_invitations(&user).await {
+        let Some(owner) = state
+            .database
+            .find_user_from_id(&repository.user_id.to_string())
+            .await
+        else {
+            continue;
+        };
+        let Some(collaborator) = repository
+            .collaborators
+            .iter()
+            .find(|inner| inner.user_id == user._id)
+        else {
+            continue;
+        };
+        invitations.push(Invitation {
+            id: repository._id.to_hex(),
+            repository: format!("{}/{}", owner.username, repository.name),
+            role: collaborator.role,
+        });
+    }
+
+    InvitationsTemplate {
+        title: "invitations",
+        identity: &Some(user),
+        invitations: &invitations,
+    }
+    .to_response()
+}
+
+#[derive(Debug, serde::Deserialize)]
+pub struct InvitationForm {
+    repository: String,
+}
+
+pub async fn accept(
+    state: web::Data<State>,
+    identity: Option<Identity>,
+    form: web::Form<InvitationForm>,
+) -> impl Responder {
+    let user = match signed_in(&state, identity).await {
+        Ok(user) => user,
+        Err(response) => return response,
+    };
+    let Ok(id) = ObjectId::from_str(&form.repository) else {
+        return HttpResponse::NotFound().finish();
+    };
+    if !state.database.accept_invitation(id, &user).await {
+        return HttpResponse::NotFound().body("there is no such invitation");
+    }
+    let Some(repository) = state.database.find_repository_from_id(id).await else {
+        return HttpResponse::NotFound().finish();
+    };
+    let owner = state
+        .database
+        .find_user_from_id(&repository.user_id.to_string())
+        .await
+        .map(|owner| owner.username)
+        .unwrap_or_default();
+
+    state
+        .database
+        .add_user_log(
+            &user,
+            Event::InvitationAccept,
+            Some(repository.name.clone()),
+        )
+        .await;
+    HttpResponse::SeeOther()
+        .insert_header(("Location", format!("/@{owner}/{}", repository.name)))
+        .finish()
+}
+
+pub async fn decline(
+    state: web::Data<State>,
+    identity: Option<Identity>,
+    form: web::Form<InvitationForm>,
+) -> impl Responder {
+    let user = match signed_in(&state, identity).await {
+        Ok(user) => user,
+        Err(response) => return response,
+    };
+    let repository = match ObjectId::from_str(&form.repository) {
+        Ok(id) => state.database.find_repository_from_id(id).await,
+        Err(_) => None,
+    };
+    let invited = repository.as_ref().is_some_and(|repository| {
+        repository
+            .collaborators
+            .iter()
+            .any(|inner| inner.user_id == user._id && !inner.accepted)
+    });
+    let Some(repository) = repository.filter(|_| invited) else {
+        return HttpResponse::NotFound().body("there is no such invitation");
+    };
+
+    state.database.remove_collaborator(&repository, &user).await;
+    state
+        .database
+        .add_user_log(
+            &user,
+            Event::InvitationDecline,
+            Some(repository.name.clone()),
+        )
+        .await;
+    HttpResponse::SeeOther()
+        .insert_header(("Location", "/invitations"))
+        .finish()
+}
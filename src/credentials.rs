@@ -0,0 +1,24 @@
+use rand::{distributions::Alphanumeric, thread_rng, Rng};
+
+pub fn create_password_using_salt(password: &str, salt: &str) -> String {
+    blake3::hash(format!("{}{}", password, salt).as_bytes()).to_string()
+}
+
+/// Hashes `password` with a new salt, returning both.
+pub fn create_password(password: &str) -> (String, String) {
+    let random_values = random_string(40);
+    let now = time::OffsetDateTime::now_utc();
+    let salt =
+        blake3::hash(format!("{random_values}{}", now.unix_timestamp()).as_bytes()).to_string();
+    let password = blake3::hash(format!("{password}{salt}").as_bytes()).to_string();
+    (password, salt)
+}
+
+/// A random alphanumeric string, used for salts and generated passwords.
+pub fn random_string(len: usize) -> String {
+    thread_rng()
+        .sample_iter(&Alphanumeric)
+        .take(len)
+        .map(char::from)
+        .collect()
+}
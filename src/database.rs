@@ -1,9 +1,21 @@
 use std::str::FromStr;
 
-use crate::model::{Event, Log, Repository, User};
+use crate::{
+    credentials,
+    model::{
+        AuditEntry, Collaborator, Event, Invite, Log, Membership, Organization, Redirect,
+        Registration, Repository, Role, SiteSettings, Team, TeamRepository, User,
+    },
+};
 use bson::oid::ObjectId;
 use futures::TryStreamExt;
-use mongodb::options::{FindOneOptions, FindOptions};
+use mongodb::{
+    options::{
+        Collation, CollationStrength, CountOptions, FindOneOptions, FindOptions, IndexOptions,
+        UpdateOptions,
+    },
+    IndexModel,
+};
 
 #[derive(Clone)]
 pub struct Database {
@@ -17,6 +29,72 @@ impl Database {
         }
     }
 
+    pub(crate) fn collection<T>(&self, name: &str) -> mongodb::Collection<T> {
+        self.inner.collection(name)
+    }
+
+    /// Makes usernames unique ignoring case, and so are the emails of users.
+    /// Organizations share the namespace of usernames but may have no email,
+    /// or the email of a member, so they are left out of the email index.
+    /// Fails if the existing documents have duplicates.
+    pub async fn create_indexes(&self) -> mongodb::error::Result<()> {
+        let users = self.inner.collection::<User>("users");
+        let username = IndexModel::builder()
+            .keys(bson::doc! { "username": 1 })
+            .options(
+                IndexOptions::builder()
+                    .name("username_unique".to_owned())
+                    .unique(true)
+                    .collation(case_insensitive())
+                    .build(),
+            )
+            .build();
+        let email = IndexModel::builder()
+            .keys(bson::doc! { "email": 1 })
+            .options(
+                IndexOptions::builder()
+                    .name("email_unique".to_owned())
+                    .unique(true)
+                    .collation(case_insensitive())
+                    .partial_filter_expression(
+                        bson::doc! { "email": { "$gt": "" }, "password": { "$gt": "" } },
+                    )
+                    .build(),
+            )
+            .build();
+        users.create_indexes([username, email], None).await?;
+        Ok(())
+    }
+
+    /// Whether someone other than `except` has `username`, ignoring case.
+    pub async fn username_taken(&self, username: &str, except: Option<&User>) -> bool {
+        let mut filter = bson::doc! { "username": username };
+        if let Some(user) = except {
+            filter.insert("_id", bson::doc! { "$ne": user._id });
+        }
+        self.count_users(filter).await > 0
+    }
+
+    /// Whether a user other than `except` has `email`, ignoring case.
+    pub async fn email_taken(&self, email: &str, except: Option<&User>) -> bool {
+        let mut filter = bson::doc! { "email": email, "password": { "$gt": "" } };
+        if let Some(user) = except {
+            filter.insert("_id", bson::doc! { "$ne": user._id });
+        }
+        self.count_users(filter).await > 0
+    }
+
+    async fn count_users(&self, filter: bson::Document) -> u64 {
+        let users = self.inner.collection::<User>("users");
+        let count_options = CountOptions::builder()
+            .collation(case_insensitive())
+            .build();
+        users
+            .count_documents(filter, count_options)
+            .await
+            .unwrap_or(0)
+    }
+
     pub async fn login(&self, username: &str, password: &str) -> Option<User> {
         let collection = self.inner.collection::<User>("users");
         let result = collection
@@ -52,7 +130,7 @@ impl Database {
 
         let collection = self.inner.collection::<Repository>("repositories");
         let find_options = FindOptions::builder()
-            .projection(bson::doc! { "user_id": ObjectId::default(), "name": 1, "description": 1, "visibility": 1, "created_at": 1, "updated_at": 1, "issues": 1 })
+            .projection(bson::doc! { "user_id": 1, "name": 1, "description": 1, "visibility": 1, "created_at": 1, "updated_at": 1, "issues": 1, "default_branch": 1, "protected_branches": 1, "website": 1, "topics": 1, "archived": 1, "collaborators": 1 })
             .build();
         let result = collection
             .find(bson::doc! { "user_id": user._id }, find_options)
@@ -70,7 +148,7 @@ impl Database {
         };
         let collection = self.inner.collection::<Repository>("repositories");
         let find_options = FindOneOptions::builder()
-            .projection(bson::doc! { "_id": 1, "user_id": 1, "name": 1, "description": 1, "visibility": 1, "created_at": 1, "updated_at": 1, "issues": 1 })
+            .projection(bson::doc! { "_id": 1, "user_id": 1, "name": 1, "description": 1, "visibility": 1, "created_at": 1, "updated_at": 1, "issues": 1, "default_branch": 1, "protected_branches": 1, "website": 1, "topics": 1, "archived": 1, "collaborators": 1 })
             .build();
         let result = collection.find_one(filter, find_options).await;
         result.unwrap_or(None)
@@ -82,6 +160,7 @@ impl Database {
         name: &str,
         description: Option<String>,
         visibility: &str,
+        default_branch: &str,
     ) -> anyhow::Result<(), Error> {
         let Some(user) = user else {
             panic!();
@@ -89,10 +168,9 @@ impl Database {
 
         let collection = self.inner.collection::<Repository>("repositories");
 
-        if let Ok(document) = collection
-            .find_one(bson::doc! { "user_id": user._id, "name": name }, None)
-            .await
-        {
+        // Users and organizations share the directories repositories are
+        // kept in, which are named after the repository alone.
+        if let Ok(document) = collection.find_one(bson::doc! { "name": name }, None).await {
             if document.is_some() {
                 return Err(Error::Found);
             }
@@ -109,11 +187,24 @@ impl Database {
             created_at: unix_timestamp,
             updated_at: unix_timestamp,
             issues: vec![],
+            default_branch: default_branch.to_owned(),
+            protected_branches: vec![],
+            website: String::new(),
+            topics: vec![],
+            archived: false,
+            collaborators: vec![],
         };
         if collection.insert_one(&repository, None).await.is_err() {
             todo!();
         }
 
+        // The name now belongs to the new repository.
+        let redirects = self.inner.collection::<Redirect>("redirects");
+        let result = redirects
+            .delete_many(bson::doc! { "user_id": user._id, "name": name }, None)
+            .await;
+        debug_assert!(result.is_ok());
+
         Ok(())
     }
 
@@ -130,12 +221,850 @@ impl Database {
             .find_one_and_delete(bson::doc! { "user_id": user._id, "name": name}, None)
             .await
             .unwrap();
-        if result.is_none() {
+        let Some(repository) = result else {
             return Err(Error::NotFound);
+        };
+        self.revoke_team_repositories(&repository).await;
+        let redirects = self.inner.collection::<Redirect>("redirects");
+        let result = redirects
+            .delete_many(bson::doc! { "repository_id": repository._id }, None)
+            .await;
+        debug_assert!(result.is_ok());
+        Ok(())
+    }
+
+    /// Whether a repository of any owner is named `name`, and so lives in the
+    /// directory of that name.
+    pub async fn directory_in_use(&self, name: &str) -> bool {
+        let collection = self.inner.collection::<Repository>("repositories");
+        let result = collection
+            .count_documents(bson::doc! { "name": name }, None)
+            .await;
+        result.map_or(true, |count| count > 0)
+    }
+
+    pub async fn update_repository(
+        &self,
+        repository: &Repository,
+        description: &str,
+        website: &str,
+        topics: &[String],
+        visibility: &str,
+    ) {
+        let collection = self.inner.collection::<Repository>("repositories");
+        let now = time::OffsetDateTime::now_utc();
+        let result = collection
+            .update_one(
+                bson::doc! { "_id": repository._id },
+                bson::doc! { "$set": {
+                    "description": description,
+                    "website": website,
+                    "topics": topics,
+                    "visibility": visibility,
+                    "updated_at": now.unix_timestamp(),
+                } },
+                None,
+            )
+            .await;
+        debug_assert!(result.is_ok());
+    }
+
+    /// Moves the repository to another owner and name, leaving a redirect from
+    /// the current ones.
+    pub async fn move_repository(
+        &self,
+        repository: &Repository,
+        owner: &User,
+        name: &str,
+    ) -> anyhow::Result<(), Error> {
+        let collection = self.inner.collection::<Repository>("repositories");
+        // Any owner's repository with the name has the directory of that name.
+        let existing = collection
+            .find_one(
+                bson::doc! { "name": name, "_id": { "$ne": repository._id } },
+                None,
+            )
+            .await
+            .unwrap();
+        if existing.is_some() {
+            return Err(Error::Found);
+        }
+
+        let now = time::OffsetDateTime::now_utc();
+        let result = collection
+            .update_one(
+                bson::doc! { "_id": repository._id },
+                bson::doc! { "$set": {
+                    "user_id": owner._id,
+                    "name": name,
+                    "updated_at": now.unix_timestamp(),
+                } },
+                None,
+            )
+            .await;
+        debug_assert!(result.is_ok());
+        if owner._id != repository.user_id {
+            // The new owner doesn't need to be a collaborator as well, and
+            // teams of the old owner no longer have access.
+            self.remove_collaborator(repository, owner).await;
+            self.revoke_team_repositories(repository).await;
         }
+
+        let redirects = self.inner.collection::<Redirect>("redirects");
+        let result = redirects
+            .delete_many(bson::doc! { "user_id": owner._id, "name": name }, None)
+            .await;
+        debug_assert!(result.is_ok());
+        let redirect = Redirect {
+            _id: ObjectId::new(),
+            user_id: repository.user_id,
+            name: repository.name.clone(),
+            repository_id: repository._id,
+        };
+        let result = redirects.insert_one(&redirect, None).await;
+        debug_assert!(result.is_ok());
         Ok(())
     }
 
+    /// The repository that used to be at `user`/`name`.
+    pub async fn find_redirect(&self, user: &User, name: &str) -> Option<Repository> {
+        let redirects = self.inner.collection::<Redirect>("redirects");
+        let redirect = redirects
+            .find_one(bson::doc! { "user_id": user._id, "name": name }, None)
+            .await
+            .ok()??;
+        let collection = self.inner.collection::<Repository>("repositories");
+        collection
+            .find_one(bson::doc! { "_id": redirect.repository_id }, None)
+            .await
+            .unwrap_or(None)
+    }
+
+    /// Old names of the repository that are still redirected.
+    pub async fn find_redirects(&self, repository: &Repository) -> Vec<Redirect> {
+        let redirects = self.inner.collection::<Redirect>("redirects");
+        let Ok(cursor) = redirects
+            .find(bson::doc! { "repository_id": repository._id }, None)
+            .await
+        else {
+            return vec![];
+        };
+        cursor.try_collect().await.unwrap_or_default()
+    }
+
+    /// Invites `user` with `role`, failing if they were invited already.
+    pub async fn invite_collaborator(
+        &self,
+        repository: &Repository,
+        user: &User,
+        role: Role,
+    ) -> anyhow::Result<(), Error> {
+        let collection = self.inner.collection::<Repository>("repositories");
+        let now = time::OffsetDateTime::now_utc();
+        let collaborator = Collaborator {
+            user_id: user._id,
+            role,
+            accepted: false,
+            invited_at: now.unix_timestamp(),
+        };
+        let result = collection
+            .update_one(
+                bson::doc! { "_id": repository._id, "collaborators.user_id": { "$ne": user._id } },
+                bson::doc! { "$push": { "collaborators": bson::to_bson(&collaborator).unwrap() } },
+                None,
+            )
+            .await
+            .unwrap();
+        if result.matched_count == 0 {
+            return Err(Error::Found);
+        }
+        Ok(())
+    }
+
+    pub async fn set_collaborator_role(&self, repository: &Repository, user: &User, role: Role) {
+        let collection = self.inner.collection::<Repository>("repositories");
+        let result = collection
+            .update_one(
+                bson::doc! { "_id": repository._id, "collaborators.user_id": user._id },
+                bson::doc! { "$set": { "collaborators.$.role": role.as_str() } },
+                None,
+            )
+            .await;
+        debug_assert!(result.is_ok());
+    }
+
+    /// Removes a collaborator or withdraws their invitation.
+    pub async fn remove_collaborator(&self, repository: &Repository, user: &User) {
+        let collection = self.inner.collection::<Repository>("repositories");
+        let result = collection
+            .update_one(
+                bson::doc! { "_id": repository._id },
+                bson::doc! { "$pull": { "collaborators": { "user_id": user._id } } },
+                None,
+            )
+            .await;
+        debug_assert!(result.is_ok());
+    }
+
+    /// Repositories `user` has been invited to and hasn't answered yet.
+    pub async fn find_invitations(&self, user: &User) -> Vec<Repository> {
+        let collection = self.inner.collection::<Repository>("repositories");
+        let filter = bson::doc! {
+            "collaborators": { "$elemMatch": { "user_id": user._id, "accepted": false } }
+        };
+        let Ok(cursor) = collection.find(filter, None).await else {
+            return vec![];
+        };
+        cursor.try_collect().await.unwrap_or_default()
+    }
+
+    /// Accepts a pending invitation, returning whether there was one.
+    pub async fn accept_invitation(&self, repository_id: ObjectId, user: &User) -> bool {
+        let collection = self.inner.collection::<Repository>("repositories");
+        let filter = bson::doc! {
+            "_id": repository_id,
+            "collaborators": { "$elemMatch": { "user_id": user._id, "accepted": false } }
+        };
+        let result = collection
+            .update_one(
+                filter,
+                bson::doc! { "$set": { "collaborators.$.accepted": true } },
+                None,
+            )
+            .await;
+        result.is_ok_and(|inner| inner.modified_count == 1)
+    }
+
+    pub async fn find_repository_from_id(&self, id: ObjectId) -> Option<Repository> {
+        let collection = self.inner.collection::<Repository>("repositories");
+        let result = collection.find_one(bson::doc! { "_id": id }, None).await;
+        result.unwrap_or(None)
+    }
+
+    /// Creates an organization owned by `user`, failing if the name is taken.
+    pub async fn new_organization(
+        &self,
+        user: &User,
+        name: &str,
+        email: &str,
+        description: &str,
+    ) -> anyhow::Result<User, Error> {
+        if self.username_taken(name, None).await {
+            return Err(Error::Found);
+        }
+        let now = time::OffsetDateTime::now_utc();
+        let organization = User {
+            _id: ObjectId::new(),
+            email: email.to_owned(),
+            username: name.to_owned(),
+            created_at: now.unix_timestamp(),
+            updated_at: now.unix_timestamp(),
+            organization: Some(Organization {
+                description: description.to_owned(),
+                members: vec![Membership {
+                    user_id: user._id,
+                    owner: true,
+                    created_at: now.unix_timestamp(),
+                }],
+                teams: vec![],
+            }),
+            ..Default::default()
+        };
+        let users = self.inner.collection::<User>("users");
+        if users.insert_one(&organization, None).await.is_err() {
+            return Err(Error::Found);
+        }
+        Ok(organization)
+    }
+
+    /// Organizations `user` is a member of.
+    pub async fn find_organizations(&self, user: &User) -> Vec<User> {
+        let users = self.inner.collection::<User>("users");
+        let filter = bson::doc! { "organization.members.user_id": user._id };
+        let Ok(cursor) = users.find(filter, None).await else {
+            return vec![];
+        };
+        cursor.try_collect().await.unwrap_or_default()
+    }
+
+    pub async fn update_organization(&self, organization: &User, email: &str, description: &str) {
+        let users = self.inner.collection::<User>("users");
+        let now = time::OffsetDateTime::now_utc();
+        let result = users
+            .update_one(
+                bson::doc! { "_id": organization._id },
+                bson::doc! { "$set": {
+                    "email": email,
+                    "organization.description": description,
+                    "updated_at": now.unix_timestamp(),
+                } },
+                None,
+            )
+            .await;
+        debug_assert!(result.is_ok());
+    }
+
+    /// Adds `user` to the organization, failing if they are a member already.
+    pub async fn add_member(
+        &self,
+        organization: &User,
+        user: &User,
+        owner: bool,
+    ) -> anyhow::Result<(), Error> {
+        let users = self.inner.collection::<User>("users");
+        let now = time::OffsetDateTime::now_utc();
+        let membership = Membership {
+            user_id: user._id,
+            owner,
+            created_at: now.unix_timestamp(),
+        };
+        let result = users
+            .update_one(
+                bson::doc! {
+                    "_id": organization._id,
+                    "organization.members.user_id": { "$ne": user._id },
+                },
+                bson::doc! { "$push": {
+                    "organization.members": bson::to_bson(&membership).unwrap(),
+                } },
+                None,
+            )
+            .await
+            .unwrap();
+        if result.matched_count == 0 {
+            return Err(Error::Found);
+        }
+        Ok(())
+    }
+
+    pub async fn set_member_owner(&self, organization: &User, user: &User, owner: bool) {
+        let users = self.inner.collection::<User>("users");
+        let result = users
+            .update_one(
+                bson::doc! { "_id": organization._id, "organization.members.user_id": user._id },
+                bson::doc! { "$set": { "organization.members.$.owner": owner } },
+                None,
+            )
+            .await;
+        debug_assert!(result.is_ok());
+    }
+
+    /// Removes `user` from the organization and from all of its teams.
+    pub async fn remove_member(&self, organization: &User, user: &User) {
+        let users = self.inner.collection::<User>("users");
+        let result = users
+            .update_one(
+                bson::doc! { "_id": organization._id },
+                bson::doc! { "$pull": {
+                    "organization.members": { "user_id": user._id },
+                    "organization.teams.$[].members": user._id,
+                } },
+                None,
+            )
+            .await;
+        debug_assert!(result.is_ok());
+    }
+
+    /// Adds a team without members or repositories, failing if the name is
+    /// taken.
+    pub async fn new_team(
+        &self,
+        organization: &User,
+        name: &str,
+        description: &str,
+    ) -> anyhow::Result<(), Error> {
+        let users = self.inner.collection::<User>("users");
+        let team = Team {
+            name: name.to_owned(),
+            description: description.to_owned(),
+            members: vec![],
+            repositories: vec![],
+        };
+        let result = users
+            .update_one(
+                bson::doc! { "_id": organization._id, "organization.teams.name": { "$ne": name } },
+                bson::doc! { "$push": { "organization.teams": bson::to_bson(&team).unwrap() } },
+                None,
+            )
+            .await
+            .unwrap();
+        if result.matched_count == 0 {
+            return Err(Error::Found);
+        }
+        Ok(())
+    }
+
+    pub async fn delete_team(&self, organization: &User, name: &str) {
+        let users = self.inner.collection::<User>("users");
+        let result = users
+            .update_one(
+                bson::doc! { "_id": organization._id },
+                bson::doc! { "$pull": { "organization.teams": { "name": name } } },
+                None,
+            )
+            .await;
+        debug_assert!(result.is_ok());
+    }
+
+    pub async fn add_team_member(&self, organization: &User, team: &str, user: &User) {
+        let users = self.inner.collection::<User>("users");
+        let result = users
+            .update_one(
+                bson::doc! { "_id": organization._id, "organization.teams.name": team },
+                bson::doc! { "$addToSet": { "organization.teams.$.members": user._id } },
+                None,
+            )
+            .await;
+        debug_assert!(result.is_ok());
+    }
+
+    pub async fn remove_team_member(&self, organization: &User, team: &str, user: &User) {
+        let users = self.inner.collection::<User>("users");
+        let result = users
+            .update_one(
+                bson::doc! { "_id": organization._id, "organization.teams.name": team },
+                bson::doc! { "$pull": { "organization.teams.$.members": user._id } },
+                None,
+            )
+            .await;
+        debug_assert!(result.is_ok());
+    }
+
+    /// Grants the team `role` in `repository`, replacing the role it had.
+    pub async fn grant_team_repository(
+        &self,
+        organization: &User,
+        team: &str,
+        repository: &Repository,
+        role: Role,
+    ) {
+        self.revoke_team_repository(organization, team, repository)
+            .await;
+        let users = self.inner.collection::<User>("users");
+        let grant = TeamRepository {
+            repository_id: repository._id,
+            role,
+        };
+        let result = users
+            .update_one(
+                bson::doc! { "_id": organization._id, "organization.teams.name": team },
+                bson::doc! { "$push": {
+                    "organization.teams.$.repositories": bson::to_bson(&grant).unwrap(),
+                } },
+                None,
+            )
+            .await;
+        debug_assert!(result.is_ok());
+    }
+
+    pub async fn revoke_team_repository(
+        &self,
+        organization: &User,
+        team: &str,
+        repository: &Repository,
+    ) {
+        let users = self.inner.collection::<User>("users");
+        let result = users
+            .update_one(
+                bson::doc! { "_id": organization._id, "organization.teams.name": team },
+                bson::doc! { "$pull": {
+                    "organization.teams.$.repositories": { "repository_id": repository._id },
+                } },
+                None,
+            )
+            .await;
+        debug_assert!(result.is_ok());
+    }
+
+    /// Takes `repository` away from all teams of its owner.
+    async fn revoke_team_repositories(&self, repository: &Repository) {
+        let users = self.inner.collection::<User>("users");
+        let result = users
+            .update_one(
+                bson::doc! { "_id": repository.user_id, "organization.teams": { "$exists": true } },
+                bson::doc! { "$pull": {
+                    "organization.teams.$[].repositories": { "repository_id": repository._id },
+                } },
+                None,
+            )
+            .await;
+        debug_assert!(result.is_ok());
+    }
+
+    pub async fn set_archived(&self, repository: &Repository, archived: bool) {
+        let collection = self.inner.collection::<Repository>("repositories");
+        let result = collection
+            .update_one(
+                bson::doc! { "_id": repository._id },
+                bson::doc! { "$set": { "archived": archived } },
+                None,
+            )
+            .await;
+        debug_assert!(result.is_ok());
+    }
+
+    pub async fn set_default_branch(&self, repository: &Repository, branch: &str) {
+        let collection = self.inner.collection::<Repository>("repositories");
+        let result = collection
+            .update_one(
+                bson::doc! { "_id": repository._id },
+                bson::doc! { "$set": { "default_branch": branch } },
+                None,
+            )
+            .await;
+        debug_assert!(result.is_ok());
+    }
+
+    pub async fn set_branch_protection(
+        &self,
+        repository: &Repository,
+        branch: &str,
+        protected: bool,
+    ) {
+        let collection = self.inner.collection::<Repository>("repositories");
+        let update = if protected {
+            bson::doc! { "$addToSet": { "protected_branches": branch } }
+        } else {
+            bson::doc! { "$pull": { "protected_branches": branch } }
+        };
+        let result = collection
+            .update_one(bson::doc! { "_id": repository._id }, update, None)
+            .await;
+        debug_assert!(result.is_ok());
+    }
+
+    /// Creates a user with `password`, failing if the username is taken.
+    pub async fn new_user(
+        &self,
+        email: &str,
+        username: &str,
+        password: &str,
+        admin: bool,
+    ) -> anyhow::Result<User, Error> {
+        if self.username_taken(username, None).await {
+            return Err(Error::Found);
+        }
+        let (password, salt) = credentials::create_password(password);
+        let now = time::OffsetDateTime::now_utc();
+        let user = User {
+            _id: ObjectId::new(),
+            email: email.to_owned(),
+            username: username.to_owned(),
+            password,
+            salt,
+            created_at: now.unix_timestamp(),
+            updated_at: now.unix_timestamp(),
+            admin,
+            ..Default::default()
+        };
+        let users = self.inner.collection::<User>("users");
+        if users.insert_one(&user, None).await.is_err() {
+            return Err(Error::Found);
+        }
+        Ok(user)
+    }
+
+    /// Users whose username or email contains `query`, sorted by username.
+    pub async fn find_users(&self, query: &str, skip: u64, limit: i64) -> (Vec<User>, u64) {
+        let users = self.inner.collection::<User>("users");
+        let filter = search(&["username", "email"], query);
+        let total = users
+            .count_documents(filter.clone(), None)
+            .await
+            .unwrap_or(0);
+        let find_options = FindOptions::builder()
+            .sort(bson::doc! { "username": 1 })
+            .skip(skip)
+            .limit(limit)
+            .build();
+        let Ok(cursor) = users.find(filter, find_options).await else {
+            return (vec![], total);
+        };
+        (cursor.try_collect().await.unwrap_or_default(), total)
+    }
+
+    pub async fn all_repositories(&self) -> Vec<Repository> {
+        let collection = self.inner.collection::<Repository>("repositories");
+        let Ok(cursor) = collection.find(None, None).await else {
+            return vec![];
+        };
+        cursor.try_collect().await.unwrap_or_default()
+    }
+
+    /// Repositories whose name or description contains `query`, sorted by
+    /// name.
+    pub async fn find_repositories(
+        &self,
+        query: &str,
+        skip: u64,
+        limit: i64,
+    ) -> (Vec<Repository>, u64) {
+        let collection = self.inner.collection::<Repository>("repositories");
+        let filter = search(&["name", "description"], query);
+        let total = collection
+            .count_documents(filter.clone(), None)
+            .await
+            .unwrap_or(0);
+        let find_options = FindOptions::builder()
+            .sort(bson::doc! { "name": 1 })
+            .skip(skip)
+            .limit(limit)
+            .build();
+        let Ok(cursor) = collection.find(filter, find_options).await else {
+            return (vec![], total);
+        };
+        (cursor.try_collect().await.unwrap_or_default(), total)
+    }
+
+    pub async fn set_suspended(&self, user: &User, suspended: bool) {
+        let users = self.inner.collection::<User>("users");
+        let result = users
+            .update_one(
+                bson::doc! { "_id": user._id },
+                bson::doc! { "$set": { "suspended": suspended } },
+                None,
+            )
+            .await;
+        debug_assert!(result.is_ok());
+    }
+
+    pub async fn set_admin(&self, user: &User, admin: bool) {
+        let users = self.inner.collection::<User>("users");
+        let result = users
+            .update_one(
+                bson::doc! { "_id": user._id },
+                bson::doc! { "$set": { "admin": admin } },
+                None,
+            )
+            .await;
+        debug_assert!(result.is_ok());
+    }
+
+    /// Stores an already hashed password.
+    pub async fn set_password(&self, user: &User, password: &str, salt: &str) {
+        let users = self.inner.collection::<User>("users");
+        let now = time::OffsetDateTime::now_utc();
+        let result = users
+            .update_one(
+                bson::doc! { "_id": user._id },
+                bson::doc! { "$set": {
+                    "password": password,
+                    "salt": salt,
+                    "updated_at": now.unix_timestamp(),
+                } },
+                None,
+            )
+            .await;
+        debug_assert!(result.is_ok());
+    }
+
+    /// Deletes the user along with their repositories, collaborations and
+    /// memberships. Returns the deleted repositories and their redirects, whose
+    /// directories are left to the caller.
+    pub async fn delete_user(&self, user: &User) -> Vec<(Repository, Vec<Redirect>)> {
+        let collection = self.inner.collection::<Repository>("repositories");
+        let repositories = match collection
+            .find(bson::doc! { "user_id": user._id }, None)
+            .await
+        {
+            Ok(cursor) => cursor.try_collect::<Vec<_>>().await.unwrap_or_default(),
+            Err(_) => vec![],
+        };
+        let mut deleted = Vec::new();
+        for repository in repositories {
+            let redirects = self.find_redirects(&repository).await;
+            deleted.push((repository, redirects));
+        }
+        let result = collection
+            .delete_many(bson::doc! { "user_id": user._id }, None)
+            .await;
+        debug_assert!(result.is_ok());
+        let result = collection
+            .update_many(
+                bson::doc! { "collaborators.user_id": user._id },
+                bson::doc! { "$pull": { "collaborators": { "user_id": user._id } } },
+                None,
+            )
+            .await;
+        debug_assert!(result.is_ok());
+
+        let redirects = self.inner.collection::<Redirect>("redirects");
+        let ids: Vec<_> = deleted.iter().map(|(inner, _)| inner._id).collect();
+        let result = redirects
+            .delete_many(
+                bson::doc! { "$or": [{ "user_id": user._id }, { "repository_id": { "$in": ids } }] },
+                None,
+            )
+            .await;
+        debug_assert!(result.is_ok());
+
+        let users = self.inner.collection::<User>("users");
+        let result = users
+            .update_many(
+                bson::doc! { "organization.members.user_id": user._id },
+                bson::doc! { "$pull": {
+                    "organization.members": { "user_id": user._id },
+                    "organization.teams.$[].members": user._id,
+                } },
+                None,
+            )
+            .await;
+        debug_assert!(result.is_ok());
+        let result = users.delete_one(bson::doc! { "_id": user._id }, None).await;
+        debug_assert!(result.is_ok());
+        deleted
+    }
+
+    pub async fn site_settings(&self) -> SiteSettings {
+        let settings = self.inner.collection::<SiteSettings>("settings");
+        let result = settings.find_one(bson::doc! { "_id": "site" }, None).await;
+        result.ok().flatten().unwrap_or_default()
+    }
+
+    pub async fn set_registration(&self, registration: Registration) {
+        let settings = self.inner.collection::<SiteSettings>("settings");
+        let update_options = UpdateOptions::builder().upsert(true).build();
+        let result = settings
+            .update_one(
+                bson::doc! { "_id": "site" },
+                bson::doc! { "$set": { "registration": registration.as_str() } },
+                update_options,
+            )
+            .await;
+        debug_assert!(result.is_ok());
+    }
+
+    pub async fn new_invite(&self, user: &User) -> Invite {
+        let invite = Invite {
+            _id: credentials::random_string(16),
+            created_by: user.username.clone(),
+            created_at: time::OffsetDateTime::now_utc().unix_timestamp(),
+            used_by: None,
+            used_at: None,
+        };
+        let invites = self.inner.collection::<Invite>("invites");
+        let result = invites.insert_one(&invite, None).await;
+        debug_assert!(result.is_ok());
+        invite
+    }
+
+    /// All invites, newest first.
+    pub async fn find_invites(&self) -> Vec<Invite> {
+        let invites = self.inner.collection::<Invite>("invites");
+        let find_options = FindOptions::builder()
+            .sort(bson::doc! { "created_at": -1 })
+            .build();
+        let Ok(cursor) = invites.find(None, find_options).await else {
+            return vec![];
+        };
+        cursor.try_collect().await.unwrap_or_default()
+    }
+
+    /// Deletes an invite nobody used yet.
+    pub async fn delete_invite(&self, code: &str) -> bool {
+        let invites = self.inner.collection::<Invite>("invites");
+        let result = invites
+            .delete_one(bson::doc! { "_id": code, "used_by": null }, None)
+            .await;
+        result.is_ok_and(|result| result.deleted_count > 0)
+    }
+
+    /// Marks an unused invite as used by `username`, failing if there is no
+    /// such invite or someone used it first.
+    pub async fn claim_invite(&self, code: &str, username: &str) -> bool {
+        let invites = self.inner.collection::<Invite>("invites");
+        let now = time::OffsetDateTime::now_utc().unix_timestamp();
+        let result = invites
+            .update_one(
+                bson::doc! { "_id": code, "used_by": null },
+                bson::doc! { "$set": { "used_by": username, "used_at": now } },
+                None,
+            )
+            .await;
+        result.is_ok_and(|result| result.modified_count > 0)
+    }
+
+    /// Makes a claimed invite usable again, for when the sign up failed after
+    /// all.
+    pub async fn release_invite(&self, code: &str) {
+        let invites = self.inner.collection::<Invite>("invites");
+        let result = invites
+            .update_one(
+                bson::doc! { "_id": code },
+                bson::doc! { "$set": { "used_by": null, "used_at": null } },
+                None,
+            )
+            .await;
+        debug_assert!(result.is_ok());
+    }
+
+    /// Log entries of all users, newest first, optionally only those of
+    /// `username` or with events starting with `event`.
+    pub async fn find_audit_log(
+        &self,
+        username: &str,
+        event: &str,
+        skip: u64,
+        limit: i64,
+    ) -> (Vec<AuditEntry>, u64) {
+        let users = self.inner.collection::<User>("users");
+        let mut filter = bson::Document::new();
+        if !username.is_empty() {
+            filter.insert("username", username);
+        }
+        let mut log_filter = bson::Document::new();
+        if !event.is_empty() {
+            log_filter.insert(
+                "log.event",
+                bson::doc! { "$regex": format!("^{}", regex::escape(event)) },
+            );
+        }
+        let entries = [
+            bson::doc! { "$match": filter },
+            bson::doc! { "$project": { "username": 1, "log": 1 } },
+            bson::doc! { "$unwind": "$log" },
+            bson::doc! { "$match": log_filter },
+        ];
+
+        let mut count = entries.to_vec();
+        count.push(bson::doc! { "$count": "total" });
+        let total = match users.aggregate(count, None).await {
+            Ok(mut cursor) => match cursor.try_next().await {
+                Ok(Some(document)) => document.get_i32("total").unwrap_or(0) as u64,
+                _ => 0,
+            },
+            Err(_) => 0,
+        };
+
+        let mut page = entries.to_vec();
+        page.push(bson::doc! { "$sort": { "log.created_at": -1 } });
+        page.push(bson::doc! { "$skip": skip as i64 });
+        page.push(bson::doc! { "$limit": limit });
+        let Ok(cursor) = users.aggregate(page, None).await else {
+            return (vec![], total);
+        };
+        let entries = cursor
+            .try_collect::<Vec<_>>()
+            .await
+            .unwrap_or_default()
+            .into_iter()
+            .filter_map(|document| bson::from_document(document).ok())
+            .collect();
+        (entries, total)
+    }
+
+    pub async fn set_split_diff(&self, user: &User, split_diff: bool) {
+        let users = self.inner.collection::<User>("users");
+        let result = users
+            .update_one(
+                bson::doc! { "_id": user._id },
+                bson::doc! { "$set": { "split_diff": split_diff } },
+                None,
+            )
+            .await;
+        debug_assert!(result.is_ok());
+    }
+
     pub async fn add_user_log(&self, user: &User, event: Event, description: Option<String>) {
         let now = time::OffsetDateTime::now_utc();
         let unix_timestamp = now.unix_timestamp();
@@ -164,6 +1093,27 @@ impl Database {
     }
 }
 
+/// Compares strings ignoring case, as the unique indexes on users do.
+fn case_insensitive() -> Collation {
+    Collation::builder()
+        .locale("en".to_owned())
+        .strength(CollationStrength::Secondary)
+        .build()
+}
+
+/// Matches documents where any of `fields` contains `query`, ignoring case.
+fn search(fields: &[&str], query: &str) -> bson::Document {
+    if query.is_empty() {
+        return bson::Document::new();
+    }
+    let pattern = regex::escape(query);
+    let conditions: Vec<_> = fields
+        .iter()
+        .map(|field| bson::doc! { *field: { "$regex": &pattern, "$options": "i" } })
+        .collect();
+    bson::doc! { "$or": conditions }
+}
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum Error {
     Unauthorized,
@@ -1,7 +1,22 @@
-use std::collections::HashMap;
-
-use git2::{DiffFormat, DiffLineType, DiffOptions, Oid};
+use git2::{
+    Delta, DiffFindOptions, DiffFormat, DiffLineType, DiffOptions, Email, EmailCreateOptions,
+    FileMode, Patch,
+};
+use serde::{Deserialize, Serialize};
 use sha2::{Digest, Sha256};
+use std::collections::{HashMap, HashSet};
+
+use crate::{attributes::Attributes, highlight};
+
+pub const DEFAULT_SIMILARITY: u16 = 50;
+pub const MAX_CONTEXT_LINES: u32 = 100;
+pub const MAX_FILES: usize = 300;
+pub const MAX_FILE_LINES: usize = 2_000;
+pub const MAX_TOTAL_BYTES: usize = 1_000_000;
+
+/// Above this many token comparisons a line pair is left without word-level
+/// highlighting.
+const MAX_WORD_DIFF_COST: usize = 250_000;
 
 #[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
 pub struct Line {
@@ -9,6 +24,11 @@ pub struct Line {
     pub new_lineno: i32,
     pub content: String,
     pub origin: u8,
+    /// Syntax-highlighted `content`, when the file's language is known.
+    pub html: Option<String>,
+    /// Word-level split of `content` for paired deletions and additions;
+    /// empty when the line has no counterpart.
+    pub segments: Vec<Segment>,
 }
 
 impl Line {
@@ -18,32 +38,120 @@ impl Line {
             new_lineno,
             content,
             origin,
+            html: None,
+            segments: Vec::new(),
         }
     }
 }
 
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Segment {
+    pub text: String,
+    pub changed: bool,
+}
+
+/// A row of the side-by-side view. Hunk headers occupy `left` alone.
+pub struct SplitRow<'a> {
+    pub left: Option<&'a Line>,
+    pub right: Option<&'a Line>,
+}
+
 #[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Default)]
 pub struct Stats {
     pub insertions: usize,
     pub deletions: usize,
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Status {
+    Unmodified,
+    Added,
+    Deleted,
+    Modified,
+    Renamed,
+    Copied,
+    Ignored,
+    Untracked,
+    Typechange,
+    Unreadable,
+    Conflicted,
+    Submodule,
+}
+
+impl Status {
+    fn new(delta: &git2::DiffDelta) -> Self {
+        let is_submodule = |file: git2::DiffFile| file.exists() && file.mode() == FileMode::Commit;
+        match delta.status() {
+            Delta::Modified if is_submodule(delta.new_file()) => Status::Submodule,
+            Delta::Unmodified => Status::Unmodified,
+            Delta::Added => Status::Added,
+            Delta::Deleted => Status::Deleted,
+            Delta::Modified => Status::Modified,
+            Delta::Renamed => Status::Renamed,
+            Delta::Copied => Status::Copied,
+            Delta::Ignored => Status::Ignored,
+            Delta::Untracked => Status::Untracked,
+            Delta::Typechange => Status::Typechange,
+            Delta::Unreadable => Status::Unreadable,
+            Delta::Conflicted => Status::Conflicted,
+        }
+    }
+
+    pub fn as_str(&self) -> &str {
+        match self {
+            Status::Unmodified => "unmodified",
+            Status::Added => "added",
+            Status::Deleted => "deleted",
+            Status::Modified => "modified",
+            Status::Renamed => "renamed",
+            Status::Copied => "copied",
+            Status::Ignored => "ignored",
+            Status::Untracked => "untracked",
+            Status::Typechange => "typechange",
+            Status::Unreadable => "unreadable",
+            Status::Conflicted => "conflicted",
+            Status::Submodule => "submodule",
+        }
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct File {
     pub name: String,
-    pub stats: Stats,
+    /// Previous path of a renamed or copied file.
+    pub old_name: Option<String>,
+    pub status: Status,
+    /// Similarity index of a renamed or copied file, in percent.
+    pub similarity: Option<u16>,
+    /// Old and new octal modes when the file mode changed.
+    pub mode_change: Option<(String, String)>,
+    pub binary: bool,
+    /// Why the hunks were left out, if they were; see [`Diff::from_trees`].
+    pub collapsed: Option<Collapse>,
+    /// Line counts, unknown for files past [`MAX_FILES`] since their patch is
+    /// never computed.
+    pub stats: Option<Stats>,
     pub hash: String,
     pub data: Vec<Line>,
 }
 
-impl From<(String, (Stats, String, Vec<Line>))> for File {
-    fn from(value: (String, (Stats, String, Vec<Line>))) -> Self {
-        let (name, (stats, hash, data)) = value;
-        File {
-            name,
-            stats,
-            hash,
-            data,
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Collapse {
+    /// Lock files, minified assets, vendored code and paths marked
+    /// `linguist-generated` or `linguist-vendored` in `.gitattributes`.
+    Generated,
+    /// More than [`MAX_FILE_LINES`] changed lines.
+    TooLarge,
+    /// Past [`MAX_FILES`] files or [`MAX_TOTAL_BYTES`] of rendered diff.
+    Limit,
+}
+
+impl Collapse {
+    pub fn as_str(&self) -> &str {
+        match self {
+            Collapse::Generated => "generated or vendored file",
+            Collapse::TooLarge => "large diff",
+            Collapse::Limit => "diff limit reached",
         }
     }
 }
@@ -51,103 +159,938 @@ impl From<(String, (Stats, String, Vec<Line>))> for File {
 #[derive(Debug)]
 pub struct Diff {
     pub files: Vec<File>,
-    pub stats: git2::DiffStats,
+    pub stats: Totals,
     pub tree: Vec<Entry>,
 }
 
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Totals {
+    pub files_changed: usize,
+    pub insertions: usize,
+    pub deletions: usize,
+}
+
+/// What a commit is diffed against.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Against {
+    /// The parent at this zero-based index.
+    Parent(usize),
+    /// Every parent at once; see [`Diff::combined`].
+    Combined,
+}
+
 #[derive(Debug)]
 pub struct Entry {
     pub path: String,
+    pub old_path: Option<String>,
     pub hash: String,
-    pub status: String,
+    pub status: Status,
+}
+
+/// Line diff algorithm. libgit2 has no histogram implementation, so links
+/// asking for it are rejected rather than quietly given another one.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase", try_from = "String")]
+pub enum Algorithm {
+    #[default]
+    Myers,
+    Patience,
+    Minimal,
+}
+
+impl TryFrom<String> for Algorithm {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        match value.as_str() {
+            "myers" => Ok(Algorithm::Myers),
+            "patience" => Ok(Algorithm::Patience),
+            "minimal" => Ok(Algorithm::Minimal),
+            "histogram" => {
+                Err("libgit2 has no histogram algorithm; use myers, patience or minimal".to_owned())
+            }
+            _ => Err(format!(
+                "unknown diff algorithm '{value}'; use myers, patience or minimal"
+            )),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Options {
+    /// Minimum similarity, in percent, for a delete/add pair to count as a
+    /// rename or copy.
+    pub similarity: u16,
+    pub ignore_whitespace: bool,
+    pub context_lines: Option<u32>,
+    pub algorithm: Algorithm,
+    /// Hash of the only file to include, rendered in full regardless of the
+    /// limits.
+    pub file: Option<String>,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Self {
+            similarity: DEFAULT_SIMILARITY,
+            ignore_whitespace: false,
+            context_lines: None,
+            algorithm: Algorithm::default(),
+            file: None,
+        }
+    }
+}
+
+impl Options {
+    fn diff_options(&self) -> DiffOptions {
+        let mut opts = DiffOptions::new();
+        opts.include_typechange(true)
+            .ignore_whitespace(self.ignore_whitespace);
+        if let Some(context_lines) = self.context_lines {
+            opts.context_lines(context_lines.min(MAX_CONTEXT_LINES));
+        }
+        match self.algorithm {
+            Algorithm::Myers => {}
+            Algorithm::Patience => {
+                opts.patience(true);
+            }
+            Algorithm::Minimal => {
+                opts.minimal(true);
+            }
+        }
+        opts
+    }
+
+    fn find_options(&self) -> DiffFindOptions {
+        let similarity = self.similarity.min(100);
+        let mut opts = DiffFindOptions::new();
+        opts.renames(true)
+            .copies(true)
+            .rename_threshold(similarity)
+            .copy_threshold(similarity);
+        opts
+    }
+}
+
+/// Download formats offered next to the rendered diff pages.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Format {
+    /// `git format-patch` mbox, one mail per commit.
+    Patch,
+    /// Plain unified diff as printed by `git diff`.
+    Diff,
+}
+
+/// Prints the changes between two trees as a unified diff, including binary
+/// patches so the output can be fed to `git apply`.
+pub fn raw(
+    repo: &git2::Repository,
+    old_tree: Option<&git2::Tree>,
+    new_tree: &git2::Tree,
+    options: &Options,
+) -> Result<Vec<u8>, git2::Error> {
+    let mut opts = options.diff_options();
+    opts.show_binary(true);
+    let mut diff = repo.diff_tree_to_tree(old_tree, Some(new_tree), Some(&mut opts))?;
+    diff.find_similar(Some(&mut options.find_options()))?;
+
+    let mut out = Vec::new();
+    diff.print(DiffFormat::Patch, |_, _, line| {
+        if let origin @ ('+' | '-' | ' ') = line.origin() {
+            out.push(origin as u8);
+        }
+        out.extend_from_slice(line.content());
+        true
+    })?;
+    Ok(out)
+}
+
+/// Formats `commit` as a `git format-patch` mail, numbered `number` of
+/// `count` within a series. Merges are diffed against their first parent.
+pub fn email(
+    repo: &git2::Repository,
+    commit: &git2::Commit,
+    number: usize,
+    count: usize,
+) -> Result<Vec<u8>, git2::Error> {
+    let old_tree = match commit.parent(0) {
+        Ok(parent) => Some(parent.tree()?),
+        Err(_) => None,
+    };
+    let new_tree = commit.tree()?;
+    let mut opts = DiffOptions::new();
+    opts.show_binary(true);
+    let mut diff = repo.diff_tree_to_tree(old_tree.as_ref(), Some(&new_tree), Some(&mut opts))?;
+    diff.find_similar(Some(DiffFindOptions::new().renames(true)))?;
+
+    let email = Email::from_diff(
+        &diff,
+        number,
+        count,
+        &commit.id(),
+        commit.summary().unwrap_or_default(),
+        commit.body().unwrap_or_default(),
+        &commit.author(),
+        &mut EmailCreateOptions::new(),
+    )?;
+    Ok(email.as_slice().to_vec())
 }
 
 impl Diff {
-    pub fn new(repo: &git2::Repository, id: &str) -> Diff {
-        let commit = repo.find_commit(Oid::from_str(id).unwrap()).unwrap();
+    pub fn new(
+        repo: &git2::Repository,
+        commit: &git2::Commit,
+        against: Against,
+        options: &Options,
+    ) -> Diff {
+        let index = match against {
+            Against::Parent(index) => index,
+            Against::Combined => return Self::combined(repo, commit, options),
+        };
         let commit_tree = commit.tree().unwrap();
+        let parent_tree = commit.parent(index).ok().map(|inner| inner.tree().unwrap());
+        Self::from_trees(repo, parent_tree.as_ref(), &commit_tree, options)
+    }
 
-        let parent_tree = commit.parents().next().map(|inner| inner.tree().unwrap());
-        let mut opts = DiffOptions::new();
-        let diff = repo
-            .diff_tree_to_tree(parent_tree.as_ref(), Some(&commit_tree), Some(&mut opts))
-            .unwrap();
+    /// Shows only what a merge changed relative to every parent, like
+    /// `git diff --cc`. The first-parent diff is the starting point: files
+    /// identical to any parent are dropped, and of the remaining hunks only
+    /// those touching a line that differs from all other parents are kept.
+    pub fn combined(repo: &git2::Repository, commit: &git2::Commit, options: &Options) -> Diff {
+        let commit_tree = commit.tree().unwrap();
+        let parent_trees: Vec<_> = commit
+            .parents()
+            .map(|parent| parent.tree().unwrap())
+            .collect();
+        let Some((first, others)) = parent_trees.split_first() else {
+            return Self::from_trees(repo, None, &commit_tree, options);
+        };
 
-        let tree: Vec<_> = diff
-            .deltas()
-            .map(|diff_delta| {
-                let path = diff_delta
-                    .new_file()
-                    .path()
-                    .unwrap()
-                    .to_string_lossy()
-                    .into_owned();
-                let mut hasher = Sha256::new();
-                hasher.update(path.as_bytes());
-                let hash = format!("{:x}", hasher.finalize());
-                let status = match diff_delta.status() {
-                    git2::Delta::Added => "added".to_owned(),
-                    git2::Delta::Modified => "modified".to_owned(),
-                    git2::Delta::Deleted => "deleted".to_owned(),
-                    _ => unimplemented!(),
-                };
-                Entry { path, hash, status }
-            })
+        let mut diff = Self::from_trees(repo, Some(first), &commit_tree, options);
+        let others: Vec<_> = others
+            .iter()
+            .map(|tree| changed_positions(repo, tree, &commit_tree, options))
             .collect();
 
+        diff.files.retain_mut(|file| {
+            let Some(changed) = others
+                .iter()
+                .map(|positions| positions.get(&file.name))
+                .collect::<Option<Vec<_>>>()
+            else {
+                return false;
+            };
+            if file.data.is_empty() {
+                return true;
+            }
+            file.data = conflicting_hunks(&file.data, &changed);
+            file.stats = Some(Stats {
+                insertions: run_count(&file.data, 1),
+                deletions: run_count(&file.data, 2),
+            });
+            !file.data.is_empty()
+        });
+        diff.tree
+            .retain(|entry| diff.files.iter().any(|file| file.hash == entry.hash));
+        diff.stats = Totals {
+            files_changed: diff.files.len(),
+            insertions: diff
+                .files
+                .iter()
+                .filter_map(|file| file.stats.as_ref())
+                .map(|stats| stats.insertions)
+                .sum(),
+            deletions: diff
+                .files
+                .iter()
+                .filter_map(|file| file.stats.as_ref())
+                .map(|stats| stats.deletions)
+                .sum(),
+        };
+        diff
+    }
+
+    pub fn from_trees(
+        repo: &git2::Repository,
+        old_tree: Option<&git2::Tree>,
+        new_tree: &git2::Tree,
+        options: &Options,
+    ) -> Diff {
+        let mut opts = options.diff_options();
+        let mut diff = repo
+            .diff_tree_to_tree(old_tree, Some(new_tree), Some(&mut opts))
+            .unwrap();
+
+        diff.find_similar(Some(&mut options.find_options()))
+            .unwrap();
+
         let stats = diff.stats().unwrap();
+        let stats = Totals {
+            files_changed: stats.files_changed(),
+            insertions: stats.insertions(),
+            deletions: stats.deletions(),
+        };
+        let attributes = Attributes::from_tree(repo, new_tree);
 
-        let mut map: HashMap<String, (Stats, String, Vec<Line>)> = HashMap::new();
-        _ = diff.print(DiffFormat::Patch, |delta, _, line| {
-            let path = delta
-                .new_file()
-                .path()
-                .map(|inner| inner.to_string_lossy().into_owned())
-                .unwrap();
-            map.entry(path.clone())
-                .and_modify(|(stats, hash, lines)| {
-                    let mut hasher = Sha256::new();
-                    hasher.update(path.as_bytes());
-                    *hash = format!("{:x}", hasher.finalize());
-                    let content = String::from_utf8_lossy(line.content());
-                    let content = content.trim_matches('\n').to_string();
-                    let old_lineno = line.old_lineno().map(|n| n as i32).unwrap_or(-1);
-                    let new_lineno = line.new_lineno().map(|n| n as i32).unwrap_or(-1);
-                    let origin = match line.origin() {
-                        ' ' => 0,
-                        '+' => 1,
-                        '-' => 2,
-                        '=' => 3,
-                        '>' => 4,
-                        '<' => 5,
-                        'F' => 6,
-                        'H' => 7,
-                        'B' => 8,
-                        _ => unreachable!(),
-                    };
-                    if content.starts_with("@@") {
-                        lines.push(Line::new(
-                            old_lineno,
-                            new_lineno,
-                            format!("  {content}"),
-                            origin,
-                        ));
+        let mut budget = MAX_TOTAL_BYTES;
+        let mut files = Vec::new();
+        let mut tree = Vec::new();
+        for (index, delta) in diff.deltas().enumerate() {
+            let name = delta_name(&delta);
+            let hash = path_hash(&name);
+            let single = options.file.is_some();
+            if options.file.as_ref().is_some_and(|file| *file != hash) {
+                continue;
+            }
+
+            let mut patch = if single || index < MAX_FILES {
+                Patch::from_diff(&diff, index).ok().flatten()
+            } else {
+                None
+            };
+            let collapsed = if single {
+                None
+            } else if index >= MAX_FILES {
+                Some(Collapse::Limit)
+            } else if is_generated(&attributes, &name) {
+                Some(Collapse::Generated)
+            } else {
+                patch.as_ref().and_then(|patch| {
+                    let (context, additions, deletions) = patch.line_stats().unwrap_or_default();
+                    let size = patch.size(true, true, false);
+                    if context + additions + deletions > MAX_FILE_LINES {
+                        Some(Collapse::TooLarge)
+                    } else if size > budget {
+                        Some(Collapse::Limit)
                     } else {
-                        if line.origin_value() == DiffLineType::Addition {
-                            stats.insertions += 1;
-                        } else if line.origin_value() == DiffLineType::Deletion {
-                            stats.deletions += 1;
-                        }
-                        lines.push(Line::new(old_lineno, new_lineno, content, origin));
+                        budget -= size;
+                        None
                     }
                 })
-                .or_insert((Stats::default(), String::new(), Vec::new()));
-            true
-        });
+            };
+
+            let mut file = File::new(&delta, patch.as_mut(), collapsed, options.ignore_whitespace);
+            file.highlight(repo, &delta, &attributes);
+            tree.push(Entry {
+                path: file.name.clone(),
+                old_path: file.old_name.clone(),
+                hash: file.hash.clone(),
+                status: file.status,
+            });
+            files.push(file);
+        }
 
-        let mut files: Vec<_> = map.into_iter().map(File::from).collect();
         files.sort_unstable_by_key(|inner| inner.name.clone());
 
         Self { files, stats, tree }
     }
 }
+
+fn path_of(file: git2::DiffFile) -> String {
+    file.path()
+        .map(|inner| inner.to_string_lossy().into_owned())
+        .unwrap_or_default()
+}
+
+fn delta_name(delta: &git2::DiffDelta) -> String {
+    match delta.status() {
+        Delta::Deleted => path_of(delta.old_file()),
+        _ => path_of(delta.new_file()),
+    }
+}
+
+fn path_hash(path: &str) -> String {
+    let mut hasher = Sha256::new();
+    hasher.update(path.as_bytes());
+    format!("{:x}", hasher.finalize())
+}
+
+const GENERATED_FILE_NAMES: &[&str] = &[
+    "Cargo.lock",
+    "package-lock.json",
+    "npm-shrinkwrap.json",
+    "yarn.lock",
+    "pnpm-lock.yaml",
+    "Gemfile.lock",
+    "composer.lock",
+    "poetry.lock",
+    "Pipfile.lock",
+    "go.sum",
+    "flake.lock",
+];
+
+const VENDORED_DIRECTORIES: &[&str] = &["vendor", "node_modules", "third_party", "third-party"];
+
+fn is_generated(attributes: &Attributes, path: &str) -> bool {
+    if attributes.get(path, "linguist-generated").is_some()
+        || attributes.get(path, "linguist-vendored").is_some()
+    {
+        return attributes.is_set(path, "linguist-generated")
+            || attributes.is_set(path, "linguist-vendored");
+    }
+    let file_name = path.rsplit('/').next().unwrap_or(path);
+    GENERATED_FILE_NAMES.contains(&file_name)
+        || file_name.ends_with(".min.js")
+        || file_name.ends_with(".min.css")
+        || file_name.ends_with(".map")
+        || path
+            .split('/')
+            .rev()
+            .skip(1)
+            .any(|directory| VENDORED_DIRECTORIES.contains(&directory))
+}
+
+impl File {
+    fn new(
+        delta: &git2::DiffDelta,
+        mut patch: Option<&mut Patch>,
+        collapsed: Option<Collapse>,
+        ignore_whitespace: bool,
+    ) -> Self {
+        let status = Status::new(delta);
+        let name = delta_name(delta);
+        let old_name = match status {
+            Status::Renamed | Status::Copied => Some(path_of(delta.old_file())),
+            _ => None,
+        };
+
+        let old_mode = delta.old_file().mode();
+        let new_mode = delta.new_file().mode();
+        let mode_change = (delta.old_file().exists()
+            && delta.new_file().exists()
+            && old_mode != new_mode)
+            .then(|| {
+                (
+                    format!("{:06o}", u32::from(old_mode)),
+                    format!("{:06o}", u32::from(new_mode)),
+                )
+            });
+        let binary = delta.flags().is_binary()
+            || delta.old_file().is_binary()
+            || delta.new_file().is_binary();
+
+        let hash = path_hash(&name);
+
+        let similarity = match (old_name.as_ref(), patch.as_deref_mut()) {
+            (Some(_), Some(patch)) => similarity_index(patch),
+            _ => None,
+        };
+
+        let (stats, data) = match patch {
+            Some(patch) if collapsed.is_none() => {
+                let (stats, data) = read_hunks(patch, ignore_whitespace);
+                (Some(stats), data)
+            }
+            Some(patch) => {
+                let (_, insertions, deletions) = patch.line_stats().unwrap_or_default();
+                let stats = Stats {
+                    insertions,
+                    deletions,
+                };
+                (Some(stats), Vec::new())
+            }
+            None => (None, Vec::new()),
+        };
+
+        Self {
+            name,
+            old_name,
+            status,
+            similarity,
+            mode_change,
+            binary,
+            collapsed,
+            stats,
+            hash,
+            data,
+        }
+    }
+}
+
+fn read_hunks(patch: &Patch, ignore_whitespace: bool) -> (Stats, Vec<Line>) {
+    let mut stats = Stats::default();
+    let mut data = Vec::new();
+    for hunk_index in 0..patch.num_hunks() {
+        let Ok((hunk, line_count)) = patch.hunk(hunk_index) else {
+            continue;
+        };
+        let header = String::from_utf8_lossy(hunk.header());
+        let header = header.trim_matches('\n');
+        data.push(Line::new(-1, -1, format!("  {header}"), 7));
+        for line_index in 0..line_count {
+            let Ok(line) = patch.line_in_hunk(hunk_index, line_index) else {
+                continue;
+            };
+            let content = String::from_utf8_lossy(line.content());
+            let content = content.trim_matches('\n').to_string();
+            let old_lineno = line.old_lineno().map(|n| n as i32).unwrap_or(-1);
+            let new_lineno = line.new_lineno().map(|n| n as i32).unwrap_or(-1);
+            let origin = match line.origin_value() {
+                DiffLineType::Context => 0,
+                DiffLineType::Addition => {
+                    stats.insertions += 1;
+                    1
+                }
+                DiffLineType::Deletion => {
+                    stats.deletions += 1;
+                    2
+                }
+                DiffLineType::ContextEOFNL => 3,
+                DiffLineType::AddEOFNL => 4,
+                DiffLineType::DeleteEOFNL => 5,
+                DiffLineType::FileHeader => 6,
+                DiffLineType::HunkHeader => 7,
+                DiffLineType::Binary => 8,
+            };
+            data.push(Line::new(old_lineno, new_lineno, content, origin));
+        }
+    }
+
+    highlight_words(&mut data, ignore_whitespace);
+
+    (stats, data)
+}
+
+/// Positions in the new tree that differ from `old_tree`, by path: the line
+/// number of every added line and, for deleted lines, of the line that follows
+/// them.
+fn changed_positions(
+    repo: &git2::Repository,
+    old_tree: &git2::Tree,
+    new_tree: &git2::Tree,
+    options: &Options,
+) -> HashMap<String, HashSet<i32>> {
+    let mut opts = options.diff_options();
+    let Ok(diff) = repo.diff_tree_to_tree(Some(old_tree), Some(new_tree), Some(&mut opts)) else {
+        return HashMap::new();
+    };
+
+    let mut changed = HashMap::new();
+    for (index, delta) in diff.deltas().enumerate() {
+        let mut positions = HashSet::new();
+        if let Ok(Some(patch)) = Patch::from_diff(&diff, index) {
+            let (_, data) = read_hunks(&patch, false);
+            positions.extend(
+                line_positions(&data).filter_map(|(line, position)| {
+                    matches!(line.origin, 1 | 2).then_some(position)
+                }),
+            );
+        }
+        changed.insert(delta_name(&delta), positions);
+    }
+    changed
+}
+
+/// Pairs each line with its position in the new file; deleted lines take the
+/// position of the line after them.
+fn line_positions(data: &[Line]) -> impl Iterator<Item = (&Line, i32)> {
+    let mut next = 1;
+    data.iter().map(move |line| {
+        if line.origin == 7 {
+            next = hunk_new_start(&line.content).unwrap_or(next);
+        }
+        let position = if line.new_lineno > 0 {
+            next = line.new_lineno + 1;
+            line.new_lineno
+        } else {
+            next
+        };
+        (line, position)
+    })
+}
+
+/// New-side start line from a `@@ -a,b +c,d @@` hunk header.
+fn hunk_new_start(header: &str) -> Option<i32> {
+    let (_, rest) = header.split_once('+')?;
+    let end = rest.find(|c: char| !c.is_ascii_digit())?;
+    rest[..end].parse().ok()
+}
+
+/// Keeps the hunks of `data` with a changed line whose position is also
+/// changed relative to every other parent.
+fn conflicting_hunks(data: &[Line], others: &[&HashSet<i32>]) -> Vec<Line> {
+    let positions: Vec<_> = line_positions(data).map(|(_, position)| position).collect();
+    let mut kept = Vec::new();
+    let mut start = 0;
+    while start < data.len() {
+        let end = data[start + 1..]
+            .iter()
+            .position(|line| line.origin == 7)
+            .map_or(data.len(), |offset| start + 1 + offset);
+        let conflicting = (start..end).any(|index| {
+            matches!(data[index].origin, 1 | 2)
+                && others
+                    .iter()
+                    .all(|changed| changed.contains(&positions[index]))
+        });
+        if conflicting {
+            kept.extend_from_slice(&data[start..end]);
+        }
+        start = end;
+    }
+    kept
+}
+
+fn run_count(data: &[Line], origin: u8) -> usize {
+    data.iter().filter(|line| line.origin == origin).count()
+}
+
+/// libgit2 doesn't expose the similarity score of a delta, but writes it into
+/// the patch header as `similarity index N%`.
+fn similarity_index(patch: &mut Patch) -> Option<u16> {
+    let buf = patch.to_buf().ok()?;
+    let text = String::from_utf8_lossy(&buf);
+    text.lines()
+        .take_while(|line| !line.starts_with("@@"))
+        .find_map(|line| line.strip_prefix("similarity index "))
+        .and_then(|value| value.trim_end_matches('%').parse().ok())
+}
+
+impl File {
+    /// Fills in highlighted HTML for every line, taken from the highlighted
+    /// old or new blob so that constructs spanning hunks are parsed correctly.
+    fn highlight(
+        &mut self,
+        repo: &git2::Repository,
+        delta: &git2::DiffDelta,
+        attributes: &Attributes,
+    ) {
+        if self.binary || self.data.is_empty() {
+            return;
+        }
+        let side = |file: git2::DiffFile| {
+            let blob = repo.find_blob(file.id()).ok()?;
+            highlight::lines(file.id(), blob.content(), &path_of(file), attributes)
+        };
+        let old = side(delta.old_file());
+        let new = side(delta.new_file());
+
+        for line in &mut self.data {
+            let (lines, lineno) = match line.origin {
+                0 | 1 => (&new, line.new_lineno),
+                2 => (&old, line.old_lineno),
+                _ => continue,
+            };
+            line.html = lines
+                .as_ref()
+                .and_then(|lines| lines.get(usize::try_from(lineno - 1).ok()?))
+                .cloned();
+        }
+    }
+
+    pub fn split_rows(&self) -> Vec<SplitRow<'_>> {
+        let mut rows = Vec::new();
+        let mut index = 0;
+        while index < self.data.len() {
+            let line = &self.data[index];
+            match line.origin {
+                2 => {
+                    let deletions = run_length(&self.data[index..], 2);
+                    let additions = run_length(&self.data[index + deletions..], 1);
+                    for offset in 0..deletions.max(additions) {
+                        rows.push(SplitRow {
+                            left: (offset < deletions).then(|| &self.data[index + offset]),
+                            right: (offset < additions)
+                                .then(|| &self.data[index + deletions + offset]),
+                        });
+                    }
+                    index += deletions + additions;
+                    continue;
+                }
+                1 | 4 => rows.push(SplitRow {
+                    left: None,
+                    right: Some(line),
+                }),
+                5 | 7 => rows.push(SplitRow {
+                    left: Some(line),
+                    right: None,
+                }),
+                _ => rows.push(SplitRow {
+                    left: Some(line),
+                    right: Some(line),
+                }),
+            }
+            index += 1;
+        }
+        rows
+    }
+}
+
+fn run_length(lines: &[Line], origin: u8) -> usize {
+    lines
+        .iter()
+        .take_while(|line| line.origin == origin)
+        .count()
+}
+
+/// Pairs each run of deleted lines with the run of added lines following it
+/// and marks the words that differ between the two sides.
+fn highlight_words(data: &mut [Line], ignore_whitespace: bool) {
+    let mut index = 0;
+    while index < data.len() {
+        if data[index].origin != 2 {
+            index += 1;
+            continue;
+        }
+        let deletions = run_length(&data[index..], 2);
+        let additions = run_length(&data[index + deletions..], 1);
+        for offset in 0..deletions.min(additions) {
+            let old = index + offset;
+            let new = index + deletions + offset;
+            if let Some((old_segments, new_segments)) =
+                word_diff(&data[old].content, &data[new].content, ignore_whitespace)
+            {
+                data[old].segments = old_segments;
+                data[new].segments = new_segments;
+            }
+        }
+        index += deletions + additions;
+    }
+}
+
+fn word_diff(
+    old: &str,
+    new: &str,
+    ignore_whitespace: bool,
+) -> Option<(Vec<Segment>, Vec<Segment>)> {
+    let old_tokens = tokenize(old);
+    let new_tokens = tokenize(new);
+    if old_tokens.len() * new_tokens.len() > MAX_WORD_DIFF_COST {
+        return None;
+    }
+
+    // longest common subsequence over tokens
+    let (n, m) = (old_tokens.len(), new_tokens.len());
+    let mut table = vec![vec![0u32; m + 1]; n + 1];
+    for i in (0..n).rev() {
+        for j in (0..m).rev() {
+            table[i][j] = if old_tokens[i] == new_tokens[j] {
+                table[i + 1][j + 1] + 1
+            } else {
+                table[i + 1][j].max(table[i][j + 1])
+            };
+        }
+    }
+    if table[0][0] == 0 {
+        return None;
+    }
+
+    let mut old_changed = vec![true; n];
+    let mut new_changed = vec![true; m];
+    let (mut i, mut j) = (0, 0);
+    while i < n && j < m {
+        if old_tokens[i] == new_tokens[j] {
+            old_changed[i] = false;
+            new_changed[j] = false;
+            i += 1;
+            j += 1;
+        } else if table[i + 1][j] >= table[i][j + 1] {
+            i += 1;
+        } else {
+            j += 1;
+        }
+    }
+
+    Some((
+        to_segments(&old_tokens, &old_changed, ignore_whitespace),
+        to_segments(&new_tokens, &new_changed, ignore_whitespace),
+    ))
+}
+
+fn tokenize(input: &str) -> Vec<&str> {
+    #[derive(PartialEq)]
+    enum Class {
+        Word,
+        Space,
+        Other,
+    }
+    let class = |c: char| {
+        if c.is_alphanumeric() || c == '_' {
+            Class::Word
+        } else if c.is_whitespace() {
+            Class::Space
+        } else {
+            Class::Other
+        }
+    };
+
+    let mut tokens = Vec::new();
+    let mut start = 0;
+    let mut previous: Option<Class> = None;
+    for (offset, c) in input.char_indices() {
+        let current = class(c);
+        let split = match previous.as_ref() {
+            Some(Class::Other) | None => true,
+            Some(previous) => *previous != current,
+        };
+        if split && offset > start {
+            tokens.push(&input[start..offset]);
+            start = offset;
+        }
+        previous = Some(current);
+    }
+    if start < input.len() {
+        tokens.push(&input[start..]);
+    }
+    tokens
+}
+
+fn to_segments(tokens: &[&str], changed: &[bool], ignore_whitespace: bool) -> Vec<Segment> {
+    let mut segments: Vec<Segment> = Vec::new();
+    for (token, changed) in tokens.iter().zip(changed) {
+        let changed = *changed && !(ignore_whitespace && token.trim().is_empty());
+        match segments.last_mut() {
+            Some(last) if last.changed == changed => last.text.push_str(token),
+            _ => segments.push(Segment {
+                text: (*token).to_owned(),
+                changed,
+            }),
+        }
+    }
+    segments
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn segments(segments: &[Segment]) -> Vec<(&str, bool)> {
+        segments
+            .iter()
+            .map(|segment| (segment.text.as_str(), segment.changed))
+            .collect()
+    }
+
+    #[test]
+    fn tokenize_groups_words_and_spaces() {
+        assert_eq!(
+            tokenize("foo_bar(baz,  1))"),
+            ["foo_bar", "(", "baz", ",", "  ", "1", ")", ")"]
+        );
+        assert_eq!(tokenize("héllo wörld"), ["héllo", " ", "wörld"]);
+        assert!(tokenize("").is_empty());
+    }
+
+    #[test]
+    fn word_diff_marks_changed_words() {
+        let (old, new) = word_diff("let x = 1;", "let y = 1;", false).unwrap();
+        assert_eq!(
+            segments(&old),
+            [("let ", false), ("x", true), (" = 1;", false)]
+        );
+        assert_eq!(
+            segments(&new),
+            [("let ", false), ("y", true), (" = 1;", false)]
+        );
+    }
+
+    #[test]
+    fn word_diff_ignores_whitespace_changes() {
+        let (_, new) = word_diff("a b", "a  b", true).unwrap();
+        assert!(new.iter().all(|segment| !segment.changed));
+        let (_, new) = word_diff("a b", "a  b", false).unwrap();
+        assert_eq!(segments(&new), [("a", false), ("  ", true), ("b", false)]);
+    }
+
+    #[test]
+    fn word_diff_skips_lines_without_common_tokens() {
+        assert!(word_diff("abc", "xyz", false).is_none());
+    }
+
+    #[test]
+    fn word_diff_gives_up_past_the_comparison_cap() {
+        // Every `(` is a token of its own.
+        let line = "(".repeat(500);
+        assert_eq!(
+            tokenize(&line).len() * tokenize(&line).len(),
+            MAX_WORD_DIFF_COST
+        );
+        assert!(word_diff(&line, &line, false).is_some());
+        assert!(word_diff(&format!("{line}("), &line, false).is_none());
+    }
+
+    #[test]
+    fn hunk_new_start_reads_the_new_side() {
+        assert_eq!(hunk_new_start("  @@ -1,3 +10,4 @@ fn main() {"), Some(10));
+        assert_eq!(hunk_new_start("@@ -1 +7 @@"), Some(7));
+        assert_eq!(hunk_new_start("not a hunk header"), None);
+    }
+
+    fn hunks() -> Vec<Line> {
+        vec![
+            Line::new(-1, -1, "  @@ -1,2 +1,2 @@".to_owned(), 7),
+            Line::new(1, 1, "same".to_owned(), 0),
+            Line::new(2, -1, "old".to_owned(), 2),
+            Line::new(-1, 2, "new".to_owned(), 1),
+            Line::new(-1, -1, "  @@ -10,0 +10,1 @@".to_owned(), 7),
+            Line::new(-1, 10, "added".to_owned(), 1),
+        ]
+    }
+
+    #[test]
+    fn conflicting_hunks_keeps_lines_changed_against_every_parent() {
+        let data = hunks();
+        let both: HashSet<i32> = [2, 10].into();
+        let second: HashSet<i32> = [10].into();
+
+        let kept = conflicting_hunks(&data, &[&both]);
+        assert_eq!(kept, data);
+
+        let kept = conflicting_hunks(&data, &[&both, &second]);
+        assert_eq!(kept, data[4..]);
+
+        assert!(conflicting_hunks(&data, &[&HashSet::new()]).is_empty());
+    }
+
+    #[test]
+    fn files_past_the_limit_have_no_stats() {
+        let path = std::env::temp_dir().join(format!("gecko-diff-{}", std::process::id()));
+        let repo = git2::Repository::init_bare(&path).unwrap();
+        let mut builder = repo.treebuilder(None).unwrap();
+        for index in 0..=MAX_FILES {
+            let blob = repo.blob(format!("line {index}\n").as_bytes()).unwrap();
+            builder
+                .insert(format!("{index:04}.txt"), blob, 0o100644)
+                .unwrap();
+        }
+        let tree = repo.find_tree(builder.write().unwrap()).unwrap();
+
+        let diff = Diff::from_trees(&repo, None, &tree, &Options::default());
+        std::fs::remove_dir_all(&path).unwrap();
+
+        assert_eq!(diff.files.len(), MAX_FILES + 1);
+        assert_eq!(diff.stats.insertions, MAX_FILES + 1);
+        let first = &diff.files[0];
+        assert_eq!(first.collapsed, None);
+        assert_eq!(
+            first.stats,
+            Some(Stats {
+                insertions: 1,
+                deletions: 0
+            })
+        );
+        let last = &diff.files[MAX_FILES];
+        assert_eq!(last.collapsed, Some(Collapse::Limit));
+        assert_eq!(last.stats, None);
+        assert!(last.data.is_empty());
+    }
+
+    #[test]
+    fn algorithms() {
+        #[derive(Deserialize)]
+        struct Query {
+            algorithm: Algorithm,
+        }
+        let parse =
+            |query: &str| serde_urlencoded::from_str::<Query>(query).map(|inner| inner.algorithm);
+        assert_eq!(parse("algorithm=patience").unwrap(), Algorithm::Patience);
+        assert_eq!(parse("algorithm=minimal").unwrap(), Algorithm::Minimal);
+        let error = parse("algorithm=histogram").unwrap_err().to_string();
+        assert!(
+            error.contains("libgit2 has no histogram algorithm"),
+            "{error}"
+        );
+        assert!(parse("algorithm=fast").is_err());
+    }
+}
@@ -0,0 +1,86 @@
+//! Repositories on disk: bare repositories in the working directory named
+//! like the repository, plus symlinks from names they were renamed from.
+
+use std::path::Path;
+
+use crate::model::Redirect;
+
+/// Branch new repositories start on.
+pub const INITIAL_BRANCH: &str = "main";
+
+/// Creates the bare repository for a new repository named `name`. Fails with
+/// [`git2::ErrorCode::Exists`] if anything is at that path already, be it the
+/// directory of another owner's repository or the symlink of a redirect.
+pub fn init_repository(name: &str, branch: &str) -> Result<(), git2::Error> {
+    let path = Path::new(name);
+    if let Err(error) = std::fs::create_dir(path) {
+        let code = if error.kind() == std::io::ErrorKind::AlreadyExists {
+            git2::ErrorCode::Exists
+        } else {
+            git2::ErrorCode::GenericError
+        };
+        let message = format!("can't create '{name}': {error}");
+        return Err(git2::Error::new(code, git2::ErrorClass::Os, message));
+    }
+    let mut options = git2::RepositoryInitOptions::new();
+    options.bare(true).no_reinit(true).initial_head(branch);
+    if let Err(error) = git2::Repository::init_opts(path, &options) {
+        let removed = std::fs::remove_dir_all(path);
+        debug_assert!(removed.is_ok());
+        return Err(error);
+    }
+    Ok(())
+}
+
+/// Removes the directory of a deleted repository and the symlinks its
+/// redirects left. Directories are named after repositories of any owner, so
+/// callers first make sure no other repository has the same name.
+pub fn remove_repository(name: &str, redirects: &[Redirect]) {
+    // Symlinks left by renames lead here, possibly through each other.
+    let path = Path::new(name);
+    let directory = std::fs::canonicalize(path).ok();
+    let links: Vec<&Path> = redirects
+        .iter()
+        .map(|redirect| Path::new(&redirect.name))
+        .filter(|link| link.is_symlink() && std::fs::canonicalize(link).ok() == directory)
+        .collect();
+    for link in links {
+        let removed = std::fs::remove_file(link);
+        debug_assert!(removed.is_ok());
+    }
+    if path.is_dir() && !path.is_symlink() {
+        let removed = std::fs::remove_dir_all(path);
+        debug_assert!(removed.is_ok());
+    }
+}
+
+/// Names of the bare repositories in the working directory, leaving out the
+/// symlinks of redirects and hidden directories such as `.git`.
+pub fn repositories() -> Vec<String> {
+    let Ok(entries) = std::fs::read_dir(".") else {
+        return vec![];
+    };
+    let mut names: Vec<String> = entries
+        .flatten()
+        .filter(|entry| entry.file_type().is_ok_and(|inner| inner.is_dir()))
+        .filter(|entry| git2::Repository::open_bare(entry.path()).is_ok())
+        .filter_map(|entry| entry.file_name().into_string().ok())
+        .filter(|name| !name.starts_with('.'))
+        .collect();
+    names.sort();
+    names
+}
+
+/// Bytes used by the files under `path`, without following symlinks.
+pub fn usage(path: &Path) -> u64 {
+    let Ok(metadata) = path.symlink_metadata() else {
+        return 0;
+    };
+    if !metadata.is_dir() {
+        return metadata.len();
+    }
+    let Ok(entries) = std::fs::read_dir(path) else {
+        return 0;
+    };
+    entries.flatten().map(|entry| usage(&entry.path())).sum()
+}
@@ -0,0 +1,521 @@
+use actix_identity::Identity;
+use actix_web::{http::Method, web, HttpRequest, HttpResponse, Responder};
+use askama::Template;
+use askama_actix::TemplateToResponse;
+use git2::{BranchType, ObjectType, Oid, Repository, Signature, Tree};
+
+use crate::{
+    history, markup,
+    model::{self, Event, User},
+    refs,
+    repository::Context,
+    State,
+};
+
+const FILEMODE_BLOB: i32 = 0o100644;
+const FILEMODE_TREE: i32 = 0o040000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Mode {
+    Create,
+    Edit,
+    Delete,
+}
+
+impl Mode {
+    fn as_str(&self) -> &str {
+        match self {
+            Mode::Create => "new",
+            Mode::Edit => "edit",
+            Mode::Delete => "delete",
+        }
+    }
+}
+
+#[derive(Template)]
+#[template(path = "editor.html")]
+struct EditorTemplate<'a> {
+    title: &'a str,
+    identity: &'a Option<User>,
+    repository: &'a model::Repository,
+    username: &'a str,
+    name: &'a str,
+    mode: Mode,
+    /// Revision the editor was opened at, as given in the URL.
+    branch: &'a str,
+    /// Whether `branch` is a local branch that can be committed to directly.
+    is_branch: bool,
+    path: &'a str,
+    content: &'a str,
+    message: &'a str,
+    /// Commit the editor was opened at, empty in a repository without commits.
+    base: &'a str,
+    /// Whether the file is markdown and can be previewed.
+    previewable: bool,
+}
+
+#[derive(Debug, serde::Deserialize)]
+pub struct EditForm {
+    path: String,
+    #[serde(default)]
+    content: String,
+    message: String,
+    /// Commit to a new branch with this name instead of the open branch.
+    #[serde(default)]
+    new_branch: String,
+    base: String,
+}
+
+/// `/new/{branch}/{dir}`: creates a file.
+pub async fn create(
+    req: HttpRequest,
+    path: web::Path<Vec<String>>,
+    state: web::Data<State>,
+    identity: Option<Identity>,
+    form: Option<web::Form<EditForm>>,
+) -> impl Responder {
+    editor(Mode::Create, req, path, state, identity, form).await
+}
+
+/// `/edit/{branch}/{path}`: changes and possibly renames a file.
+pub async fn edit(
+    req: HttpRequest,
+    path: web::Path<Vec<String>>,
+    state: web::Data<State>,
+    identity: Option<Identity>,
+    form: Option<web::Form<EditForm>>,
+) -> impl Responder {
+    editor(Mode::Edit, req, path, state, identity, form).await
+}
+
+/// `/delete/{branch}/{path}`: deletes a file.
+pub async fn delete(
+    req: HttpRequest,
+    path: web::Path<Vec<String>>,
+    state: web::Data<State>,
+    identity: Option<Identity>,
+    form: Option<web::Form<EditForm>>,
+) -> impl Responder {
+    editor(Mode::Delete, req, path, state, identity, form).await
+}
+
+async fn editor(
+    mode: Mode,
+    req: HttpRequest,
+    path: web::Path<Vec<String>>,
+    state: web::Data<State>,
+    identity: Option<Identity>,
+    form: Option<web::Form<EditForm>>,
+) -> HttpResponse {
+    let path = path.into_inner();
+    let (username, name) = (&path[0], &path[1]);
+    let branch = &path[2].replace("%2F", "/").replace("%2f", "/");
+    let tail = path.get(3).map(|tail| tail.trim_matches('/')).unwrap_or("");
+
+    let context = match Context::load(&state, username, name, identity).await {
+        Ok(context) => context,
+        Err(response) => return response,
+    };
+    let user = match context.writer() {
+        Ok(user) => user,
+        Err(response) => return response,
+    };
+    let repo = &context.repo;
+
+    let commit = match refs::resolve(repo, branch) {
+        Ok(commit) => Some(commit),
+        Err(_) if refs::is_empty(repo) && mode == Mode::Create => None,
+        Err(error) => return HttpResponse::NotFound().body(error),
+    };
+
+    match *req.method() {
+        Method::GET => {
+            let content = match mode {
+                Mode::Create => String::new(),
+                Mode::Edit | Mode::Delete => {
+                    let blob = commit
+                        .as_ref()
+                        .and_then(|commit| commit.tree().ok())
+                        .and_then(|tree| tree.get_path(std::path::Path::new(tail)).ok())
+                        .and_then(|entry| repo.find_blob(entry.id()).ok());
+                    let Some(blob) = blob else {
+                        let body = format!("the path '{tail}' does not exist in the given tree");
+                        return HttpResponse::NotFound().body(body);
+                    };
+                    if blob.is_binary() && mode == Mode::Edit {
+                        return HttpResponse::BadRequest().body("binary files can't be edited");
+                    }
+                    String::from_utf8_lossy(blob.content()).into_owned()
+                }
+            };
+            let path = match mode {
+                Mode::Create if !tail.is_empty() => format!("{tail}/"),
+                Mode::Create => String::new(),
+                Mode::Edit | Mode::Delete => tail.to_owned(),
+            };
+            let base = commit
+                .as_ref()
+                .map(|commit| commit.id().to_string())
+                .unwrap_or_default();
+            let message = match mode {
+                Mode::Create => String::new(),
+                Mode::Edit => format!("Update {path}"),
+                Mode::Delete => format!("Delete {path}"),
+            };
+            let title = format!("{} {name}/{path}", mode.as_str());
+
+            EditorTemplate {
+                title: &title,
+                identity: &context.identity,
+                repository: &context.repository,
+                username,
+                name,
+                mode,
+                branch,
+                is_branch: repo.find_branch(branch, BranchType::Local).is_ok() || commit.is_none(),
+                path: &path,
+                content: &content,
+                message: &message,
+                base: &base,
+                previewable: is_markdown(&path),
+            }
+            .to_response()
+        }
+        Method::POST => {
+            let Some(form) = form else {
+                return HttpResponse::BadRequest().finish();
+            };
+            let location = match save(mode, repo, user, branch, tail, &form) {
+                Ok(location) => location,
+                Err(response) => return response,
+            };
+            let new_branch = form.new_branch.trim();
+            if !new_branch.is_empty() {
+                let description = format!("{name}:{new_branch}");
+                state
+                    .database
+                    .add_user_log(user, Event::BranchCreate, Some(description))
+                    .await;
+            }
+            HttpResponse::SeeOther()
+                .insert_header(("Location", format!("/@{username}/{name}/{location}")))
+                .finish()
+        }
+        _ => HttpResponse::NotFound().finish(),
+    }
+}
+
+/// Commits the edit and returns the page to show next, relative to the
+/// repository.
+fn save(
+    mode: Mode,
+    repo: &Repository,
+    user: &User,
+    branch: &str,
+    tail: &str,
+    form: &EditForm,
+) -> Result<String, HttpResponse> {
+    let new_path = match mode {
+        Mode::Delete => tail.to_owned(),
+        Mode::Create | Mode::Edit => clean_path(&form.path).ok_or_else(|| {
+            HttpResponse::BadRequest().body(format!("'{}' is not a valid file name", form.path))
+        })?,
+    };
+    let old_path = (mode != Mode::Create).then_some(tail);
+    let touched: Vec<&str> = old_path.into_iter().chain([new_path.as_str()]).collect();
+    let target = Target {
+        branch,
+        message: &form.message,
+        new_branch: &form.new_branch,
+        base: &form.base,
+    };
+
+    let (target, tree) = commit_changes(repo, user, &target, &touched, |parent_tree| {
+        let existing = |path: &str| {
+            parent_tree.and_then(|tree| tree.get_path(std::path::Path::new(path)).ok())
+        };
+        let old_entry = match old_path {
+            Some(old_path) => {
+                let entry = existing(old_path)
+                    .filter(|entry| entry.kind() == Some(ObjectType::Blob))
+                    .ok_or_else(|| {
+                        HttpResponse::NotFound()
+                            .body(format!("the file '{old_path}' does not exist"))
+                    })?;
+                Some(entry)
+            }
+            None => None,
+        };
+        if Some(new_path.as_str()) != old_path && existing(&new_path).is_some() {
+            return Err(HttpResponse::Conflict().body(format!("'{new_path}' already exists")));
+        }
+
+        let mut changes = Vec::new();
+        if let Some(old_path) = old_path {
+            changes.push((old_path.to_owned(), None));
+        }
+        if mode != Mode::Delete {
+            let original = old_entry
+                .as_ref()
+                .and_then(|entry| repo.find_blob(entry.id()).ok());
+            let content =
+                normalize_newlines(&form.content, original.as_ref().map(|blob| blob.content()));
+            let blob = repo.blob(content.as_bytes()).map_err(internal_error)?;
+            let mode = old_entry
+                .as_ref()
+                .map_or(FILEMODE_BLOB, |entry| entry.filemode());
+            changes.push((new_path.clone(), Some((blob, mode))));
+        }
+        Ok(changes)
+    })?;
+
+    let rev = target.replace('/', "%2F");
+    Ok(match mode {
+        Mode::Delete => {
+            // Emptied directories are gone, so go to the closest one left.
+            let mut dir = new_path.as_str();
+            loop {
+                dir = dir.rsplit_once('/').map_or("", |(parent, _)| parent);
+                if dir.is_empty() || tree.get_path(std::path::Path::new(dir)).is_ok() {
+                    break;
+                }
+            }
+            format!("tree/{rev}/{dir}")
+        }
+        Mode::Create | Mode::Edit => format!("blob/{rev}/{new_path}"),
+    })
+}
+
+/// Where a change made in the browser is committed, as submitted by the editor
+/// and upload forms.
+pub struct Target<'a> {
+    /// Branch the form was opened on.
+    pub branch: &'a str,
+    pub message: &'a str,
+    /// Commit to a new branch with this name instead, if not empty.
+    pub new_branch: &'a str,
+    /// Commit the form was opened at, empty in a repository without commits.
+    pub base: &'a str,
+}
+
+/// A path to set to a blob and mode, or to remove when `None`.
+pub type Change = (String, Option<(Oid, i32)>);
+
+/// Commits the changes returned by `changes`, which is given the tree of the
+/// parent commit, as `user` and returns the branch that was updated along with
+/// the new tree.
+///
+/// If the branch moved since `target.base`, the commit is only made when none
+/// of the `touched` paths changed in between; the branch itself is updated with
+/// a compare-and-swap so concurrent commits aren't lost either.
+pub fn commit_changes<'r, F>(
+    repo: &'r Repository,
+    user: &User,
+    target: &Target,
+    touched: &[&str],
+    changes: F,
+) -> Result<(String, Tree<'r>), HttpResponse>
+where
+    F: FnOnce(Option<&Tree>) -> Result<Vec<Change>, HttpResponse>,
+{
+    let bad_request = |body: String| HttpResponse::BadRequest().body(body);
+    let branch = target.branch;
+
+    let message = target.message.trim();
+    if message.is_empty() {
+        return Err(bad_request("a commit message is required".to_owned()));
+    }
+
+    let base = if target.base.is_empty() {
+        None
+    } else {
+        let commit = Oid::from_str(target.base)
+            .and_then(|oid| repo.find_commit(oid))
+            .map_err(|_| bad_request(format!("unknown commit '{}'", target.base)))?;
+        Some(commit)
+    };
+
+    // The branch to update, the commit it must still point at and the parent
+    // of the new commit.
+    let new_branch = target.new_branch.trim();
+    let (target, expected, parent) = if !new_branch.is_empty() {
+        if !git2::Reference::is_valid_name(&format!("refs/heads/{new_branch}")) {
+            return Err(bad_request(format!(
+                "'{new_branch}' is not a valid branch name"
+            )));
+        }
+        if repo.find_branch(new_branch, BranchType::Local).is_ok() {
+            return Err(
+                HttpResponse::Conflict().body(format!("branch '{new_branch}' already exists"))
+            );
+        }
+        (new_branch.to_owned(), None, base)
+    } else {
+        let tip = match repo.find_branch(branch, BranchType::Local) {
+            Ok(inner) => Some(inner.get().peel_to_commit().map_err(internal_error)?),
+            Err(_) if refs::is_empty(repo) => None,
+            Err(_) => {
+                return Err(bad_request(format!(
+                    "'{branch}' is not a branch, commit to a new branch instead"
+                )))
+            }
+        };
+        let moved = tip.as_ref().map(|tip| tip.id()) != base.as_ref().map(|base| base.id());
+        if moved {
+            // Someone else committed since the form was opened; go ahead only
+            // if they didn't touch the files being changed.
+            let entry = |commit: &Option<git2::Commit>, path: &str| {
+                commit
+                    .as_ref()
+                    .and_then(|commit| history::path_entry(commit, path))
+            };
+            if let Some(path) = touched
+                .iter()
+                .find(|path| entry(&base, path) != entry(&tip, path))
+            {
+                return Err(HttpResponse::Conflict().body(format!(
+                    "'{branch}' has changed '{path}' since you started editing; \
+                     reload the page to start from the latest version"
+                )));
+            }
+        }
+        let expected = tip.as_ref().map(|tip| tip.id());
+        (branch.to_owned(), expected, tip)
+    };
+
+    let parent_tree = parent
+        .as_ref()
+        .map(|parent| parent.tree())
+        .transpose()
+        .map_err(internal_error)?;
+    let mut tree = parent_tree.as_ref().map(Tree::id);
+    for (path, entry) in changes(parent_tree.as_ref())? {
+        tree = update_tree(repo, tree, &path, entry).map_err(internal_error)?;
+    }
+    let tree = match tree {
+        Some(tree) => repo.find_tree(tree),
+        None => repo
+            .treebuilder(None)
+            .and_then(|builder| builder.write())
+            .and_then(|oid| repo.find_tree(oid)),
+    }
+    .map_err(internal_error)?;
+    if parent_tree.as_ref().map(Tree::id) == Some(tree.id()) {
+        return Err(bad_request("there are no changes to commit".to_owned()));
+    }
+
+    let signature = Signature::now(&user.username, &user.email).map_err(internal_error)?;
+    let parents: Vec<&git2::Commit> = parent.iter().collect();
+    let oid = repo
+        .commit(None, &signature, &signature, message, &tree, &parents)
+        .map_err(internal_error)?;
+
+    let reference = format!("refs/heads/{target}");
+    let log_message = format!("commit: {}", message.lines().next().unwrap_or_default());
+    let updated = match expected {
+        Some(expected) => repo.reference_matching(&reference, oid, true, expected, &log_message),
+        None => repo.reference(&reference, oid, false, &log_message),
+    };
+    if updated.is_err() {
+        return Err(HttpResponse::Conflict().body(format!(
+            "'{target}' moved while the commit was being made; please try again"
+        )));
+    }
+    Ok((target, tree))
+}
+
+fn internal_error(error: git2::Error) -> HttpResponse {
+    HttpResponse::InternalServerError().body(error.message().to_owned())
+}
+
+/// A repository-relative file path without empty, `.`, `..` or `.git`
+/// components or control characters.
+pub fn clean_path(path: &str) -> Option<String> {
+    let parts: Vec<&str> = path.trim().trim_matches('/').split('/').collect();
+    let valid = parts.iter().all(|part| {
+        !part.is_empty()
+            && *part != "."
+            && *part != ".."
+            && !part.eq_ignore_ascii_case(".git")
+            && !part.contains(char::is_control)
+    });
+    valid.then(|| parts.join("/"))
+}
+
+/// Browsers submit textareas with CRLF line endings; keep them only when the
+/// file used them before.
+fn normalize_newlines(content: &str, original: Option<&[u8]>) -> String {
+    let crlf = original.is_some_and(|original| original.windows(2).any(|pair| pair == b"\r\n"));
+    if crlf {
+        content.to_owned()
+    } else {
+        content.replace("\r\n", "\n")
+    }
+}
+
+fn is_markdown(path: &str) -> bool {
+    path.rsplit_once('.').is_some_and(|(_, extension)| {
+        markup::MARKDOWN_EXTENSIONS
+            .iter()
+            .any(|inner| inner.eq_ignore_ascii_case(extension))
+    })
+}
+
+/// Writes `tree` with the entry at `path` set to `entry`, or removed when it is
+/// `None`. Directories are created as needed and removed once empty, in which
+/// case `None` is returned for an empty result.
+fn update_tree(
+    repo: &Repository,
+    tree: Option<Oid>,
+    path: &str,
+    entry: Option<(Oid, i32)>,
+) -> Result<Option<Oid>, git2::Error> {
+    let tree = tree.map(|tree| repo.find_tree(tree)).transpose()?;
+    let mut builder = repo.treebuilder(tree.as_ref())?;
+    let (name, rest) = match path.split_once('/') {
+        Some((name, rest)) => (name, Some(rest)),
+        None => (path, None),
+    };
+    match rest {
+        None => match entry {
+            Some((oid, mode)) => {
+                builder.insert(name, oid, mode)?;
+            }
+            None => builder.remove(name)?,
+        },
+        Some(rest) => {
+            let subtree = match builder.get(name)? {
+                Some(existing) if existing.kind() == Some(ObjectType::Tree) => Some(existing.id()),
+                Some(_) => {
+                    return Err(git2::Error::from_str(&format!(
+                        "'{name}' is not a directory"
+                    )))
+                }
+                None => None,
+            };
+            match update_tree(repo, subtree, rest, entry)? {
+                Some(oid) => {
+                    builder.insert(name, oid, FILEMODE_TREE)?;
+                }
+                None if subtree.is_some() => builder.remove(name)?,
+                None => {}
+            }
+        }
+    }
+    if builder.is_empty() {
+        return Ok(None);
+    }
+    builder.write().map(Some)
+}
+
+#[derive(Debug, serde::Deserialize)]
+pub struct PreviewForm {
+    content: String,
+}
+
+/// Renders markdown for the editor's preview tab.
+pub async fn preview(form: web::Form<PreviewForm>) -> impl Responder {
+    let html = markdown::to_html_with_options(&form.content, &markdown::Options::gfm())
+        .unwrap_or_default();
+    HttpResponse::Ok().content_type("text/html").body(html)
+}
@@ -0,0 +1,150 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    path::Path,
+    sync::{Arc, Mutex, OnceLock},
+};
+
+use git2::Oid;
+use syntect::{
+    easy::HighlightLines,
+    highlighting::{Theme, ThemeSet},
+    html::{styled_line_to_highlighted_html, IncludeBackground},
+    parsing::{SyntaxReference, SyntaxSet},
+    util::LinesWithEndings,
+};
+
+use crate::attributes::Attributes;
+
+/// Blobs larger than this are shown as plain text.
+pub const MAX_HIGHLIGHT_BYTES: usize = 512 * 1024;
+const MAX_CACHE_ENTRIES: usize = 512;
+const THEME: &str = "base16-ocean.dark";
+
+/// Highlighted lines keyed by blob and syntax name, so a blob shown under
+/// several paths or in several diffs is only parsed once per language.
+type Key = (Oid, String);
+
+#[derive(Default)]
+struct Cache {
+    entries: HashMap<Key, Arc<Vec<String>>>,
+    order: VecDeque<Key>,
+}
+
+impl Cache {
+    fn insert(&mut self, key: Key, lines: Arc<Vec<String>>) {
+        if self.entries.insert(key.clone(), lines).is_none() {
+            self.order.push_back(key);
+        }
+        while self.order.len() > MAX_CACHE_ENTRIES {
+            if let Some(oldest) = self.order.pop_front() {
+                self.entries.remove(&oldest);
+            }
+        }
+    }
+}
+
+fn syntaxes() -> &'static SyntaxSet {
+    static SYNTAXES: OnceLock<SyntaxSet> = OnceLock::new();
+    SYNTAXES.get_or_init(SyntaxSet::load_defaults_newlines)
+}
+
+fn theme() -> &'static Theme {
+    static THEME_: OnceLock<Theme> = OnceLock::new();
+    THEME_.get_or_init(|| {
+        let mut themes = ThemeSet::load_defaults();
+        themes.themes.remove(THEME).unwrap_or_default()
+    })
+}
+
+fn cache() -> &'static Mutex<Cache> {
+    static CACHE: OnceLock<Mutex<Cache>> = OnceLock::new();
+    CACHE.get_or_init(Mutex::default)
+}
+
+/// Picks a grammar for `path`: a `linguist-language` attribute wins, then the
+/// file name or extension, then the first line (shebangs, modelines).
+fn syntax_for(
+    path: &str,
+    content: &str,
+    attributes: &Attributes,
+) -> Option<&'static SyntaxReference> {
+    let syntaxes = syntaxes();
+    if let Some(language) = attributes.get(path, "linguist-language") {
+        if let Some(syntax) = find_language(&language.replace('-', " ")) {
+            return Some(syntax);
+        }
+    }
+
+    let path = Path::new(path);
+    let file_name = path.file_name().and_then(|name| name.to_str());
+    let extension = path.extension().and_then(|extension| extension.to_str());
+    file_name
+        .and_then(|name| syntaxes.find_syntax_by_extension(name))
+        .or_else(|| extension.and_then(|extension| syntaxes.find_syntax_by_extension(extension)))
+        .or_else(|| {
+            let first_line = content.lines().next()?;
+            syntaxes.find_syntax_by_first_line(first_line)
+        })
+        .filter(|syntax| syntax.name != "Plain Text")
+}
+
+/// Highlights the blob `oid` with the grammar chosen for `path`, one HTML
+/// fragment per line as split by [`str::lines`].
+///
+/// Returns `None` for binary, oversized or unrecognised files, which are shown
+/// as plain text.
+pub fn lines(
+    oid: Oid,
+    content: &[u8],
+    path: &str,
+    attributes: &Attributes,
+) -> Option<Arc<Vec<String>>> {
+    if content.len() > MAX_HIGHLIGHT_BYTES || content.contains(&0) {
+        return None;
+    }
+    let text = std::str::from_utf8(content).ok()?;
+    let syntax = syntax_for(path, text, attributes)?;
+
+    let key = (oid, syntax.name.clone());
+    if let Some(lines) = cache().lock().ok()?.entries.get(&key) {
+        return Some(lines.clone());
+    }
+
+    let lines = Arc::new(highlight(text, syntax)?);
+    if let Ok(mut cache) = cache().lock() {
+        cache.insert(key, lines.clone());
+    }
+    Some(lines)
+}
+
+/// Highlights a snippet that isn't stored as a blob, such as a notebook cell,
+/// using the grammar named or identified by `language`.
+pub fn snippet(text: &str, language: &str) -> Option<Vec<String>> {
+    if text.len() > MAX_HIGHLIGHT_BYTES {
+        return None;
+    }
+    highlight(text, find_language(language)?)
+}
+
+fn find_language(language: &str) -> Option<&'static SyntaxReference> {
+    let syntaxes = syntaxes();
+    syntaxes
+        .syntaxes()
+        .iter()
+        .find(|syntax| syntax.name.eq_ignore_ascii_case(language))
+        .or_else(|| syntaxes.find_syntax_by_token(language))
+}
+
+fn highlight(text: &str, syntax: &SyntaxReference) -> Option<Vec<String>> {
+    let mut highlighter = HighlightLines::new(syntax, theme());
+    let mut lines = Vec::new();
+    for line in LinesWithEndings::from(text) {
+        let regions = highlighter.highlight_line(line, syntaxes()).ok()?;
+        let regions: Vec<_> = regions
+            .into_iter()
+            .map(|(style, text)| (style, text.trim_end_matches(['\n', '\r'])))
+            .collect();
+        lines.push(styled_line_to_highlighted_html(&regions, IncludeBackground::No).ok()?);
+    }
+    Some(lines)
+}
@@ -0,0 +1,309 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    path::Path,
+    sync::{Arc, Mutex, OnceLock},
+};
+
+use git2::{Commit, DiffFindOptions, Oid, Repository, Sort, Tree};
+use serde::{Deserialize, Serialize};
+
+pub const DEFAULT_PAGE_SIZE: usize = 20;
+pub const MAX_PAGE_SIZE: usize = 100;
+/// Commits visited when looking for the last commits of a directory's entries;
+/// entries not resolved by then are shown without one.
+const MAX_LAST_COMMIT_WALK: usize = 10_000;
+const MAX_LAST_COMMIT_CACHE_ENTRIES: usize = 256;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Order {
+    #[default]
+    Time,
+    #[serde(rename = "topo")]
+    Topological,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Merges {
+    #[default]
+    Include,
+    Only,
+    Exclude,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Filter {
+    pub author: Option<String>,
+    pub committer: Option<String>,
+    pub since: Option<i64>,
+    pub until: Option<i64>,
+    pub message: Option<String>,
+    pub merges: Merges,
+    pub path: Option<String>,
+}
+
+impl Filter {
+    fn matches(&self, commit: &Commit) -> bool {
+        let is_merge = commit.parent_count() > 1;
+        match self.merges {
+            Merges::Only if !is_merge => return false,
+            Merges::Exclude if is_merge => return false,
+            _ => {}
+        }
+        if let Some(author) = self.author.as_deref() {
+            if !signature_contains(&commit.author(), author) {
+                return false;
+            }
+        }
+        if let Some(committer) = self.committer.as_deref() {
+            if !signature_contains(&commit.committer(), committer) {
+                return false;
+            }
+        }
+        let seconds = commit.time().seconds();
+        if self.since.is_some_and(|since| seconds < since) {
+            return false;
+        }
+        if self.until.is_some_and(|until| seconds > until) {
+            return false;
+        }
+        if let Some(message) = self.message.as_deref() {
+            let haystack = commit.message().unwrap_or_default().to_lowercase();
+            if !haystack.contains(&message.to_lowercase()) {
+                return false;
+            }
+        }
+        true
+    }
+}
+
+fn signature_contains(signature: &git2::Signature, needle: &str) -> bool {
+    let needle = needle.to_lowercase();
+    let name = signature.name().unwrap_or_default().to_lowercase();
+    let email = signature.email().unwrap_or_default().to_lowercase();
+    name.contains(&needle) || email.contains(&needle)
+}
+
+pub struct Page<'r> {
+    pub commits: Vec<Commit<'r>>,
+    /// Id of the last commit on this page when more commits follow it.
+    pub next: Option<Oid>,
+}
+
+/// Walks the history reachable from `start` and returns up to `per_page`
+/// commits matching `filter`, beginning after the commit `after`.
+///
+/// When `filter.path` is set only commits changing that path are kept, and the
+/// path is followed across renames like `git log --follow`. The cursor is
+/// resolved by re-walking from `start`, so rename tracking stays consistent
+/// between pages.
+pub fn walk<'r>(
+    repo: &'r Repository,
+    start: Oid,
+    order: Order,
+    filter: &Filter,
+    after: Option<Oid>,
+    per_page: usize,
+) -> Result<Page<'r>, git2::Error> {
+    let mut revwalk = repo.revwalk()?;
+    revwalk.set_sorting(match order {
+        Order::Time => Sort::TIME,
+        Order::Topological => Sort::TOPOLOGICAL | Sort::TIME,
+    })?;
+    revwalk.push(start)?;
+
+    let mut path = filter.path.clone();
+    let mut skipping = after.is_some();
+    let mut commits = Vec::new();
+    for oid in revwalk {
+        let oid = oid?;
+        let commit = repo.find_commit(oid)?;
+
+        if let Some(current) = path.as_deref() {
+            match touches(repo, &commit, current) {
+                Touch::No => continue,
+                Touch::Yes => {}
+                Touch::Renamed(old_path) => path = Some(old_path),
+            }
+        }
+
+        if skipping {
+            skipping = Some(oid) != after;
+            continue;
+        }
+
+        if !filter.matches(&commit) {
+            continue;
+        }
+
+        if commits.len() == per_page {
+            let next = commits.last().map(Commit::id);
+            return Ok(Page { commits, next });
+        }
+        commits.push(commit);
+    }
+
+    Ok(Page {
+        commits,
+        next: None,
+    })
+}
+
+enum Touch {
+    No,
+    Yes,
+    Renamed(String),
+}
+
+/// A commit touches `path` when its entry there differs from the entry in
+/// every parent, so merges that took one side unchanged are skipped.
+fn touches(repo: &Repository, commit: &Commit, path: &str) -> Touch {
+    let entry = path_entry(commit, path);
+    let mut parents = commit.parents();
+    let Some(first_parent) = parents.next() else {
+        return if entry.is_some() {
+            Touch::Yes
+        } else {
+            Touch::No
+        };
+    };
+    let first_entry = path_entry(&first_parent, path);
+    if first_entry == entry || parents.any(|parent| path_entry(&parent, path) == entry) {
+        return Touch::No;
+    }
+    if entry.is_some() && first_entry.is_none() && commit.parent_count() == 1 {
+        if let Some(old_path) = find_rename_source(repo, &first_parent, commit, path) {
+            return Touch::Renamed(old_path);
+        }
+    }
+    Touch::Yes
+}
+
+pub fn path_entry(commit: &Commit, path: &str) -> Option<(Oid, i32)> {
+    let entry = commit.tree().ok()?.get_path(Path::new(path)).ok()?;
+    Some((entry.id(), entry.filemode()))
+}
+
+fn find_rename_source(
+    repo: &Repository,
+    parent: &Commit,
+    commit: &Commit,
+    path: &str,
+) -> Option<String> {
+    let old_tree = parent.tree().ok()?;
+    let new_tree = commit.tree().ok()?;
+    let mut diff = repo
+        .diff_tree_to_tree(Some(&old_tree), Some(&new_tree), None)
+        .ok()?;
+    diff.find_similar(Some(DiffFindOptions::new().renames(true)))
+        .ok()?;
+    let delta = diff.deltas().find(|delta| {
+        delta.status() == git2::Delta::Renamed && delta.new_file().path() == Some(Path::new(path))
+    })?;
+    let old_path = delta.old_file().path()?;
+    Some(old_path.to_string_lossy().into_owned())
+}
+
+/// Last commits of a directory keyed by its tree and the commit the walk
+/// started from, so a listing is only computed once per revision.
+type Key = (Oid, Oid);
+
+#[derive(Default)]
+struct LastCommitCache {
+    entries: HashMap<Key, Arc<HashMap<String, Oid>>>,
+    order: VecDeque<Key>,
+}
+
+impl LastCommitCache {
+    fn insert(&mut self, key: Key, commits: Arc<HashMap<String, Oid>>) {
+        if self.entries.insert(key, commits).is_none() {
+            self.order.push_back(key);
+        }
+        while self.order.len() > MAX_LAST_COMMIT_CACHE_ENTRIES {
+            if let Some(oldest) = self.order.pop_front() {
+                self.entries.remove(&oldest);
+            }
+        }
+    }
+}
+
+fn last_commit_cache() -> &'static Mutex<LastCommitCache> {
+    static CACHE: OnceLock<Mutex<LastCommitCache>> = OnceLock::new();
+    CACHE.get_or_init(Mutex::default)
+}
+
+/// Finds, for every entry of `tree` (the directory `dir` of `start`), the most
+/// recent commit that gave the entry its current content, in a single history
+/// walk.
+///
+/// Like [`touches`], a commit is only credited when the entry differs from
+/// every parent, and commits leaving the whole directory as one of their
+/// parents had it are skipped without looking at the entries.
+pub fn last_commits(
+    repo: &Repository,
+    start: &Commit,
+    dir: &str,
+    tree: &Tree,
+) -> Result<Arc<HashMap<String, Oid>>, git2::Error> {
+    let key = (start.id(), tree.id());
+    if let Some(commits) = last_commit_cache()
+        .lock()
+        .ok()
+        .and_then(|cache| cache.entries.get(&key).cloned())
+    {
+        return Ok(commits);
+    }
+
+    let mut pending: HashMap<String, Oid> = tree
+        .iter()
+        .filter_map(|entry| Some((entry.name()?.to_owned(), entry.id())))
+        .collect();
+    let mut found = HashMap::new();
+
+    let mut revwalk = repo.revwalk()?;
+    revwalk.set_sorting(Sort::TIME)?;
+    revwalk.push(start.id())?;
+    for oid in revwalk.take(MAX_LAST_COMMIT_WALK) {
+        let commit = repo.find_commit(oid?)?;
+        let Some(current) = dir_tree(repo, &commit, dir) else {
+            continue;
+        };
+        let parents: Vec<Tree> = commit
+            .parents()
+            .filter_map(|parent| dir_tree(repo, &parent, dir))
+            .collect();
+        if parents.iter().any(|parent| parent.id() == current.id()) {
+            continue;
+        }
+
+        pending.retain(|name, id| {
+            let entry_id = |tree: &Tree| tree.get_name(name).map(|entry| entry.id());
+            if entry_id(&current) != Some(*id)
+                || parents.iter().any(|parent| entry_id(parent) == Some(*id))
+            {
+                return true;
+            }
+            found.insert(name.clone(), commit.id());
+            false
+        });
+        if pending.is_empty() {
+            break;
+        }
+    }
+
+    let found = Arc::new(found);
+    if let Ok(mut cache) = last_commit_cache().lock() {
+        cache.insert(key, found.clone());
+    }
+    Ok(found)
+}
+
+fn dir_tree<'r>(repo: &'r Repository, commit: &Commit, dir: &str) -> Option<Tree<'r>> {
+    let tree = commit.tree().ok()?;
+    if dir.is_empty() {
+        return repo.find_tree(tree.id()).ok();
+    }
+    let entry = tree.get_path(Path::new(dir)).ok()?;
+    repo.find_tree(entry.id()).ok()
+}
@@ -7,7 +7,8 @@ use serde::{Deserialize, Serialize};
 use time::OffsetDateTime;
 
 use crate::{
-    model::{Issue, Repository, User},
+    model::{Event, Issue, Repository, Role, User},
+    repository::Context,
     time_utils, State,
 };
 
@@ -60,6 +61,9 @@ struct IssueTemplate<'a> {
     issue: &'a Issue,
     user: &'a User,
     comments: &'a [Comment],
+    /// Whether the signed in user may close and reopen the issue: its author
+    /// or anyone with the triage role.
+    can_close: bool,
 }
 
 struct Comment {
@@ -90,9 +94,14 @@ pub async fn view(
         .find_repository(user.as_ref(), &name)
         .await
         .unwrap();
+    let can_triage = repo.can(&user, &identity, Role::Triage) && !repo.archived;
     let Some(mut issue) = repo.issues.iter_mut().find(|issue| issue.index == index) else {
         todo!()
     };
+    let can_close = can_triage
+        || identity
+            .as_ref()
+            .is_some_and(|identity| identity._id == issue.user_id && !repo.archived);
     let mut comments = Vec::new();
     for comment in &issue.comments {
         let user = state
@@ -137,6 +146,7 @@ pub async fn view(
         issue,
         user: &user,
         comments: &comments,
+        can_close,
     }
     .to_response()
 }
@@ -154,16 +164,13 @@ pub async fn add_comment(
 ) -> impl Responder {
     let (username, name, issue_id) = path.into_inner();
 
-    let identity = match identity {
-        Some(identity) => match identity.id() {
-            Ok(id) => state.database.find_user_from_id(&id).await,
-            Err(_) => todo!(),
-        },
-        None => {
-            return HttpResponse::SeeOther()
-                .insert_header(("Location", format!("/@{username}/{name}/issues/{issue_id}")))
-                .finish()
-        }
+    let context = match Context::load(&state, &username, &name, identity).await {
+        Ok(context) => context,
+        Err(response) => return response,
+    };
+    let identity = match context.require(Role::Read) {
+        Ok(identity) => identity,
+        Err(response) => return response,
     };
 
     if form.body.is_empty() {
@@ -172,13 +179,10 @@ pub async fn add_comment(
             .finish();
     }
 
-    let user = state.database.find_user(&username).await;
-
-    let repo = state
-        .database
-        .find_repository(user.as_ref(), &name)
-        .await
-        .unwrap();
+    let repo = &context.repository;
+    if repo.archived {
+        return HttpResponse::Forbidden().body("This repository is archived and read-only");
+    }
     let Some(issue) = repo.issues.iter().find(|issue| issue.index == issue_id) else {
         todo!()
     };
@@ -200,7 +204,7 @@ pub async fn add_comment(
                     "issues.$.comments": {
                         "_id": ObjectId::new(),
                         "index": index,
-                        "user_id": identity.unwrap()._id,
+                        "user_id": identity._id,
                         "body": &form.body,
                         "created_at": unix_timestamp,
                     }
@@ -251,13 +255,14 @@ pub async fn new(
         None => None,
     };
 
-    let repo = {
-        let user = state.database.find_user(&username).await;
-        state
-            .database
-            .find_repository(user.as_ref(), &name)
-            .await
-            .unwrap()
+    let owner = state.database.find_user(&username).await;
+    let Some(repo) = state
+        .database
+        .find_repository(owner.as_ref(), &name)
+        .await
+        .filter(|repo| repo.can(&owner, &identity, Role::Read))
+    else {
+        return HttpResponse::NotFound().finish();
     };
 
     match *req.method() {
@@ -273,6 +278,9 @@ pub async fn new(
                     .insert_header(("Location", "/login"))
                     .finish();
             };
+            if repo.archived {
+                return HttpResponse::Forbidden().body("This repository is archived and read-only");
+            }
 
             let form = form.unwrap();
 
@@ -325,3 +333,65 @@ pub async fn new(
         _ => unimplemented!(),
     }
 }
+
+#[derive(Debug, Deserialize)]
+pub struct StatusForm {
+    open: bool,
+}
+
+/// Closes or reopens an issue, which its author and anyone with the triage
+/// role may do.
+pub async fn set_status(
+    path: web::Path<(String, String, i64)>,
+    form: web::Form<StatusForm>,
+    state: web::Data<State>,
+    identity: Option<Identity>,
+) -> impl Responder {
+    let (username, name, index) = path.into_inner();
+    let context = match Context::load(&state, &username, &name, identity).await {
+        Ok(context) => context,
+        Err(response) => return response,
+    };
+    let identity = match context.require(Role::Read) {
+        Ok(identity) => identity,
+        Err(response) => return response,
+    };
+    let repo = &context.repository;
+    if repo.archived {
+        return HttpResponse::Forbidden().body("This repository is archived and read-only");
+    }
+    let Some(issue) = repo.issues.iter().find(|issue| issue.index == index) else {
+        return HttpResponse::NotFound().finish();
+    };
+    if issue.user_id != identity._id && !context.can(Role::Triage) {
+        return HttpResponse::Forbidden().body("Unauthorized");
+    }
+
+    let status: i32 = if form.open { 0 } else { 1 };
+    let now = OffsetDateTime::now_utc().unix_timestamp();
+    let repositories = state.db.collection::<Repository>("repositories");
+    let result = repositories
+        .update_one(
+            bson::doc! { "_id": repo._id, "issues.index": index },
+            bson::doc! { "$set": {
+                "issues.$.status": status,
+                "issues.$.updated_at": now,
+            } },
+            None,
+        )
+        .await;
+    debug_assert!(result.is_ok());
+
+    let event = if form.open {
+        Event::IssueReopen
+    } else {
+        Event::IssueClose
+    };
+    state
+        .database
+        .add_user_log(identity, event, Some(format!("{name}:{index}")))
+        .await;
+    HttpResponse::SeeOther()
+        .insert_header(("Location", format!("/@{username}/{name}/issues/{index}")))
+        .finish()
+}
@@ -0,0 +1,14 @@
+//! The parts of gecko shared by the web server and `gecko-admin`: the data
+//! model, the database and the repositories on disk.
+
+pub mod credentials;
+pub mod database;
+pub mod disk;
+pub mod migrations;
+pub mod model;
+pub mod time_utils;
+pub mod validation;
+
+/// Where the database is unless told otherwise.
+pub const DEFAULT_DATABASE_URI: &str = "mongodb://localhost:27017";
+pub const DATABASE_NAME: &str = "gecko";
@@ -1,12 +1,24 @@
-mod database;
+mod access;
+mod admin;
+mod attributes;
+mod branches;
+mod collaborators;
 mod diff;
+mod editor;
+mod highlight;
+mod history;
 mod issues;
-mod model;
+mod markup;
+mod organizations;
+mod refs;
+mod render;
 mod repository;
-mod time_utils;
+mod settings;
+mod submodules;
+mod upload;
 mod user;
 
-use crate::model::{Repository, User};
+use crate::model::{Repository, Role, User};
 use actix_files::Files;
 use actix_identity::{Identity, IdentityMiddleware};
 use actix_session::{config::PersistentSession, storage::CookieSessionStore, SessionMiddleware};
@@ -18,17 +30,16 @@ use actix_web::{
 };
 use askama::Template;
 use askama_actix::TemplateToResponse;
-use bson::oid::ObjectId;
 use database::Database;
 use futures::TryStreamExt;
+use gecko::{credentials, database, disk, model, time_utils, validation};
 use mongodb::Client;
 
-const DATABASE_NAME: &str = "gecko";
-
 #[derive(Clone)]
 pub struct State {
     pub db: mongodb::Database,
     pub database: Database,
+    pub upload: upload::Pending,
 }
 
 #[derive(Clone, serde::Deserialize)]
@@ -72,7 +83,8 @@ async fn index(state: web::Data<State>, identity: Option<Identity>) -> Result<im
                                 "description": "$description",
                                 "visibility": "$visibility",
                                 "created_at": "$created_at",
-                                "updated_at": "$updated_at"
+                                "updated_at": "$updated_at",
+                                "collaborators": "$collaborators"
                             }
                         },
                     ],
@@ -103,25 +115,35 @@ async fn index(state: web::Data<State>, identity: Option<Identity>) -> Result<im
                 let created_at = inner.get_i64("created_at");
                 let updated_at = inner.get_i64("updated_at");
                 Repository {
-                    _id: ObjectId::new(),
-                    user_id: ObjectId::default(),
+                    _id: inner.get_object_id("_id").unwrap(),
+                    user_id: user._id,
                     name: name.unwrap().to_string(),
                     description: description.unwrap().to_string(),
                     visibility: visibility.unwrap().to_string(),
                     created_at: created_at.unwrap(),
                     updated_at: updated_at.unwrap(),
                     issues: vec![],
+                    default_branch: String::new(),
+                    protected_branches: vec![],
+                    website: String::new(),
+                    topics: vec![],
+                    archived: false,
+                    collaborators: inner
+                        .get_array("collaborators")
+                        .ok()
+                        .and_then(|collaborators| {
+                            bson::from_bson(bson::Bson::Array(collaborators.clone())).ok()
+                        })
+                        .unwrap_or_default(),
                 }
             })
             .collect();
 
-        let repositories: Vec<_> = match identity.as_ref() {
-            Some(identity) if identity._id == user._id => repositories.into_iter().collect(),
-            _ => repositories
-                .into_iter()
-                .filter(|inner| inner.visibility == "public")
-                .collect(),
-        };
+        let owner = Some(user);
+        let repositories: Vec<_> = repositories
+            .into_iter()
+            .filter(|inner| inner.can(&owner, &identity, Role::Read))
+            .collect();
 
         users.push(User_ {
             username: username.to_owned(),
@@ -149,18 +171,23 @@ async fn main() -> std::io::Result<()> {
 
     let secret_key = Key::generate();
 
-    let client = Client::with_uri_str("mongodb://localhost:27017")
+    let client = Client::with_uri_str(gecko::DEFAULT_DATABASE_URI)
         .await
         .unwrap();
 
-    let database = Database::new(&client, DATABASE_NAME);
+    let database = Database::new(&client, gecko::DATABASE_NAME);
+    if let Err(error) = database.create_indexes().await {
+        eprintln!("usernames and emails are not unique, see `gecko-admin migrate`: {error}");
+    }
     let state = State {
-        db: client.database(DATABASE_NAME),
+        db: client.database(gecko::DATABASE_NAME),
         database,
+        upload: upload::Pending::default(),
     };
 
     HttpServer::new(move || {
         App::new()
+            .wrap(access::SignOutSuspended)
             .wrap(IdentityMiddleware::default())
             .wrap(
                 SessionMiddleware::builder(CookieSessionStore::default(), secret_key.clone())
@@ -187,35 +214,164 @@ async fn main() -> std::io::Result<()> {
                     .route(web::get().to(user::new))
                     .route(web::post().to(user::new)),
             )
+            .service(
+                web::scope("/invitations")
+                    .default_service(web::get().to(collaborators::invitations))
+                    .route("/accept", web::post().to(collaborators::accept))
+                    .route("/decline", web::post().to(collaborators::decline)),
+            )
+            .service(
+                web::scope("/organizations")
+                    .service(
+                        web::resource("/new")
+                            .route(web::get().to(organizations::new))
+                            .route(web::post().to(organizations::new)),
+                    )
+                    .service(
+                        web::scope("/{name}/settings")
+                            .default_service(web::get().to(organizations::settings))
+                            .route("/update", web::post().to(organizations::update))
+                            .route("/members/add", web::post().to(organizations::add_member))
+                            .route(
+                                "/members/role",
+                                web::post().to(organizations::update_member),
+                            )
+                            .route(
+                                "/members/remove",
+                                web::post().to(organizations::remove_member),
+                            )
+                            .route("/teams/new", web::post().to(organizations::new_team))
+                            .route("/teams/delete", web::post().to(organizations::delete_team))
+                            .route(
+                                "/teams/members/add",
+                                web::post().to(organizations::add_team_member),
+                            )
+                            .route(
+                                "/teams/members/remove",
+                                web::post().to(organizations::remove_team_member),
+                            )
+                            .route(
+                                "/teams/repositories/grant",
+                                web::post().to(organizations::grant),
+                            )
+                            .route(
+                                "/teams/repositories/revoke",
+                                web::post().to(organizations::revoke),
+                            ),
+                    ),
+            )
+            .service(
+                web::scope("/admin")
+                    .default_service(web::get().to(admin::index))
+                    .route("/registration", web::post().to(admin::registration))
+                    .route("/invites/new", web::post().to(admin::new_invite))
+                    .route("/invites/delete", web::post().to(admin::delete_invite))
+                    .route("/users", web::get().to(admin::users))
+                    .route("/users/{username}/suspend", web::post().to(admin::suspend))
+                    .route("/users/{username}/admin", web::post().to(admin::promote))
+                    .route(
+                        "/users/{username}/password",
+                        web::post().to(admin::reset_password),
+                    )
+                    .route("/users/{username}/delete", web::post().to(admin::delete))
+                    .route("/repositories", web::get().to(admin::repositories))
+                    .route("/log", web::get().to(admin::log)),
+            )
             .service(user::logout)
             .service(user::index)
             .service(index)
-            .service(repository::delete)
             .service(
                 web::scope("/settings")
                     .default_service(web::get().to(user::settings))
                     .route("/update", web::post().to(user::update))
                     .route("/password", web::get().to(user::password))
                     .route("/update_password", web::post().to(user::update_password))
+                    .route("/diff", web::post().to(user::update_diff_layout))
                     .route("/log", web::get().to(user::log)),
             )
             .service(
                 web::scope("/@{username}")
+                    .wrap(access::RepositoryAccess)
                     .service(repository::index)
                     .service(
                         web::scope("/{name}")
-                            .route("/branches", web::get().to(repository::branches))
+                            .service(
+                                web::scope("/settings")
+                                    .default_service(web::get().to(settings::index))
+                                    .route(
+                                        "/default_branch",
+                                        web::post().to(settings::default_branch),
+                                    )
+                                    .route("/update", web::post().to(settings::update))
+                                    .route("/rename", web::post().to(settings::rename))
+                                    .route("/transfer", web::post().to(settings::transfer))
+                                    .route("/archive", web::post().to(settings::archive))
+                                    .route("/delete", web::post().to(settings::delete))
+                                    .route(
+                                        "/collaborators/invite",
+                                        web::post().to(collaborators::invite),
+                                    )
+                                    .route(
+                                        "/collaborators/role",
+                                        web::post().to(collaborators::update),
+                                    )
+                                    .route(
+                                        "/collaborators/remove",
+                                        web::post().to(collaborators::remove),
+                                    ),
+                            )
+                            .service(
+                                web::scope("/branches")
+                                    .default_service(web::get().to(branches::index))
+                                    .route("/new", web::post().to(branches::create))
+                                    .route("/delete", web::post().to(branches::delete))
+                                    .route("/restore", web::post().to(branches::restore))
+                                    .route("/protect", web::post().to(branches::protect)),
+                            )
+                            .route(
+                                "/commit/{id}.{format:patch|diff}",
+                                web::get().to(repository::diff_download),
+                            )
                             .route("/commit/{id}", web::get().to(repository::diff))
+                            .route(
+                                "/compare/{spec:.*}.{format:patch|diff}",
+                                web::get().to(repository::compare_download),
+                            )
+                            .route("/compare/{spec:.*}", web::get().to(repository::compare))
                             .service(
                                 web::scope("/tree/{branch}")
                                     .default_service(web::get().to(repository::tree))
                                     .route("/{tail}*", web::get().to(repository::tree_)),
                             )
                             .route("/blob/{branch}/{tail}*", web::get().to(repository::tree_))
+                            .route("/raw/{branch}/{tail}*", web::get().to(repository::raw))
+                            .service(
+                                web::resource(["/new/{branch}", "/new/{branch}/{tail}*"])
+                                    .route(web::get().to(editor::create))
+                                    .route(web::post().to(editor::create)),
+                            )
+                            .service(
+                                web::resource("/edit/{branch}/{tail}*")
+                                    .route(web::get().to(editor::edit))
+                                    .route(web::post().to(editor::edit)),
+                            )
+                            .service(
+                                web::resource("/delete/{branch}/{tail}*")
+                                    .route(web::get().to(editor::delete))
+                                    .route(web::post().to(editor::delete)),
+                            )
+                            .route("/preview", web::post().to(editor::preview))
+                            .service(
+                                web::resource(["/upload/{branch}", "/upload/{branch}/{tail}*"])
+                                    .route(web::get().to(upload::index))
+                                    .route(web::post().to(upload::preview)),
+                            )
+                            .route("/upload", web::post().to(upload::commit))
                             .service(
                                 web::scope("/commits")
                                     .default_service(web::get().to(repository::commits))
-                                    .route("/{branch}", web::get().to(repository::commits)),
+                                    .route("/{branch}", web::get().to(repository::commits))
+                                    .route("/{branch}/{tail}*", web::get().to(repository::commits)),
                             )
                             .service(
                                 web::scope("/issues")
@@ -228,7 +384,8 @@ async fn main() -> std::io::Result<()> {
                                     .service(
                                         web::scope("/{issue_id}")
                                             .default_service(web::get().to(issues::view))
-                                            .route("/add", web::post().to(issues::add_comment)),
+                                            .route("/add", web::post().to(issues::add_comment))
+                                            .route("/status", web::post().to(issues::set_status)),
                                     ),
                             ),
                     ),
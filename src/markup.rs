@@ -0,0 +1,1067 @@
+use std::{collections::HashMap, path::Path, sync::OnceLock};
+
+use git2::{ObjectType, Repository, Tree};
+use regex::{Captures, Regex};
+
+use crate::highlight;
+
+/// Where a rendered document lives, for resolving its relative links.
+pub struct Location<'a> {
+    pub username: &'a str,
+    pub name: &'a str,
+    /// Branch or commit the document was read from.
+    pub rev: &'a str,
+    /// Directory of the document relative to the repository root, empty for
+    /// the root itself.
+    pub dir: &'a str,
+}
+
+pub struct Heading {
+    pub level: u8,
+    pub text: String,
+    pub anchor: String,
+}
+
+pub struct Readme {
+    pub file_name: String,
+    pub html: String,
+    pub toc: Vec<Heading>,
+}
+
+/// A lightweight markup language rendered to HTML for READMEs and docs.
+pub trait Markup: Sync {
+    fn name(&self) -> &'static str;
+
+    /// Handled extensions without the dot; an empty string matches files
+    /// without an extension.
+    fn extensions(&self) -> &'static [&'static str];
+
+    fn to_html(&self, source: &str) -> String;
+}
+
+pub const MARKDOWN_EXTENSIONS: &[&str] = &["md", "markdown", "mdown", "mkd"];
+pub const ASCIIDOC_EXTENSIONS: &[&str] = &["adoc", "asciidoc", "asc"];
+pub const ORG_EXTENSIONS: &[&str] = &["org"];
+pub const RST_EXTENSIONS: &[&str] = &["rst", "rest"];
+const PLAIN_TEXT_EXTENSIONS: &[&str] = &["", "txt"];
+
+/// Every supported format, in the order a README is picked when a directory
+/// has several.
+pub const MARKUPS: &[&dyn Markup] = &[&Markdown, &AsciiDoc, &Org, &RestructuredText, &PlainText];
+
+/// Finds and renders the README of `tree`, the directory at `location.dir` of
+/// the commit whose root tree is `root`. The same names are recognised at the
+/// root and in subdirectories.
+pub fn readme(repo: &Repository, root: &Tree, tree: &Tree, location: &Location) -> Option<Readme> {
+    let (_, markup, entry) = tree
+        .iter()
+        .filter(|entry| entry.kind() == Some(ObjectType::Blob))
+        .filter_map(|entry| {
+            let path = Path::new(entry.name()?);
+            let stem = path.file_stem()?.to_str()?;
+            if !stem.eq_ignore_ascii_case("readme") {
+                return None;
+            }
+            let extension = path
+                .extension()
+                .map_or(Some(""), |extension| extension.to_str())?;
+            let (priority, markup) = MARKUPS.iter().enumerate().find(|(_, markup)| {
+                markup
+                    .extensions()
+                    .iter()
+                    .any(|inner| inner.eq_ignore_ascii_case(extension))
+            })?;
+            Some((priority, *markup, entry))
+        })
+        .min_by_key(|(priority, _, _)| *priority)?;
+
+    let blob = repo.find_blob(entry.id()).ok()?;
+    let content = String::from_utf8_lossy(blob.content());
+    let (html, toc) = postprocess(&markup.to_html(&content), root, location);
+    Some(Readme {
+        file_name: entry.name()?.to_owned(),
+        html,
+        toc,
+    })
+}
+
+/// Points relative links of rendered HTML at the repository and gives every
+/// heading an anchor, returning the headings as a table of contents.
+pub fn postprocess(html: &str, root: &Tree, location: &Location) -> (String, Vec<Heading>) {
+    let html = rewrite_links(html, root, location);
+    add_anchors(&html)
+}
+
+/// Rewrites `href` attributes to blob or tree pages and `src` attributes to
+/// the raw endpoint, at the same revision and relative to the document's
+/// directory. Absolute URLs and fragments are left alone.
+fn rewrite_links(html: &str, root: &Tree, location: &Location) -> String {
+    const HREF: &str = " href=\"";
+    const SRC: &str = " src=\"";
+
+    let mut out = String::with_capacity(html.len());
+    let mut rest = html;
+    loop {
+        let href = rest.find(HREF).map(|index| (index, HREF, false));
+        let src = rest.find(SRC).map(|index| (index, SRC, true));
+        let Some((index, attribute, raw)) = href.into_iter().chain(src).min() else {
+            break;
+        };
+        let start = index + attribute.len();
+        out.push_str(&rest[..start]);
+        rest = &rest[start..];
+        let end = rest.find('"').unwrap_or(rest.len());
+        out.push_str(&resolve(&rest[..end], raw, root, location));
+        rest = &rest[end..];
+    }
+    out.push_str(rest);
+    out
+}
+
+fn resolve(url: &str, raw: bool, root: &Tree, location: &Location) -> String {
+    if url.is_empty() || url.starts_with('#') || url.starts_with("//") || has_scheme(url) {
+        return url.to_owned();
+    }
+    let split = url.find(['?', '#']).unwrap_or(url.len());
+    let (path, suffix) = url.split_at(split);
+    let path = join(location.dir, path);
+
+    let Location {
+        username,
+        name,
+        rev,
+        ..
+    } = location;
+    if raw {
+        return format!("/@{username}/{name}/raw/{rev}/{path}{suffix}");
+    }
+    if path.is_empty() {
+        return format!("/@{username}/{name}/tree/{rev}{suffix}");
+    }
+    let kind = root
+        .get_path(Path::new(&percent_decode(&path)))
+        .ok()
+        .and_then(|entry| entry.kind());
+    let page = if kind == Some(ObjectType::Tree) {
+        "tree"
+    } else {
+        "blob"
+    };
+    format!("/@{username}/{name}/{page}/{rev}/{path}{suffix}")
+}
+
+fn has_scheme(url: &str) -> bool {
+    url.split_once(':').is_some_and(|(scheme, _)| {
+        !scheme.is_empty()
+            && scheme
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || matches!(c, '+' | '-' | '.'))
+    })
+}
+
+/// Resolves `path` against `dir`; a leading slash starts from the repository
+/// root and `..` never climbs above it.
+fn join(dir: &str, path: &str) -> String {
+    let mut parts: Vec<&str> = if path.starts_with('/') {
+        Vec::new()
+    } else {
+        dir.split('/').filter(|part| !part.is_empty()).collect()
+    };
+    for part in path.split('/') {
+        match part {
+            "" | "." => {}
+            ".." => {
+                parts.pop();
+            }
+            part => parts.push(part),
+        }
+    }
+    parts.join("/")
+}
+
+fn percent_decode(input: &str) -> String {
+    let bytes = input.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut index = 0;
+    while index < bytes.len() {
+        let decoded = (bytes[index] == b'%')
+            .then(|| input.get(index + 1..index + 3))
+            .flatten()
+            .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+        match decoded {
+            Some(byte) => {
+                out.push(byte);
+                index += 3;
+            }
+            None => {
+                out.push(bytes[index]);
+                index += 1;
+            }
+        }
+    }
+    String::from_utf8_lossy(&out).into_owned()
+}
+
+/// Adds GitHub-style ids to `<h1>`..`<h6>` and a link to each, numbering
+/// repeated slugs.
+fn add_anchors(html: &str) -> (String, Vec<Heading>) {
+    let mut out = String::with_capacity(html.len());
+    let mut toc = Vec::new();
+    let mut seen: HashMap<String, usize> = HashMap::new();
+    let mut rest = html;
+    while let Some(index) = rest.find("<h") {
+        let level = rest.as_bytes().get(index + 2).copied().unwrap_or_default();
+        let close = format!("</h{}>", level as char);
+        let inner_end = rest[index..].find(&close).map(|end| index + end);
+        let (Some(inner_end), b'1'..=b'6', Some(b'>')) =
+            (inner_end, level, rest.as_bytes().get(index + 3))
+        else {
+            out.push_str(&rest[..index + 2]);
+            rest = &rest[index + 2..];
+            continue;
+        };
+        let inner = &rest[index + 4..inner_end];
+        let text = text_content(inner);
+        let mut anchor = slug(&text);
+        let count = seen.entry(anchor.clone()).or_default();
+        if *count > 0 {
+            anchor = format!("{anchor}-{count}");
+        }
+        *count += 1;
+
+        out.push_str(&rest[..index]);
+        out.push_str(&format!(
+            "<h{level} id=\"{anchor}\"><a class=\"anchor\" href=\"#{anchor}\">#</a>{inner}",
+            level = level as char,
+        ));
+        toc.push(Heading {
+            level: level - b'0',
+            text,
+            anchor,
+        });
+        rest = &rest[inner_end..];
+    }
+    out.push_str(rest);
+    (out, toc)
+}
+
+/// Text of an HTML fragment with tags removed and the entities the markdown
+/// renderer emits decoded.
+fn text_content(html: &str) -> String {
+    let mut text = String::new();
+    let mut in_tag = false;
+    for c in html.chars() {
+        match c {
+            '<' => in_tag = true,
+            '>' => in_tag = false,
+            c if !in_tag => text.push(c),
+            _ => {}
+        }
+    }
+    text.replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&#x27;", "'")
+        .replace("&amp;", "&")
+}
+
+fn slug(text: &str) -> String {
+    text.trim()
+        .to_lowercase()
+        .chars()
+        .filter_map(|c| match c {
+            ' ' => Some('-'),
+            c if c.is_alphanumeric() || c == '-' || c == '_' => Some(c),
+            _ => None,
+        })
+        .collect()
+}
+
+struct Markdown;
+
+impl Markup for Markdown {
+    fn name(&self) -> &'static str {
+        "markdown"
+    }
+
+    fn extensions(&self) -> &'static [&'static str] {
+        MARKDOWN_EXTENSIONS
+    }
+
+    fn to_html(&self, source: &str) -> String {
+        markdown::to_html_with_options(source, &markdown::Options::gfm())
+            .unwrap_or_else(|_| PlainText.to_html(source))
+    }
+}
+
+struct PlainText;
+
+impl Markup for PlainText {
+    fn name(&self) -> &'static str {
+        "plain text"
+    }
+
+    fn extensions(&self) -> &'static [&'static str] {
+        PLAIN_TEXT_EXTENSIONS
+    }
+
+    fn to_html(&self, source: &str) -> String {
+        format!("<pre>{}</pre>", escape(source))
+    }
+}
+
+/// The commonly used subset of AsciiDoc: section titles, paragraphs, lists,
+/// listing and literal blocks, images and inline formatting.
+struct AsciiDoc;
+
+impl Markup for AsciiDoc {
+    fn name(&self) -> &'static str {
+        "asciidoc"
+    }
+
+    fn extensions(&self) -> &'static [&'static str] {
+        ASCIIDOC_EXTENSIONS
+    }
+
+    fn to_html(&self, source: &str) -> String {
+        static LIST: OnceLock<Regex> = OnceLock::new();
+        let list = LIST.get_or_init(|| Regex::new(r"^\s*(\*+|-|\.+|\d+\.)\s+(.*)$").unwrap());
+
+        let lines: Vec<&str> = source.lines().collect();
+        let mut blocks = Blocks::new(asciidoc_inline);
+        let mut language = None;
+        let mut index = 0;
+        while index < lines.len() {
+            let line = lines[index];
+            let trimmed = line.trim();
+            index += 1;
+
+            if let Some(delimiter) = ["----", "....", "```", "////", "____"]
+                .into_iter()
+                .find(|delimiter| trimmed.starts_with(delimiter))
+            {
+                if delimiter == "```" {
+                    let fence = trimmed.trim_start_matches('`').trim();
+                    language = (!fence.is_empty()).then(|| fence.to_owned()).or(language);
+                }
+                let body = delimited(&lines, &mut index, |line| line.trim() == trimmed);
+                match delimiter {
+                    "////" => {}
+                    "____" => blocks.raw(&format!(
+                        "<blockquote><p>{}</p></blockquote>",
+                        asciidoc_inline(&body.join(" "))
+                    )),
+                    _ => blocks.code(&body.join("\n"), language.take().as_deref()),
+                }
+            } else if trimmed.is_empty() {
+                blocks.flush_paragraph();
+            } else if trimmed.starts_with("//") || trimmed == "+" {
+            } else if let Some(attributes) = trimmed
+                .strip_prefix('[')
+                .and_then(|rest| rest.strip_suffix(']'))
+            {
+                blocks.flush();
+                let mut attributes = attributes.split(',').map(str::trim);
+                if matches!(attributes.next(), Some("source")) {
+                    language = attributes.next().map(str::to_owned);
+                }
+            } else if is_attribute_entry(trimmed) {
+                blocks.flush();
+            } else if let Some(level) = heading_level(trimmed, '=') {
+                blocks.heading(level, trimmed[level..].trim());
+            } else if let Some(rest) = trimmed.strip_prefix("image::") {
+                let (target, alt) = rest.split_once('[').unwrap_or((rest, ""));
+                blocks.raw(&format!(
+                    "<p><img src=\"{}\" alt=\"{}\"></p>",
+                    escape(target),
+                    escape(alt.trim_end_matches(']'))
+                ));
+            } else if let Some(captures) = list.captures(line) {
+                let tag = if captures[1].starts_with(['*', '-']) {
+                    "ul"
+                } else {
+                    "ol"
+                };
+                blocks.item(tag, &captures[2]);
+            } else {
+                blocks.text(trimmed);
+            }
+        }
+        blocks.finish()
+    }
+}
+
+fn is_attribute_entry(line: &str) -> bool {
+    line.strip_prefix(':')
+        .and_then(|rest| rest.split_once(':'))
+        .is_some_and(|(name, _)| {
+            !name.is_empty()
+                && name
+                    .trim_end_matches('!')
+                    .chars()
+                    .all(|c| c.is_alphanumeric() || c == '-' || c == '_')
+        })
+}
+
+fn asciidoc_inline(text: &str) -> String {
+    static CODE: OnceLock<Regex> = OnceLock::new();
+    static RULES: OnceLock<Vec<(Regex, &str)>> = OnceLock::new();
+    let code = CODE.get_or_init(|| Regex::new(r"(?P<pre>)`(?P<code>[^`]+)`").unwrap());
+    let rules = RULES.get_or_init(|| {
+        vec![
+            (
+                Regex::new(r"image:([^\s\[]+)\[([^\]]*)\]").unwrap(),
+                r#"<img src="$1" alt="$2">"#,
+            ),
+            (
+                Regex::new(r"link:([^\s\[]+)\[([^\]]*)\]").unwrap(),
+                r#"<a href="$1">$2</a>"#,
+            ),
+            (
+                Regex::new(r"(^|[\s(])(https?://[^\s\[<]+)\[([^\]]*)\]").unwrap(),
+                r#"$1<a href="$2">$3</a>"#,
+            ),
+            (
+                Regex::new(r"(^|[\s(])(https?://[^\s\[<]+)").unwrap(),
+                r#"$1<a href="$2">$2</a>"#,
+            ),
+            (
+                Regex::new(r"(^|[\s(])\*([^\s*](?:[^*]*[^\s*])?)\*").unwrap(),
+                "$1<strong>$2</strong>",
+            ),
+            (
+                Regex::new(r"(^|[\s(])_([^\s_](?:[^_]*[^\s_])?)_").unwrap(),
+                "$1<em>$2</em>",
+            ),
+        ]
+    });
+    inline(text, code, rules)
+}
+
+/// Emacs Org mode: headlines, paragraphs, lists, tables, source, example and
+/// quote blocks, links and emphasis. Drawers and keywords other than the
+/// title are hidden.
+struct Org;
+
+impl Markup for Org {
+    fn name(&self) -> &'static str {
+        "org"
+    }
+
+    fn extensions(&self) -> &'static [&'static str] {
+        ORG_EXTENSIONS
+    }
+
+    fn to_html(&self, source: &str) -> String {
+        static LIST: OnceLock<Regex> = OnceLock::new();
+        static TAGS: OnceLock<Regex> = OnceLock::new();
+        let list = LIST.get_or_init(|| Regex::new(r"^\s*([-+]|\d+[.)])\s+(.*)$").unwrap());
+        let tags = TAGS.get_or_init(|| Regex::new(r"\s+:[\w@#%:]+:\s*$").unwrap());
+
+        let lines: Vec<&str> = source.lines().collect();
+        let mut blocks = Blocks::new(org_inline);
+        let mut table: Vec<Vec<String>> = Vec::new();
+        let mut index = 0;
+        while index < lines.len() {
+            let line = lines[index];
+            let trimmed = line.trim();
+            index += 1;
+
+            if !trimmed.starts_with('|') && !table.is_empty() {
+                blocks.table(&std::mem::take(&mut table));
+            }
+
+            let keyword = trimmed
+                .strip_prefix("#+")
+                .map(|rest| rest.split_once([':', ' ']).unwrap_or((rest, "")));
+            if let Some((name, value)) = keyword {
+                let name = name.to_ascii_lowercase();
+                if let Some(kind) = name.strip_prefix("begin_") {
+                    let end = format!("#+end_{kind}");
+                    let body = delimited(&lines, &mut index, |line| {
+                        line.trim().eq_ignore_ascii_case(&end)
+                    });
+                    match kind {
+                        "src" => {
+                            let language = value.split_whitespace().next();
+                            blocks.code(&body.join("\n"), language);
+                        }
+                        "quote" => blocks.raw(&format!(
+                            "<blockquote><p>{}</p></blockquote>",
+                            org_inline(&body.join(" "))
+                        )),
+                        _ => blocks.code(&body.join("\n"), None),
+                    }
+                } else if name == "title" {
+                    blocks.heading(1, value.trim());
+                } else {
+                    blocks.flush();
+                }
+            } else if trimmed == "#" || trimmed.starts_with("# ") {
+            } else if trimmed.eq_ignore_ascii_case(":properties:")
+                || trimmed.eq_ignore_ascii_case(":logbook:")
+            {
+                delimited(&lines, &mut index, |line| {
+                    line.trim().eq_ignore_ascii_case(":end:")
+                });
+            } else if let Some(level) = heading_level(line, '*') {
+                blocks.heading(level, &tags.replace(line[level..].trim(), ""));
+            } else if trimmed.starts_with('|') {
+                blocks.flush();
+                if !trimmed.starts_with("|-") {
+                    table.push(
+                        trimmed
+                            .trim_matches('|')
+                            .split('|')
+                            .map(|cell| cell.trim().to_owned())
+                            .collect(),
+                    );
+                }
+            } else if trimmed.is_empty() {
+                blocks.flush_paragraph();
+            } else if let Some(captures) = list.captures(line) {
+                let tag = if captures[1].starts_with(['-', '+']) {
+                    "ul"
+                } else {
+                    "ol"
+                };
+                blocks.item(tag, &captures[2]);
+            } else if line.starts_with(char::is_whitespace) && blocks.in_list() {
+                blocks.continue_item(trimmed);
+            } else {
+                blocks.text(trimmed);
+            }
+        }
+        if !table.is_empty() {
+            blocks.table(&table);
+        }
+        blocks.finish()
+    }
+}
+
+fn org_inline(text: &str) -> String {
+    static CODE: OnceLock<Regex> = OnceLock::new();
+    static RULES: OnceLock<Vec<(Regex, &str)>> = OnceLock::new();
+    let code = CODE.get_or_init(|| {
+        Regex::new(r"(?P<pre>^|[\s(])[=~](?P<code>[^\s=~](?:[^=~]*[^\s=~])?)[=~]").unwrap()
+    });
+    let rules = RULES.get_or_init(|| {
+        vec![
+            (
+                Regex::new(r"\[\[(?:file:)?([^\]]+)\]\[([^\]]+)\]\]").unwrap(),
+                r#"<a href="$1">$2</a>"#,
+            ),
+            (
+                Regex::new(r"\[\[(?:file:)?([^\]]+\.(?:png|jpe?g|gif|svg|webp))\]\]").unwrap(),
+                r#"<img src="$1" alt="">"#,
+            ),
+            (
+                Regex::new(r"\[\[(?:file:)?([^\]]+)\]\]").unwrap(),
+                r#"<a href="$1">$1</a>"#,
+            ),
+            (
+                Regex::new(r"(^|[\s(])\*([^\s*](?:[^*]*[^\s*])?)\*").unwrap(),
+                "$1<strong>$2</strong>",
+            ),
+            (
+                Regex::new(r"(^|[\s(])/([^\s/](?:[^/]*[^\s/])?)/($|[\s.,;:!?)])").unwrap(),
+                "$1<em>$2</em>$3",
+            ),
+        ]
+    });
+    inline(text, code, rules)
+}
+
+/// The core of reStructuredText: adorned section titles, paragraphs, lists,
+/// literal blocks, and the `code-block`, `image` and admonition directives.
+/// Other directives and comments are skipped.
+struct RestructuredText;
+
+impl Markup for RestructuredText {
+    fn name(&self) -> &'static str {
+        "restructuredtext"
+    }
+
+    fn extensions(&self) -> &'static [&'static str] {
+        RST_EXTENSIONS
+    }
+
+    fn to_html(&self, source: &str) -> String {
+        static LIST: OnceLock<Regex> = OnceLock::new();
+        let list = LIST.get_or_init(|| Regex::new(r"^([-*+•]|\d+\.|#\.)\s+(.*)$").unwrap());
+
+        let lines: Vec<&str> = source.lines().collect();
+        let mut blocks = Blocks::new(rst_inline);
+        // Title adornments in order of first use, which decides their level.
+        let mut styles: Vec<(char, bool)> = Vec::new();
+        let mut level = |style: (char, bool)| match styles.iter().position(|inner| *inner == style)
+        {
+            Some(position) => position + 1,
+            None => {
+                styles.push(style);
+                styles.len()
+            }
+        };
+        let mut index = 0;
+        while index < lines.len() {
+            let line = lines[index];
+            let trimmed = line.trim();
+            let next = lines.get(index + 1).map(|line| line.trim());
+            let after = lines.get(index + 2).map(|line| line.trim());
+
+            if let (Some(adornment), Some(title), Some(underline)) =
+                (adornment(trimmed), next, after.and_then(adornment))
+            {
+                if adornment == underline && !title.is_empty() {
+                    blocks.heading(level((adornment, true)), title);
+                    index += 3;
+                    continue;
+                }
+            }
+            if let Some(underline) = next.and_then(adornment) {
+                let long_enough =
+                    next.map_or(0, |next| next.chars().count()) >= trimmed.chars().count();
+                if !trimmed.is_empty() && !line.starts_with(' ') && long_enough {
+                    blocks.heading(level((underline, false)), trimmed);
+                    index += 2;
+                    continue;
+                }
+            }
+            index += 1;
+
+            if trimmed.is_empty() {
+                blocks.flush_paragraph();
+            } else if let Some(directive) = trimmed.strip_prefix("..") {
+                blocks.flush();
+                let (name, argument) = directive.split_once("::").unwrap_or(("", directive));
+                let body = indented(&lines, &mut index);
+                let body: Vec<&str> = body
+                    .iter()
+                    .map(String::as_str)
+                    .skip_while(|line| line.starts_with(':'))
+                    .collect();
+                let argument = argument.trim();
+                match name.trim() {
+                    "code-block" | "code" | "sourcecode" => {
+                        let language = (!argument.is_empty()).then_some(argument);
+                        blocks.code(body.join("\n").trim_start_matches('\n'), language);
+                    }
+                    "image" | "figure" => blocks.raw(&format!(
+                        "<p><img src=\"{}\" alt=\"\"></p>",
+                        escape(argument)
+                    )),
+                    name @ ("note" | "tip" | "hint" | "important" | "attention" | "caution"
+                    | "warning" | "danger" | "error") => {
+                        let text = format!("{argument} {}", body.join(" "));
+                        blocks.raw(&format!(
+                            "<blockquote><p><strong>{name}:</strong> {}</p></blockquote>",
+                            rst_inline(text.trim())
+                        ));
+                    }
+                    _ => {}
+                }
+            } else if let Some(captures) = list.captures(trimmed) {
+                let tag = if captures[1].ends_with('.') {
+                    "ol"
+                } else {
+                    "ul"
+                };
+                blocks.item(tag, &captures[2]);
+            } else if line.starts_with(char::is_whitespace) && blocks.in_list() {
+                blocks.continue_item(trimmed);
+            } else if let Some(text) = trimmed.strip_suffix("::") {
+                if !text.is_empty() {
+                    blocks.text(&format!("{}:", text.trim_end()));
+                }
+                let body = indented(&lines, &mut index);
+                blocks.code(&body.join("\n"), None);
+            } else {
+                blocks.text(trimmed);
+            }
+        }
+        blocks.finish()
+    }
+}
+
+/// The punctuation character a section adornment line repeats.
+fn adornment(line: &str) -> Option<char> {
+    let first = line.chars().next()?;
+    (line.len() >= 3 && "=-~^\"'`#*+:.<>_".contains(first) && line.chars().all(|c| c == first))
+        .then_some(first)
+}
+
+fn rst_inline(text: &str) -> String {
+    static CODE: OnceLock<Regex> = OnceLock::new();
+    static RULES: OnceLock<Vec<(Regex, &str)>> = OnceLock::new();
+    let code = CODE.get_or_init(|| Regex::new(r"(?P<pre>)``(?P<code>[^`]+)``").unwrap());
+    let rules = RULES.get_or_init(|| {
+        vec![
+            (
+                Regex::new(r"`([^`<]+?)\s*&lt;([^`&]+)&gt;`__?").unwrap(),
+                r#"<a href="$2">$1</a>"#,
+            ),
+            (
+                Regex::new(r"(^|[\s(])(https?://[^\s<]+)").unwrap(),
+                r#"$1<a href="$2">$2</a>"#,
+            ),
+            (
+                Regex::new(r"\*\*([^\s*](?:[^*]*[^\s*])?)\*\*").unwrap(),
+                "<strong>$1</strong>",
+            ),
+            (
+                Regex::new(r"\*([^\s*](?:[^*]*[^\s*])?)\*").unwrap(),
+                "<em>$1</em>",
+            ),
+            (Regex::new(r"`([^`]+)`_?").unwrap(), "<em>$1</em>"),
+        ]
+    });
+    inline(text, code, rules)
+}
+
+/// Escapes `text` and applies `rules` in order to everything outside the code
+/// spans matched by `code`, whose `pre` group is kept and `code` group shown
+/// verbatim.
+fn inline(text: &str, code: &Regex, rules: &[(Regex, &str)]) -> String {
+    let format = |text: &str| {
+        let mut text = escape(text);
+        for (pattern, replacement) in rules {
+            text = pattern.replace_all(&text, *replacement).into_owned();
+        }
+        strip_unsafe_links(&text)
+    };
+
+    let mut out = String::new();
+    let mut last = 0;
+    for captures in code.captures_iter(text) {
+        let whole = captures.get(0).unwrap();
+        out.push_str(&format(&text[last..whole.start()]));
+        out.push_str(&escape(captures.name("pre").map_or("", |pre| pre.as_str())));
+        out.push_str(&format!("<code>{}</code>", escape(&captures["code"])));
+        last = whole.end();
+    }
+    out.push_str(&format(&text[last..]));
+    out
+}
+
+/// Drops link targets with a script scheme that the rules above produced.
+fn strip_unsafe_links(html: &str) -> String {
+    static UNSAFE: OnceLock<Regex> = OnceLock::new();
+    let pattern = UNSAFE.get_or_init(|| {
+        Regex::new(r#"(?i)(href|src)="\s*(?:javascript|vbscript|data):[^"]*""#).unwrap()
+    });
+    pattern
+        .replace_all(html, |captures: &Captures| {
+            format!("{}=\"#\"", &captures[1])
+        })
+        .into_owned()
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+/// Level of a heading written as a run of `marker` followed by a space.
+fn heading_level(line: &str, marker: char) -> Option<usize> {
+    let level = line.chars().take_while(|c| *c == marker).count();
+    ((1..=6).contains(&level) && line[level..].starts_with(' ')).then_some(level)
+}
+
+/// Lines up to the one matching `end`, which is consumed too.
+fn delimited<'a>(lines: &[&'a str], index: &mut usize, end: impl Fn(&str) -> bool) -> Vec<&'a str> {
+    let mut body = Vec::new();
+    while let Some(line) = lines.get(*index) {
+        *index += 1;
+        if end(line) {
+            break;
+        }
+        body.push(*line);
+    }
+    body
+}
+
+/// The indented block starting at `index`, dedented, with surrounding blank
+/// lines dropped.
+fn indented(lines: &[&str], index: &mut usize) -> Vec<String> {
+    let start = *index;
+    while let Some(line) = lines.get(*index) {
+        if !line.trim().is_empty() && !line.starts_with(char::is_whitespace) {
+            break;
+        }
+        *index += 1;
+    }
+    let block = &lines[start..*index];
+    let indent = block
+        .iter()
+        .filter(|line| !line.trim().is_empty())
+        .map(|line| line.len() - line.trim_start().len())
+        .min()
+        .unwrap_or(0);
+    let mut block: Vec<String> = block
+        .iter()
+        .map(|line| line.get(indent..).unwrap_or_default().to_owned())
+        .collect();
+    while block.first().is_some_and(|line| line.trim().is_empty()) {
+        block.remove(0);
+    }
+    while block.last().is_some_and(|line| line.trim().is_empty()) {
+        block.pop();
+    }
+    block
+}
+
+/// Collects block-level HTML for the line-oriented formats.
+struct Blocks {
+    html: String,
+    paragraph: Vec<String>,
+    list: Option<(&'static str, Vec<String>)>,
+    inline: fn(&str) -> String,
+}
+
+impl Blocks {
+    fn new(inline: fn(&str) -> String) -> Self {
+        Self {
+            html: String::new(),
+            paragraph: Vec::new(),
+            list: None,
+            inline,
+        }
+    }
+
+    fn text(&mut self, line: &str) {
+        self.flush_list();
+        self.paragraph.push(line.to_owned());
+    }
+
+    fn in_list(&self) -> bool {
+        self.list.is_some() && self.paragraph.is_empty()
+    }
+
+    fn item(&mut self, tag: &'static str, text: &str) {
+        self.flush_paragraph();
+        if self
+            .list
+            .as_ref()
+            .is_some_and(|(current, _)| *current != tag)
+        {
+            self.flush_list();
+        }
+        self.list
+            .get_or_insert_with(|| (tag, Vec::new()))
+            .1
+            .push(text.to_owned());
+    }
+
+    fn continue_item(&mut self, text: &str) {
+        if let Some(item) = self.list.as_mut().and_then(|(_, items)| items.last_mut()) {
+            item.push(' ');
+            item.push_str(text);
+        }
+    }
+
+    fn heading(&mut self, level: usize, text: &str) {
+        self.flush();
+        let level = level.clamp(1, 6);
+        self.html
+            .push_str(&format!("<h{level}>{}</h{level}>\n", (self.inline)(text)));
+    }
+
+    fn code(&mut self, code: &str, language: Option<&str>) {
+        self.flush();
+        let code = language
+            .and_then(|language| highlight::snippet(code, language))
+            .map(|lines| lines.join("\n"))
+            .unwrap_or_else(|| escape(code));
+        self.html
+            .push_str(&format!("<pre><code>{code}</code></pre>\n"));
+    }
+
+    fn table(&mut self, rows: &[Vec<String>]) {
+        self.flush();
+        self.html.push_str("<table>\n");
+        for (index, row) in rows.iter().enumerate() {
+            let tag = if index == 0 && rows.len() > 1 {
+                "th"
+            } else {
+                "td"
+            };
+            self.html.push_str("<tr>");
+            for cell in row {
+                self.html
+                    .push_str(&format!("<{tag}>{}</{tag}>", (self.inline)(cell)));
+            }
+            self.html.push_str("</tr>\n");
+        }
+        self.html.push_str("</table>\n");
+    }
+
+    /// Adds block HTML built by the caller, whose attributes haven't been
+    /// through [`inline`].
+    fn raw(&mut self, html: &str) {
+        self.flush();
+        self.html.push_str(&strip_unsafe_links(html));
+        self.html.push('\n');
+    }
+
+    fn flush_paragraph(&mut self) {
+        if !self.paragraph.is_empty() {
+            let text = self.paragraph.join(" ");
+            self.paragraph.clear();
+            self.html
+                .push_str(&format!("<p>{}</p>\n", (self.inline)(&text)));
+        }
+    }
+
+    fn flush_list(&mut self) {
+        if let Some((tag, items)) = self.list.take() {
+            self.html.push_str(&format!("<{tag}>\n"));
+            for item in items {
+                self.html
+                    .push_str(&format!("<li>{}</li>\n", (self.inline)(&item)));
+            }
+            self.html.push_str(&format!("</{tag}>\n"));
+        }
+    }
+
+    fn flush(&mut self) {
+        self.flush_paragraph();
+        self.flush_list();
+    }
+
+    fn finish(mut self) -> String {
+        self.flush();
+        self.html
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn inline_escapes_and_keeps_code_verbatim() {
+        assert_eq!(
+            asciidoc_inline("*bold* and `<b>*code*</b>`"),
+            "<strong>bold</strong> and <code>&lt;b&gt;*code*&lt;/b&gt;</code>"
+        );
+        assert_eq!(
+            org_inline("see =x < y= or [[https://example.com][the site]]"),
+            "see <code>x &lt; y</code> or <a href=\"https://example.com\">the site</a>"
+        );
+        assert_eq!(
+            rst_inline("**strong** *em* ``code`` `Docs <https://example.com>`_"),
+            "<strong>strong</strong> <em>em</em> <code>code</code> <a href=\"https://example.com\">Docs</a>"
+        );
+    }
+
+    #[test]
+    fn inline_strips_script_links() {
+        assert_eq!(
+            asciidoc_inline("link:javascript:alert(1)[click]"),
+            "<a href=\"#\">click</a>"
+        );
+        assert_eq!(
+            org_inline("[[JavaScript:alert(1)][click]]"),
+            "<a href=\"#\">click</a>"
+        );
+        assert_eq!(
+            strip_unsafe_links(r##"<img src=" data:image/png;base64,AA"> <a href="/ok">"##),
+            r##"<img src="#"> <a href="/ok">"##
+        );
+    }
+
+    #[test]
+    fn block_images_strip_script_links() {
+        assert_eq!(
+            AsciiDoc.to_html("image::javascript:alert(1)[logo]"),
+            "<p><img src=\"#\" alt=\"logo\"></p>\n"
+        );
+        assert_eq!(
+            RestructuredText.to_html(".. image:: javascript:alert(1)"),
+            "<p><img src=\"#\" alt=\"\"></p>\n"
+        );
+        assert_eq!(
+            AsciiDoc.to_html("image::docs/logo.png[logo]"),
+            "<p><img src=\"docs/logo.png\" alt=\"logo\"></p>\n"
+        );
+    }
+
+    #[test]
+    fn lists() {
+        assert_eq!(
+            AsciiDoc.to_html("* one\n* two\n. first"),
+            "<ul>\n<li>one</li>\n<li>two</li>\n</ul>\n<ol>\n<li>first</li>\n</ol>\n"
+        );
+        assert_eq!(
+            Org.to_html("- one\n  continued\n- two\n\ntext"),
+            "<ul>\n<li>one continued</li>\n<li>two</li>\n</ul>\n<p>text</p>\n"
+        );
+        assert_eq!(
+            RestructuredText.to_html("1. first\n2. second"),
+            "<ol>\n<li>first</li>\n<li>second</li>\n</ol>\n"
+        );
+    }
+
+    #[test]
+    fn tables() {
+        assert_eq!(
+            Org.to_html("| a | b |\n|---+---|\n| 1 | *2* |"),
+            "<table>\n<tr><th>a</th><th>b</th></tr>\n<tr><td>1</td><td><strong>2</strong></td></tr>\n</table>\n"
+        );
+        assert_eq!(
+            Org.to_html("| only |"),
+            "<table>\n<tr><td>only</td></tr>\n</table>\n"
+        );
+    }
+
+    #[test]
+    fn headings() {
+        assert_eq!(AsciiDoc.to_html("== Usage"), "<h2>Usage</h2>\n");
+        assert_eq!(Org.to_html("** Tasks :work:"), "<h2>Tasks</h2>\n");
+        assert_eq!(
+            RestructuredText.to_html("=====\nTitle\n=====\n\nSection\n-------"),
+            "<h1>Title</h1>\n<h2>Section</h2>\n"
+        );
+    }
+
+    #[test]
+    fn anchors() {
+        let (html, toc) = add_anchors("<h1>Hello, World</h1><h2>Hello, World</h2><hr>");
+        assert_eq!(
+            html,
+            "<h1 id=\"hello-world\"><a class=\"anchor\" href=\"#hello-world\">#</a>Hello, World</h1>\
+             <h2 id=\"hello-world-1\"><a class=\"anchor\" href=\"#hello-world-1\">#</a>Hello, World</h2><hr>"
+        );
+        let toc: Vec<_> = toc
+            .iter()
+            .map(|heading| {
+                (
+                    heading.level,
+                    heading.text.as_str(),
+                    heading.anchor.as_str(),
+                )
+            })
+            .collect();
+        assert_eq!(
+            toc,
+            [
+                (1, "Hello, World", "hello-world"),
+                (2, "Hello, World", "hello-world-1")
+            ]
+        );
+        assert_eq!(slug(" <Tom & Jerry> "), "tom--jerry");
+        assert_eq!(text_content("<code>a &lt; b</code> &amp; c"), "a < b & c");
+    }
+
+    #[test]
+    fn relative_paths() {
+        assert_eq!(join("docs/guide", "../img/a.png"), "docs/img/a.png");
+        assert_eq!(join("docs", "/README.md"), "README.md");
+        assert_eq!(join("", "../../etc/passwd"), "etc/passwd");
+        assert!(has_scheme("mailto:someone@example.com"));
+        assert!(!has_scheme("docs/a:b"));
+        assert_eq!(percent_decode("a%20b%zz"), "a b%zz");
+    }
+}
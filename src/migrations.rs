@@ -0,0 +1,161 @@
+//! Changes to stored documents that the code relies on, applied once each by
+//! `gecko-admin migrate` and recorded in the `migrations` collection.
+
+use std::path::Path;
+
+use futures::TryStreamExt;
+use mongodb::error::Result;
+
+use crate::{database::Database, model::Repository};
+
+/// Name and description of every migration, in the order they are applied.
+pub const MIGRATIONS: [(&str, &str); 5] = [
+    (
+        "repository_defaults",
+        "store the fields repositories gained since they were created",
+    ),
+    (
+        "user_defaults",
+        "store the fields users gained since they signed up",
+    ),
+    (
+        "default_branches",
+        "store the branch HEAD points at for repositories without a default branch",
+    ),
+    (
+        "registration_mode",
+        "replace the open_registration site setting with a registration mode",
+    ),
+    (
+        "unique_indexes",
+        "make usernames and emails unique; fails while there are duplicates",
+    ),
+];
+
+#[derive(Debug, serde::Serialize, serde::Deserialize)]
+struct Migration {
+    _id: String,
+    applied_at: i64,
+}
+
+/// Names of the migrations applied so far.
+pub async fn applied(database: &Database) -> Result<Vec<String>> {
+    let migrations = database.collection::<Migration>("migrations");
+    let cursor = migrations.find(None, None).await?;
+    let applied: Vec<_> = cursor.try_collect().await?;
+    Ok(applied.into_iter().map(|inner| inner._id).collect())
+}
+
+/// Applies the migration called `name` and records it, returning how many
+/// documents changed.
+pub async fn run(database: &Database, name: &str) -> Result<u64> {
+    let changed = match name {
+        "repository_defaults" => {
+            fill_defaults(
+                database,
+                "repositories",
+                bson::doc! {
+                    "default_branch": "",
+                    "protected_branches": [],
+                    "website": "",
+                    "topics": [],
+                    "archived": false,
+                    "collaborators": [],
+                },
+            )
+            .await?
+        }
+        "user_defaults" => {
+            fill_defaults(
+                database,
+                "users",
+                bson::doc! {
+                    "split_diff": false,
+                    "organization": null,
+                    "admin": false,
+                    "suspended": false,
+                },
+            )
+            .await?
+        }
+        "default_branches" => default_branches(database).await?,
+        "registration_mode" => registration_mode(database).await?,
+        "unique_indexes" => {
+            database.create_indexes().await?;
+            0
+        }
+        _ => panic!("unknown migration '{name}'"),
+    };
+
+    let migrations = database.collection::<Migration>("migrations");
+    let now = time::OffsetDateTime::now_utc();
+    let migration = Migration {
+        _id: name.to_owned(),
+        applied_at: now.unix_timestamp(),
+    };
+    migrations.insert_one(migration, None).await?;
+    Ok(changed)
+}
+
+/// Sets each field of `defaults` on the documents that don't have it.
+async fn fill_defaults(
+    database: &Database,
+    collection: &str,
+    defaults: bson::Document,
+) -> Result<u64> {
+    let collection = database.collection::<bson::Document>(collection);
+    let mut changed = 0;
+    for (field, value) in defaults {
+        let result = collection
+            .update_many(
+                bson::doc! { &field: { "$exists": false } },
+                bson::doc! { "$set": { &field: value } },
+                None,
+            )
+            .await?;
+        changed += result.modified_count;
+    }
+    Ok(changed)
+}
+
+async fn default_branches(database: &Database) -> Result<u64> {
+    let collection = database.collection::<Repository>("repositories");
+    let cursor = collection
+        .find(bson::doc! { "default_branch": "" }, None)
+        .await?;
+    let repositories: Vec<_> = cursor.try_collect().await?;
+    let mut changed = 0;
+    for repository in repositories {
+        let Ok(repo) = git2::Repository::open_bare(Path::new(&repository.name)) else {
+            continue;
+        };
+        let Some(branch) = repo
+            .find_reference("HEAD")
+            .ok()
+            .and_then(|head| head.symbolic_target().map(str::to_owned))
+            .and_then(|target| target.strip_prefix("refs/heads/").map(str::to_owned))
+        else {
+            continue;
+        };
+        database.set_default_branch(&repository, &branch).await;
+        changed += 1;
+    }
+    Ok(changed)
+}
+
+async fn registration_mode(database: &Database) -> Result<u64> {
+    let collection = database.collection::<bson::Document>("settings");
+    let result = collection
+        .update_one(
+            bson::doc! { "_id": "site", "open_registration": { "$exists": true } },
+            vec![
+                bson::doc! { "$set": { "registration": {
+                    "$cond": ["$open_registration", "open", "closed"]
+                } } },
+                bson::doc! { "$unset": "open_registration" },
+            ],
+            None,
+        )
+        .await?;
+    Ok(result.modified_count)
+}
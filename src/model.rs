@@ -49,6 +49,124 @@ pub struct Repository {
     pub created_at: i64,
     pub updated_at: i64,
     pub issues: Vec<Issue>,
+    /// Branch shown on the repository page and used as the base for
+    /// comparisons. Empty for repositories created before it was stored, which
+    /// use whatever HEAD points at.
+    #[serde(default)]
+    pub default_branch: String,
+    /// Branches that can't be deleted from the web UI, besides the default
+    /// branch.
+    #[serde(default)]
+    pub protected_branches: Vec<String>,
+    #[serde(default)]
+    pub website: String,
+    #[serde(default)]
+    pub topics: Vec<String>,
+    /// Archived repositories are read-only until unarchived.
+    #[serde(default)]
+    pub archived: bool,
+    /// Users invited to work on the repository, including pending invitations.
+    #[serde(default)]
+    pub collaborators: Vec<Collaborator>,
+}
+
+impl Repository {
+    /// The role `user` has: owners are admins and collaborators have the role
+    /// they were invited with once they accept. In repositories owned by an
+    /// organization, its owners are admins too, its members can read and teams
+    /// add the role they were granted.
+    pub fn role(&self, owner: Option<&User>, user: Option<&User>) -> Option<Role> {
+        let user = user?;
+        if user._id == self.user_id {
+            return Some(Role::Admin);
+        }
+        let collaborator = self
+            .collaborators
+            .iter()
+            .find(|inner| inner.user_id == user._id && inner.accepted)
+            .map(|inner| inner.role);
+        let organization = owner
+            .filter(|owner| owner._id == self.user_id)
+            .and_then(|owner| owner.organization.as_ref());
+        let Some(organization) = organization else {
+            return collaborator;
+        };
+        match organization.membership(user) {
+            Some(membership) if membership.owner => Some(Role::Admin),
+            Some(_) => collaborator.max(organization.role(self, user)),
+            None => collaborator,
+        }
+    }
+
+    /// Whether `user` has at least `role` in the repository `owner` owns.
+    /// Anyone can read public repositories.
+    pub fn can(&self, owner: &Option<User>, user: &Option<User>, role: Role) -> bool {
+        if role == Role::Read && self.visibility == "public" {
+            return true;
+        }
+        self.role(owner.as_ref(), user.as_ref())
+            .is_some_and(|inner| inner >= role)
+    }
+}
+
+/// What a collaborator may do, each role including the ones before it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Role {
+    /// Browse and clone, even when the repository is private, and open issues.
+    Read,
+    /// Manage issues.
+    Triage,
+    /// Commit from the web and create or delete branches.
+    Write,
+    /// Protect branches and edit the description, topics and default branch.
+    Maintain,
+    /// Everything, including visibility, collaborators, renaming and deleting,
+    /// except transferring, which is left to the owner.
+    Admin,
+}
+
+impl Role {
+    pub const ALL: [Role; 5] = [
+        Role::Read,
+        Role::Triage,
+        Role::Write,
+        Role::Maintain,
+        Role::Admin,
+    ];
+
+    pub fn as_str(&self) -> &str {
+        match self {
+            Role::Read => "read",
+            Role::Triage => "triage",
+            Role::Write => "write",
+            Role::Maintain => "maintain",
+            Role::Admin => "admin",
+        }
+    }
+
+    pub fn parse(role: &str) -> Option<Role> {
+        Role::ALL.into_iter().find(|inner| inner.as_str() == role)
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Collaborator {
+    pub user_id: ObjectId,
+    pub role: Role,
+    /// Invitations are pending until the user accepts them.
+    pub accepted: bool,
+    pub invited_at: i64,
+}
+
+/// An old owner and name of a repository that was renamed or transferred,
+/// kept so links and clones using them still work.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Redirect {
+    pub _id: ObjectId,
+    pub user_id: ObjectId,
+    pub name: String,
+    pub repository_id: ObjectId,
 }
 
 #[derive(Default, Debug, Clone, Serialize, Deserialize)]
@@ -61,6 +179,96 @@ pub struct User {
     pub created_at: i64,
     pub updated_at: i64,
     pub log: Vec<Log>,
+    /// Whether diffs are shown side by side rather than unified.
+    #[serde(default)]
+    pub split_diff: bool,
+    /// Set for organizations, which own repositories under `/@{username}` like
+    /// users do but have no password to sign in with.
+    #[serde(default)]
+    pub organization: Option<Organization>,
+    /// Site administrators manage users and repositories from `/admin`.
+    #[serde(default)]
+    pub admin: bool,
+    /// Suspended users can't sign in.
+    #[serde(default)]
+    pub suspended: bool,
+}
+
+impl User {
+    pub fn is_organization(&self) -> bool {
+        self.organization.is_some()
+    }
+}
+
+#[derive(Default, Debug, Clone, Serialize, Deserialize)]
+pub struct Organization {
+    #[serde(default)]
+    pub description: String,
+    pub members: Vec<Membership>,
+    #[serde(default)]
+    pub teams: Vec<Team>,
+}
+
+impl Organization {
+    pub fn membership(&self, user: &User) -> Option<&Membership> {
+        self.members.iter().find(|inner| inner.user_id == user._id)
+    }
+
+    pub fn is_owner(&self, user: Option<&User>) -> bool {
+        user.and_then(|user| self.membership(user))
+            .is_some_and(|inner| inner.owner)
+    }
+
+    /// The role members get in `repository`: read, or the highest role granted
+    /// to one of the teams they are in.
+    pub fn role(&self, repository: &Repository, user: &User) -> Option<Role> {
+        self.membership(user)?;
+        let granted = self
+            .teams
+            .iter()
+            .filter(|team| team.members.contains(&user._id))
+            .filter_map(|team| team.role(repository))
+            .max();
+        Some(granted.unwrap_or(Role::Read))
+    }
+
+    pub fn team(&self, name: &str) -> Option<&Team> {
+        self.teams.iter().find(|inner| inner.name == name)
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Membership {
+    pub user_id: ObjectId,
+    /// Owners manage the organization and are admins of all its repositories.
+    pub owner: bool,
+    pub created_at: i64,
+}
+
+/// A group of members that is granted a role in some of the organization's
+/// repositories.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Team {
+    pub name: String,
+    #[serde(default)]
+    pub description: String,
+    pub members: Vec<ObjectId>,
+    pub repositories: Vec<TeamRepository>,
+}
+
+impl Team {
+    pub fn role(&self, repository: &Repository) -> Option<Role> {
+        self.repositories
+            .iter()
+            .find(|inner| inner.repository_id == repository._id)
+            .map(|inner| inner.role)
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TeamRepository {
+    pub repository_id: ObjectId,
+    pub role: Role,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -70,6 +278,42 @@ pub enum Event {
     UpdatePassword,
     RepositoryCreate,
     RepositoryDelete,
+    RepositoryDefaultBranch,
+    RepositoryUpdate,
+    RepositoryRename,
+    RepositoryTransfer,
+    RepositoryArchive,
+    RepositoryUnarchive,
+    CollaboratorInvite,
+    CollaboratorUpdate,
+    CollaboratorRemove,
+    InvitationAccept,
+    InvitationDecline,
+    OrganizationCreate,
+    OrganizationUpdate,
+    OrganizationMemberAdd,
+    OrganizationMemberUpdate,
+    OrganizationMemberRemove,
+    TeamCreate,
+    TeamDelete,
+    TeamMemberAdd,
+    TeamMemberRemove,
+    TeamRepositoryGrant,
+    TeamRepositoryRevoke,
+    AdminUserSuspend,
+    AdminUserUnsuspend,
+    AdminUserDelete,
+    AdminPasswordReset,
+    AdminGrant,
+    AdminRevoke,
+    AdminRegistration,
+    AdminInviteCreate,
+    AdminInviteDelete,
+    BranchCreate,
+    BranchDelete,
+    BranchRestore,
+    IssueClose,
+    IssueReopen,
 }
 
 impl Event {
@@ -80,6 +324,42 @@ impl Event {
             Event::UpdatePassword => "user.update_password",
             Event::RepositoryCreate => "repository.create",
             Event::RepositoryDelete => "repository.delete",
+            Event::RepositoryDefaultBranch => "repository.default_branch",
+            Event::RepositoryUpdate => "repository.update",
+            Event::RepositoryRename => "repository.rename",
+            Event::RepositoryTransfer => "repository.transfer",
+            Event::RepositoryArchive => "repository.archive",
+            Event::RepositoryUnarchive => "repository.unarchive",
+            Event::CollaboratorInvite => "collaborator.invite",
+            Event::CollaboratorUpdate => "collaborator.update",
+            Event::CollaboratorRemove => "collaborator.remove",
+            Event::InvitationAccept => "invitation.accept",
+            Event::InvitationDecline => "invitation.decline",
+            Event::OrganizationCreate => "organization.create",
+            Event::OrganizationUpdate => "organization.update",
+            Event::OrganizationMemberAdd => "organization.member_add",
+            Event::OrganizationMemberUpdate => "organization.member_update",
+            Event::OrganizationMemberRemove => "organization.member_remove",
+            Event::TeamCreate => "team.create",
+            Event::TeamDelete => "team.delete",
+            Event::TeamMemberAdd => "team.member_add",
+            Event::TeamMemberRemove => "team.member_remove",
+            Event::TeamRepositoryGrant => "team.repository_grant",
+            Event::TeamRepositoryRevoke => "team.repository_revoke",
+            Event::AdminUserSuspend => "admin.user_suspend",
+            Event::AdminUserUnsuspend => "admin.user_unsuspend",
+            Event::AdminUserDelete => "admin.user_delete",
+            Event::AdminPasswordReset => "admin.password_reset",
+            Event::AdminGrant => "admin.grant",
+            Event::AdminRevoke => "admin.revoke",
+            Event::AdminRegistration => "admin.registration",
+            Event::AdminInviteCreate => "admin.invite_create",
+            Event::AdminInviteDelete => "admin.invite_delete",
+            Event::BranchCreate => "branch.create",
+            Event::BranchDelete => "branch.delete",
+            Event::BranchRestore => "branch.restore",
+            Event::IssueClose => "issue.close",
+            Event::IssueReopen => "issue.reopen",
         }
     }
 }
@@ -103,3 +383,72 @@ impl Log {
         )
     }
 }
+
+/// An entry of the site-wide audit log: a log entry and whose log it is in.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AuditEntry {
+    pub username: String,
+    pub log: Log,
+}
+
+/// Settings for the whole site, changed from the admin panel.
+#[derive(Default, Debug, Clone, Serialize, Deserialize)]
+pub struct SiteSettings {
+    #[serde(default)]
+    pub registration: Registration,
+}
+
+/// Who can sign up.
+#[derive(Default, Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Registration {
+    /// Anyone.
+    #[default]
+    Open,
+    /// Only people with an invite code from an admin.
+    Invite,
+    /// No one; admins create accounts with `gecko-admin`.
+    Closed,
+}
+
+impl Registration {
+    pub const ALL: [Registration; 3] = [
+        Registration::Open,
+        Registration::Invite,
+        Registration::Closed,
+    ];
+
+    pub fn as_str(&self) -> &str {
+        match self {
+            Registration::Open => "open",
+            Registration::Invite => "invite",
+            Registration::Closed => "closed",
+        }
+    }
+
+    pub fn parse(registration: &str) -> Option<Registration> {
+        Registration::ALL
+            .into_iter()
+            .find(|inner| inner.as_str() == registration)
+    }
+}
+
+/// A single-use code that lets someone sign up while registration is by
+/// invitation.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Invite {
+    /// The code itself.
+    pub _id: String,
+    /// Username of the admin who created it.
+    pub created_by: String,
+    pub created_at: i64,
+    /// Username of the user who signed up with it.
+    pub used_by: Option<String>,
+    pub used_at: Option<i64>,
+}
+
+impl Invite {
+    pub fn created_at(&self) -> String {
+        crate::time_utils::to_relative_time(self.created_at)
+    }
+}
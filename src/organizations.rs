@@ -0,0 +1,677 @@
+use actix_identity::Identity;
+use actix_web::{http::Method, web, HttpRequest, HttpResponse, Responder};
+use askama::Template;
+use askama_actix::TemplateToResponse;
+
+use crate::{
+    model::{Event, Organization, Repository, Role, User},
+    repository, validation, State,
+};
+
+/// A member as listed on the organization pages.
+pub struct Member {
+    pub username: String,
+    pub owner: bool,
+}
+
+/// A team with its members and repositories resolved to names.
+pub struct TeamView {
+    pub name: String,
+    pub description: String,
+    pub members: Vec<String>,
+    pub repositories: Vec<(String, Role)>,
+}
+
+async fn members(state: &State, organization: &Organization) -> Vec<Member> {
+    let mut members = Vec::new();
+    for membership in &organization.members {
+        let user = state
+            .database
+            .find_user_from_id(&membership.user_id.to_string())
+            .await;
+        if let Some(user) = user {
+            members.push(Member {
+                username: user.username,
+                owner: membership.owner,
+            });
+        }
+    }
+    members.sort_by(|a, b| b.owner.cmp(&a.owner).then(a.username.cmp(&b.username)));
+    members
+}
+
+async fn teams(
+    state: &State,
+    organization: &Organization,
+    repositories: &[Repository],
+) -> Vec<TeamView> {
+    let mut teams = Vec::new();
+    for team in &organization.teams {
+        let mut members = Vec::new();
+        for id in &team.members {
+            if let Some(user) = state.database.find_user_from_id(&id.to_string()).await {
+                members.push(user.username);
+            }
+        }
+        members.sort();
+        let repositories = repositories
+            .iter()
+            .filter_map(|repository| Some((repository.name.clone(), team.role(repository)?)))
+            .collect();
+        teams.push(TeamView {
+            name: team.name.clone(),
+            description: team.description.clone(),
+            members,
+            repositories,
+        });
+    }
+    teams.sort_by(|a, b| a.name.cmp(&b.name));
+    teams
+}
+
+async fn signed_in(state: &State, identity: Option<Identity>) -> Result<User, HttpResponse> {
+    let user = match identity.map(|identity| identity.id()) {
+        Some(Ok(id)) => state.database.find_user_from_id(&id).await,
+        _ => None,
+    };
+    user.ok_or_else(|| {
+        HttpResponse::SeeOther()
+            .insert_header(("Location", "/login"))
+            .finish()
+    })
+}
+
+#[derive(Template)]
+#[template(path = "organization/new.html")]
+struct NewTemplate<'a> {
+    title: &'a str,
+    identity: &'a Option<User>,
+}
+
+#[derive(Debug, serde::Deserialize)]
+pub struct NewForm {
+    name: String,
+    #[serde(default)]
+    email: String,
+    #[serde(default)]
+    description: String,
+}
+
+/// Creates an organization with the signed in user as its owner.
+pub async fn new(
+    req: HttpRequest,
+    state: web::Data<State>,
+    identity: Option<Identity>,
+    form: Option<web::Form<NewForm>>,
+) -> impl Responder {
+    let user = match signed_in(&state, identity).await {
+        Ok(user) => user,
+        Err(response) => return response,
+    };
+    let form = match (req.method(), form) {
+        (&Method::POST, Some(form)) => form.into_inner(),
+        _ => {
+            return NewTemplate {
+                title: "new organization",
+                identity: &Some(user),
+            }
+            .to_response()
+        }
+    };
+
+    let name = form.name.trim();
+    if let Err(error) = validation::check_username(name) {
+        return HttpResponse::BadRequest().body(error);
+    }
+    let result = state
+        .database
+        .new_organization(&user, name, form.email.trim(), form.description.trim())
+        .await;
+    if result.is_err() {
+        return HttpResponse::Conflict().body(format!("the name '{name}' is already taken"));
+    }
+
+    state
+        .database
+        .add_user_log(&user, Event::OrganizationCreate, Some(name.to_owned()))
+        .await;
+    HttpResponse::SeeOther()
+        .insert_header(("Location", format!("/@{name}")))
+        .finish()
+}
+
+#[derive(Template)]
+#[template(path = "organization/index.html")]
+struct IndexTemplate<'a> {
+    title: &'a str,
+    user: &'a User,
+    organization: &'a Organization,
+    identity: &'a Option<User>,
+    repositories: &'a [Repository],
+    members: &'a [Member],
+    /// Only shown to members.
+    teams: &'a [TeamView],
+    is_owner: bool,
+}
+
+/// The profile page of an organization, the counterpart of `user::index`.
+pub async fn index(state: &State, user: User, identity: Option<User>) -> HttpResponse {
+    let Some(organization) = user.organization.as_ref() else {
+        return HttpResponse::NotFound().finish();
+    };
+    let repositories = state
+        .database
+        .find_user_repositories(user._id)
+        .await
+        .unwrap_or_default();
+    let owner = Some(user.clone());
+    let repositories: Vec<_> = repositories
+        .into_iter()
+        .filter(|inner| inner.can(&owner, &identity, Role::Read))
+        .collect();
+
+    let members = members(state, organization).await;
+    let is_member = identity
+        .as_ref()
+        .is_some_and(|identity| organization.membership(identity).is_some());
+    let teams = if is_member {
+        teams(state, organization, &repositories).await
+    } else {
+        vec![]
+    };
+
+    IndexTemplate {
+        title: &user.username,
+        user: &user,
+        organization,
+        identity: &identity,
+        repositories: &repositories,
+        members: &members,
+        teams: &teams,
+        is_owner: organization.is_owner(identity.as_ref()),
+    }
+    .to_response()
+}
+
+/// The signed in owner and the organization named `name`.
+async fn load(
+    state: &State,
+    name: &str,
+    identity: Option<Identity>,
+) -> Result<(User, User), HttpResponse> {
+    let user = signed_in(state, identity).await?;
+    let organization = state
+        .database
+        .find_user(name)
+        .await
+        .filter(User::is_organization);
+    let Some(organization) = organization else {
+        return Err(HttpResponse::NotFound().finish());
+    };
+    if !organization
+        .organization
+        .as_ref()
+        .is_some_and(|inner| inner.is_owner(Some(&user)))
+    {
+        return Err(HttpResponse::Forbidden().body("Unauthorized"));
+    }
+    Ok((user, organization))
+}
+
+fn redirect(name: &str) -> HttpResponse {
+    HttpResponse::SeeOther()
+        .insert_header(("Location", format!("/organizations/{name}/settings")))
+        .finish()
+}
+
+async fn find_member(
+    state: &State,
+    organization: &User,
+    username: &str,
+) -> Result<User, HttpResponse> {
+    let username = username.trim().trim_start_matches('@');
+    let user = state.database.find_user(username).await.filter(|user| {
+        organization
+            .organization
+            .as_ref()
+            .is_some_and(|inner| inner.membership(user).is_some())
+    });
+    user.ok_or_else(|| HttpResponse::NotFound().body(format!("@{username} is not a member")))
+}
+
+#[derive(Template)]
+#[template(path = "organization/settings.html")]
+struct SettingsTemplate<'a> {
+    title: &'a str,
+    identity: &'a Option<User>,
+    user: &'a User,
+    organization: &'a Organization,
+    repositories: &'a [Repository],
+    members: &'a [Member],
+    teams: &'a [TeamView],
+}
+
+/// Organization settings: the profile, members and teams, for owners.
+pub async fn settings(
+    path: web::Path<String>,
+    state: web::Data<State>,
+    identity: Option<Identity>,
+) -> impl Responder {
+    let name = path.into_inner();
+    let (identity, user) = match load(&state, &name, identity).await {
+        Ok(loaded) => loaded,
+        Err(response) => return response,
+    };
+    let Some(organization) = user.organization.as_ref() else {
+        return HttpResponse::NotFound().finish();
+    };
+    let mut repositories = state
+        .database
+        .find_user_repositories(user._id)
+        .await
+        .unwrap_or_default();
+    repositories.sort_by(|a, b| a.name.cmp(&b.name));
+    let members = members(&state, organization).await;
+    let teams = teams(&state, organization, &repositories).await;
+    let title = format!("@{name}/settings");
+
+    SettingsTemplate {
+        title: &title,
+        identity: &Some(identity),
+        user: &user,
+        organization,
+        repositories: &repositories,
+        members: &members,
+        teams: &teams,
+    }
+    .to_response()
+}
+
+#[derive(Debug, serde::Deserialize)]
+pub struct UpdateForm {
+    #[serde(default)]
+    email: String,
+    #[serde(default)]
+    description: String,
+}
+
+pub async fn update(
+    path: web::Path<String>,
+    state: web::Data<State>,
+    identity: Option<Identity>,
+    form: web::Form<UpdateForm>,
+) -> impl Responder {
+    let name = path.into_inner();
+    let (identity, organization) = match load(&state, &name, identity).await {
+        Ok(loaded) => loaded,
+        Err(response) => return response,
+    };
+
+    state
+        .database
+        .update_organization(&organization, form.email.trim(), form.description.trim())
+        .await;
+    state
+        .database
+        .add_user_log(&identity, Event::OrganizationUpdate, Some(name.clone()))
+        .await;
+    redirect(&name)
+}
+
+#[derive(Debug, serde::Deserialize)]
+pub struct MemberForm {
+    username: String,
+    /// `owner` or `member`.
+    #[serde(default)]
+    role: String,
+}
+
+/// Adds a user to the organization as a member or an owner.
+pub async fn add_member(
+    path: web::Path<String>,
+    state: web::Data<State>,
+    identity: Option<Identity>,
+    form: web::Form<MemberForm>,
+) -> impl Responder {
+    let name = path.into_inner();
+    let (identity, organization) = match load(&state, &name, identity).await {
+        Ok(loaded) => loaded,
+        Err(response) => return response,
+    };
+    let username = form.username.trim().trim_start_matches('@');
+    let user = state
+        .database
+        .find_user(username)
+        .await
+        .filter(|user| !user.is_organization());
+    let Some(user) = user else {
+        return HttpResponse::NotFound().body(format!("no user named '{username}'"));
+    };
+
+    let owner = form.role == "owner";
+    if state
+        .database
+        .add_member(&organization, &user, owner)
+        .await
+        .is_err()
+    {
+        return HttpResponse::Conflict().body(format!("@{} is already a member", user.username));
+    }
+    let description = format!("{name}:{}:{}", user.username, form.role);
+    state
+        .database
+        .add_user_log(&identity, Event::OrganizationMemberAdd, Some(description))
+        .await;
+    redirect(&name)
+}
+
+/// Whether `user` is the only owner left, who can't be demoted or removed.
+fn is_last_owner(organization: &User, user: &User) -> bool {
+    let Some(organization) = organization.organization.as_ref() else {
+        return false;
+    };
+    organization.is_owner(Some(user))
+        && organization
+            .members
+            .iter()
+            .filter(|inner| inner.owner)
+            .count()
+            == 1
+}
+
+/// Makes a member an owner or an owner a member.
+pub async fn update_member(
+    path: web::Path<String>,
+    state: web::Data<State>,
+    identity: Option<Identity>,
+    form: web::Form<MemberForm>,
+) -> impl Responder {
+    let name = path.into_inner();
+    let (identity, organization) = match load(&state, &name, identity).await {
+        Ok(loaded) => loaded,
+        Err(response) => return response,
+    };
+    let user = match find_member(&state, &organization, &form.username).await {
+        Ok(user) => user,
+        Err(response) => return response,
+    };
+    let owner = form.role == "owner";
+    if !owner && is_last_owner(&organization, &user) {
+        return HttpResponse::BadRequest().body("an organization needs at least one owner");
+    }
+
+    state
+        .database
+        .set_member_owner(&organization, &user, owner)
+        .await;
+    let description = format!("{name}:{}:{}", user.username, form.role);
+    state
+        .database
+        .add_user_log(
+            &identity,
+            Event::OrganizationMemberUpdate,
+            Some(description),
+        )
+        .await;
+    redirect(&name)
+}
+
+/// Removes a member from the organization and its teams.
+pub async fn remove_member(
+    path: web::Path<String>,
+    state: web::Data<State>,
+    identity: Option<Identity>,
+    form: web::Form<MemberForm>,
+) -> impl Responder {
+    let name = path.into_inner();
+    let (identity, organization) = match load(&state, &name, identity).await {
+        Ok(loaded) => loaded,
+        Err(response) => return response,
+    };
+    let user = match find_member(&state, &organization, &form.username).await {
+        Ok(user) => user,
+        Err(response) => return response,
+    };
+    if is_last_owner(&organization, &user) {
+        return HttpResponse::BadRequest().body("an organization needs at least one owner");
+    }
+
+    state.database.remove_member(&organization, &user).await;
+    let description = format!("{name}:{}", user.username);
+    state
+        .database
+        .add_user_log(
+            &identity,
+            Event::OrganizationMemberRemove,
+            Some(description),
+        )
+        .await;
+    redirect(&name)
+}
+
+#[derive(Debug, serde::Deserialize)]
+pub struct TeamForm {
+    team: String,
+    #[serde(default)]
+    description: String,
+}
+
+pub async fn new_team(
+    path: web::Path<String>,
+    state: web::Data<State>,
+    identity: Option<Identity>,
+    form: web::Form<TeamForm>,
+) -> impl Responder {
+    let name = path.into_inner();
+    let (identity, organization) = match load(&state, &name, identity).await {
+        Ok(loaded) => loaded,
+        Err(response) => return response,
+    };
+    let team = form.team.trim();
+    if !repository::is_valid_name(team) {
+        return HttpResponse::BadRequest().body(format!("'{team}' is not a valid team name"));
+    }
+    if state
+        .database
+        .new_team(&organization, team, form.description.trim())
+        .await
+        .is_err()
+    {
+        return HttpResponse::Conflict().body(format!("there is already a team named '{team}'"));
+    }
+
+    state
+        .database
+        .add_user_log(&identity, Event::TeamCreate, Some(format!("{name}:{team}")))
+        .await;
+    redirect(&name)
+}
+
+pub async fn delete_team(
+    path: web::Path<String>,
+    state: web::Data<State>,
+    identity: Option<Identity>,
+    form: web::Form<TeamForm>,
+) -> impl Responder {
+    let name = path.into_inner();
+    let (identity, organization) = match load(&state, &name, identity).await {
+        Ok(loaded) => loaded,
+        Err(response) => return response,
+    };
+
+    state.database.delete_team(&organization, &form.team).await;
+    let description = format!("{name}:{}", form.team);
+    state
+        .database
+        .add_user_log(&identity, Event::TeamDelete, Some(description))
+        .await;
+    redirect(&name)
+}
+
+#[derive(Debug, serde::Deserialize)]
+pub struct TeamMemberForm {
+    team: String,
+    username: String,
+}
+
+/// Loads the organization and team for an owner, and the member named in the
+/// form.
+async fn load_team_member(
+    state: &State,
+    name: &str,
+    identity: Option<Identity>,
+    form: &TeamMemberForm,
+) -> Result<(User, User, User), HttpResponse> {
+    let (identity, organization) = load(state, name, identity).await?;
+    let team = organization
+        .organization
+        .as_ref()
+        .and_then(|inner| inner.team(&form.team));
+    if team.is_none() {
+        return Err(HttpResponse::NotFound().body(format!("no team named '{}'", form.team)));
+    }
+    let user = find_member(state, &organization, &form.username).await?;
+    Ok((identity, organization, user))
+}
+
+/// Adds a member of the organization to a team.
+pub async fn add_team_member(
+    path: web::Path<String>,
+    state: web::Data<State>,
+    identity: Option<Identity>,
+    form: web::Form<TeamMemberForm>,
+) -> impl Responder {
+    let name = path.into_inner();
+    let (identity, organization, user) =
+        match load_team_member(&state, &name, identity, &form).await {
+            Ok(loaded) => loaded,
+            Err(response) => return response,
+        };
+
+    state
+        .database
+        .add_team_member(&organization, &form.team, &user)
+        .await;
+    let description = format!("{name}:{}:{}", form.team, user.username);
+    state
+        .database
+        .add_user_log(&identity, Event::TeamMemberAdd, Some(description))
+        .await;
+    redirect(&name)
+}
+
+pub async fn remove_team_member(
+    path: web::Path<String>,
+    state: web::Data<State>,
+    identity: Option<Identity>,
+    form: web::Form<TeamMemberForm>,
+) -> impl Responder {
+    let name = path.into_inner();
+    let (identity, organization, user) =
+        match load_team_member(&state, &name, identity, &form).await {
+            Ok(loaded) => loaded,
+            Err(response) => return response,
+        };
+
+    state
+        .database
+        .remove_team_member(&organization, &form.team, &user)
+        .await;
+    let description = format!("{name}:{}:{}", form.team, user.username);
+    state
+        .database
+        .add_user_log(&identity, Event::TeamMemberRemove, Some(description))
+        .await;
+    redirect(&name)
+}
+
+#[derive(Debug, serde::Deserialize)]
+pub struct TeamRepositoryForm {
+    team: String,
+    repository: String,
+    #[serde(default)]
+    role: String,
+}
+
+/// Loads the organization and team for an owner, and the organization's
+/// repository named in the form.
+async fn load_team_repository(
+    state: &State,
+    name: &str,
+    identity: Option<Identity>,
+    form: &TeamRepositoryForm,
+) -> Result<(User, User, Repository), HttpResponse> {
+    let (identity, organization) = load(state, name, identity).await?;
+    let team = organization
+        .organization
+        .as_ref()
+        .and_then(|inner| inner.team(&form.team));
+    if team.is_none() {
+        return Err(HttpResponse::NotFound().body(format!("no team named '{}'", form.team)));
+    }
+    let Some(repository) = state
+        .database
+        .find_repository(Some(&organization), &form.repository)
+        .await
+    else {
+        return Err(HttpResponse::NotFound().body(format!(
+            "@{name} has no repository named '{}'",
+            form.repository
+        )));
+    };
+    Ok((identity, organization, repository))
+}
+
+/// Grants a team a role in one of the organization's repositories.
+pub async fn grant(
+    path: web::Path<String>,
+    state: web::Data<State>,
+    identity: Option<Identity>,
+    form: web::Form<TeamRepositoryForm>,
+) -> impl Responder {
+    let name = path.into_inner();
+    let (identity, organization, repository) =
+        match load_team_repository(&state, &name, identity, &form).await {
+            Ok(loaded) => loaded,
+            Err(response) => return response,
+        };
+    let Some(role) = Role::parse(&form.role) else {
+        return HttpResponse::BadRequest().body(format!("unknown role '{}'", form.role));
+    };
+
+    state
+        .database
+        .grant_team_repository(&organization, &form.team, &repository, role)
+        .await;
+    let description = format!("{name}:{}:{}:{}", form.team, repository.name, role.as_str());
+    state
+        .database
+        .add_user_log(&identity, Event::TeamRepositoryGrant, Some(description))
+        .await;
+    redirect(&name)
+}
+
+pub async fn revoke(
+    path: web::Path<String>,
+    state: web::Data<State>,
+    identity: Option<Identity>,
+    form: web::Form<TeamRepositoryForm>,
+) -> impl Responder {
+    let name = path.into_inner();
+    let (identity, organization, repository) =
+        match load_team_repository(&state, &name, identity, &form).await {
+            Ok(loaded) => loaded,
+            Err(response) => return response,
+        };
+
+    state
+        .database
+        .revoke_team_repository(&organization, &form.team, &repository)
+        .await;
+    let description = format!("{name}:{}:{}", form.team, repository.name);
+    state
+        .database
+        .add_user_log(&identity, Event::TeamRepositoryRevoke, Some(description))
+        .await;
+    redirect(&name)
+}
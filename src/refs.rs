@@ -0,0 +1,97 @@
+use git2::{BranchType, Commit, Repository};
+
+/// Resolves a revision taken from a URL like `git rev-parse` does: branches,
+/// tags, remote-tracking branches, full or abbreviated commit ids and suffixes
+/// such as `HEAD~3` or `v1.0^2` are all accepted.
+///
+/// Unlike git, a local branch wins over a tag with the same name so the branch
+/// listing always leads to the branch; the tag is still reachable as
+/// `tags/<name>`. Slashes may be escaped as `%2F` to fit in one path segment.
+pub fn resolve<'r>(repo: &'r Repository, rev: &str) -> Result<Commit<'r>, String> {
+    let rev = rev.replace("%2F", "/").replace("%2f", "/");
+    let name_end = rev.find(['~', '^', '@', ':']).unwrap_or(rev.len());
+    let spec = if name_end > 0
+        && repo
+            .find_branch(&rev[..name_end], BranchType::Local)
+            .is_ok()
+    {
+        format!("refs/heads/{rev}")
+    } else {
+        rev.clone()
+    };
+    repo.revparse_single(&spec)
+        .and_then(|object| object.peel_to_commit())
+        .map_err(|_| format!("unknown revision '{rev}'"))
+}
+
+/// Whether the repository has no branches yet. `Repository::is_empty` only
+/// holds while HEAD names the configured `init.defaultBranch`.
+pub fn is_empty(repo: &Repository) -> bool {
+    repo.references_glob("refs/heads/*")
+        .map_or(true, |mut references| references.next().is_none())
+}
+
+pub struct RefLink {
+    pub name: String,
+    /// Revision to put in URLs, qualified when the name is ambiguous.
+    pub rev: String,
+}
+
+/// Branches and tags offered by the ref switcher, linking to the same page at
+/// another revision.
+pub struct RefPicker {
+    /// Name of the revision being shown.
+    pub current: String,
+    /// URL up to the revision segment, such as `/@user/name/tree`.
+    pub base: String,
+    /// Rest of the URL after the revision, such as `/src/main.rs`.
+    pub suffix: String,
+    pub branches: Vec<RefLink>,
+    pub tags: Vec<RefLink>,
+}
+
+impl RefPicker {
+    pub fn new(repo: &Repository, current: &str, base: String, suffix: String) -> Self {
+        let branches: Vec<String> = repo
+            .branches(Some(BranchType::Local))
+            .map(|branches| {
+                branches
+                    .filter_map(|branch| branch.ok()?.0.name().ok()?.map(str::to_owned))
+                    .collect()
+            })
+            .unwrap_or_default();
+        let mut tags: Vec<String> = repo
+            .tag_names(None)
+            .map(|tags| tags.iter().flatten().map(str::to_owned).collect())
+            .unwrap_or_default();
+        tags.sort_by(|a, b| b.cmp(a));
+
+        let escape = |name: &str| name.replace('/', "%2F");
+        let tags = tags
+            .into_iter()
+            .map(|tag| {
+                let rev = if branches.contains(&tag) {
+                    format!("tags%2F{}", escape(&tag))
+                } else {
+                    escape(&tag)
+                };
+                RefLink { name: tag, rev }
+            })
+            .collect();
+        let branches = branches
+            .into_iter()
+            .map(|branch| RefLink {
+                rev: escape(&branch),
+                name: branch,
+            })
+            .collect();
+
+        Self {
+            current: current.replace("%2F", "/").replace("%2f", "/"),
+            base,
+            suffix,
+            branches,
+            tags,
+        }
+    }
+}
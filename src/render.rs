@@ -0,0 +1,259 @@
+use serde_json::Value;
+
+use crate::{highlight, markup};
+
+/// Files larger than this are shown as text instead of being rendered.
+pub const MAX_RENDER_BYTES: usize = 2 * 1024 * 1024;
+pub const MAX_TABLE_ROWS: usize = 1_000;
+
+/// A rendered alternative to the line-by-line file view.
+pub enum View {
+    /// HTML from one of the [`markup::MARKUPS`] formats.
+    Markup(String),
+    /// Shown inline from the raw endpoint.
+    Image,
+    Table(Table),
+    Notebook(Vec<Cell>),
+}
+
+pub struct Table {
+    pub header: Vec<String>,
+    pub rows: Vec<Vec<String>>,
+    /// Rows past [`MAX_TABLE_ROWS`] were left out.
+    pub truncated: bool,
+}
+
+pub struct Cell {
+    pub kind: CellKind,
+    pub execution_count: Option<u64>,
+    pub source: String,
+    /// Rendered markdown, or highlighted code lines joined by newlines.
+    pub html: Option<String>,
+    pub outputs: Vec<Output>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CellKind {
+    Markdown,
+    Code,
+    Raw,
+}
+
+pub enum Output {
+    Text(String),
+    /// Mime type and base64 data of an image.
+    Image(String, String),
+}
+
+pub struct Renderer {
+    pub name: &'static str,
+    extensions: &'static [&'static str],
+    render: fn(&[u8]) -> Option<View>,
+}
+
+const RENDERERS: &[Renderer] = &[
+    Renderer {
+        name: "markdown",
+        extensions: markup::MARKDOWN_EXTENSIONS,
+        render: |content| markup(content, "markdown"),
+    },
+    Renderer {
+        name: "asciidoc",
+        extensions: markup::ASCIIDOC_EXTENSIONS,
+        render: |content| markup(content, "asciidoc"),
+    },
+    Renderer {
+        name: "org",
+        extensions: markup::ORG_EXTENSIONS,
+        render: |content| markup(content, "org"),
+    },
+    Renderer {
+        name: "restructuredtext",
+        extensions: markup::RST_EXTENSIONS,
+        render: |content| markup(content, "restructuredtext"),
+    },
+    Renderer {
+        name: "image",
+        extensions: &[
+            "png", "jpg", "jpeg", "gif", "webp", "avif", "bmp", "ico", "svg",
+        ],
+        render: |_| Some(View::Image),
+    },
+    Renderer {
+        name: "csv",
+        extensions: &["csv"],
+        render: |content| table(content, ','),
+    },
+    Renderer {
+        name: "tsv",
+        extensions: &["tsv", "tab"],
+        render: |content| table(content, '\t'),
+    },
+    Renderer {
+        name: "notebook",
+        extensions: &["ipynb"],
+        render: notebook,
+    },
+];
+
+impl Renderer {
+    /// The renderer registered for the extension of `path`, if any.
+    pub fn find(path: &str) -> Option<&'static Renderer> {
+        let (_, extension) = path.rsplit_once('.')?;
+        RENDERERS.iter().find(|renderer| {
+            renderer
+                .extensions
+                .iter()
+                .any(|inner| inner.eq_ignore_ascii_case(extension))
+        })
+    }
+
+    /// Renders `content`, or returns `None` when it is too large or can't be
+    /// parsed, in which case the text view is used.
+    pub fn render(&self, content: &[u8]) -> Option<View> {
+        if content.len() > MAX_RENDER_BYTES {
+            return None;
+        }
+        (self.render)(content)
+    }
+}
+
+/// Renders with the markup format called `name`.
+fn markup(content: &[u8], name: &str) -> Option<View> {
+    let content = std::str::from_utf8(content).ok()?;
+    markup_html(content, name).map(View::Markup)
+}
+
+fn markup_html(source: &str, name: &str) -> Option<String> {
+    let markup = markup::MARKUPS
+        .iter()
+        .find(|markup| markup.name() == name)?;
+    Some(markup.to_html(source))
+}
+
+fn table(content: &[u8], delimiter: char) -> Option<View> {
+    let content = std::str::from_utf8(content).ok()?;
+    let mut records = parse_delimited(content, delimiter);
+    if records.is_empty() {
+        return None;
+    }
+    let header = records.remove(0);
+    let truncated = records.len() > MAX_TABLE_ROWS;
+    records.truncate(MAX_TABLE_ROWS);
+    Some(View::Table(Table {
+        header,
+        rows: records,
+        truncated,
+    }))
+}
+
+/// Splits RFC 4180 style records: fields may be quoted, and quoted fields may
+/// contain delimiters, newlines and doubled quotes.
+fn parse_delimited(content: &str, delimiter: char) -> Vec<Vec<String>> {
+    let mut records = Vec::new();
+    let mut record = Vec::new();
+    let mut field = String::new();
+    let mut quoted = false;
+    let mut chars = content.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '"' if quoted && chars.peek() == Some(&'"') => {
+                field.push('"');
+                chars.next();
+            }
+            '"' if quoted => quoted = false,
+            '"' if field.is_empty() => quoted = true,
+            c if quoted => field.push(c),
+            c if c == delimiter => record.push(std::mem::take(&mut field)),
+            '\r' if chars.peek() == Some(&'\n') => {}
+            '\n' => {
+                record.push(std::mem::take(&mut field));
+                records.push(std::mem::take(&mut record));
+                if records.len() > MAX_TABLE_ROWS + 1 {
+                    return records;
+                }
+            }
+            c => field.push(c),
+        }
+    }
+    if !field.is_empty() || !record.is_empty() {
+        record.push(field);
+        records.push(record);
+    }
+    records
+}
+
+/// Renders an nbformat 4 notebook. Markdown cells go through the same
+/// renderer as markdown files, and their links are left for the caller to
+/// resolve like those of any other markup view; HTML outputs are skipped for
+/// their plain text counterpart so notebooks can't inject markup.
+fn notebook(content: &[u8]) -> Option<View> {
+    let notebook: Value = serde_json::from_slice(content).ok()?;
+    let language = notebook["metadata"]["language_info"]["name"]
+        .as_str()
+        .or_else(|| notebook["metadata"]["kernelspec"]["language"].as_str())
+        .unwrap_or("python");
+
+    let cells = notebook["cells"]
+        .as_array()?
+        .iter()
+        .map(|cell| {
+            let source = multiline(&cell["source"]);
+            let kind = match cell["cell_type"].as_str() {
+                Some("markdown") => CellKind::Markdown,
+                Some("code") => CellKind::Code,
+                _ => CellKind::Raw,
+            };
+            let html = match kind {
+                CellKind::Markdown => markup_html(&source, "markdown"),
+                CellKind::Code => {
+                    highlight::snippet(&source, language).map(|lines| lines.join("\n"))
+                }
+                CellKind::Raw => None,
+            };
+            let outputs = cell["outputs"]
+                .as_array()
+                .map(|outputs| outputs.iter().filter_map(output).collect())
+                .unwrap_or_default();
+            Cell {
+                kind,
+                execution_count: cell["execution_count"].as_u64(),
+                source,
+                html,
+                outputs,
+            }
+        })
+        .collect();
+    Some(View::Notebook(cells))
+}
+
+fn output(output: &Value) -> Option<Output> {
+    match output["output_type"].as_str()? {
+        "stream" => Some(Output::Text(multiline(&output["text"]))),
+        "error" => {
+            let name = output["ename"].as_str().unwrap_or_default();
+            let value = output["evalue"].as_str().unwrap_or_default();
+            Some(Output::Text(format!("{name}: {value}")))
+        }
+        _ => {
+            let data = &output["data"];
+            for mime in ["image/png", "image/jpeg", "image/gif"] {
+                if !data[mime].is_null() {
+                    let data = multiline(&data[mime]).replace('\n', "");
+                    return Some(Output::Image(mime.to_owned(), data));
+                }
+            }
+            data.get("text/plain")
+                .map(|text| Output::Text(multiline(text)))
+        }
+    }
+}
+
+/// Notebook strings are stored either whole or as a list of lines.
+fn multiline(value: &Value) -> String {
+    match value {
+        Value::String(text) => text.clone(),
+        Value::Array(lines) => lines.iter().filter_map(Value::as_str).collect(),
+        _ => String::new(),
+    }
+}
@@ -1,23 +1,27 @@
 use crate::{
-    diff::Diff,
-    model::{self, Event, User},
+    attributes::Attributes,
+    diff::{self, Diff},
+    highlight, history, markup,
+    model::{self, Role, User},
+    refs::{self, RefPicker},
+    render::{CellKind, Output, Renderer, View},
+    submodules::{Submodule, Submodules},
     time_utils, State,
 };
 use actix_identity::Identity;
-use actix_web::{get, web, HttpResponse, Responder, Result};
+use actix_web::{get, web, HttpRequest, HttpResponse, Responder, Result};
 use askama::Template;
 use askama_actix::TemplateToResponse;
 use git2::Oid;
 use std::path::Path;
 use time::OffsetDateTime;
 
-const MAX_COMMIT_LEN: usize = 20;
-
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 enum Kind {
     Tree,
     File,
     Submodule,
+    Symlink,
 }
 
 impl Kind {
@@ -26,6 +30,7 @@ impl Kind {
             Kind::Tree => "Tree",
             Kind::File => "Kind",
             Kind::Submodule => "Submodule",
+            Kind::Symlink => "Symlink",
         }
     }
 }
@@ -49,6 +54,134 @@ struct Commit {
 struct Entry {
     name: String,
     kind: Kind,
+    /// Page of the entry; a submodule links to its repository, if known.
+    href: Option<String>,
+    /// Where a submodule or symbolic link points.
+    link: Option<Link>,
+    last_commit: Option<Commit>,
+}
+
+#[derive(Debug, Clone)]
+struct Link {
+    /// Submodule URL or symbolic link target.
+    target: String,
+    /// Commit a submodule is pinned to.
+    commit: Option<String>,
+    /// Page the entry links to instead of its own, when it can be browsed.
+    href: Option<String>,
+}
+
+/// The signed in user, owner and repository behind a `/@{username}/{name}`
+/// request, for pages that change the repository.
+pub struct Context {
+    pub identity: Option<User>,
+    pub user: Option<User>,
+    pub repository: model::Repository,
+    pub repo: git2::Repository,
+}
+
+impl Context {
+    pub async fn load(
+        state: &State,
+        username: &str,
+        name: &str,
+        identity: Option<Identity>,
+    ) -> Result<Self, HttpResponse> {
+        let identity = match identity {
+            Some(identity) => match identity.id() {
+                Ok(id) => state.database.find_user_from_id(&id).await,
+                Err(_) => todo!(),
+            },
+            None => None,
+        };
+        let user = state.database.find_user(username).await;
+        let Some(repository) = state.database.find_repository(user.as_ref(), name).await else {
+            return Err(HttpResponse::NotFound().finish());
+        };
+        if !repository.can(&user, &identity, Role::Read) {
+            return Err(HttpResponse::NotFound().finish());
+        }
+        let Ok(repo) = git2::Repository::open(name) else {
+            return Err(HttpResponse::NotFound().finish());
+        };
+        Ok(Self {
+            identity,
+            user,
+            repository,
+            repo,
+        })
+    }
+
+    pub fn can(&self, role: Role) -> bool {
+        self.repository.can(&self.user, &self.identity, role)
+    }
+
+    /// The signed-in user if they have at least `role`.
+    pub fn require(&self, role: Role) -> Result<&User, HttpResponse> {
+        match self.identity.as_ref() {
+            Some(identity) if self.can(role) => Ok(identity),
+            Some(_) => Err(HttpResponse::Forbidden().body("Unauthorized")),
+            None => Err(HttpResponse::SeeOther()
+                .insert_header(("Location", "/login"))
+                .finish()),
+        }
+    }
+
+    /// Requires the write role, for changes to the contents of the repository,
+    /// which archived repositories don't accept.
+    pub fn writer(&self) -> Result<&User, HttpResponse> {
+        let user = self.require(Role::Write)?;
+        if self.repository.archived {
+            return Err(HttpResponse::Forbidden().body("This repository is archived and read-only"));
+        }
+        Ok(user)
+    }
+
+    pub fn default_branch(&self) -> Option<String> {
+        default_branch(&self.repository, &self.repo)
+    }
+}
+
+/// The stored default branch, or for repositories that predate the setting,
+/// the branch HEAD points at. The branch may have no commits yet.
+pub fn default_branch(repository: &model::Repository, repo: &git2::Repository) -> Option<String> {
+    if !repository.default_branch.is_empty() {
+        return Some(repository.default_branch.clone());
+    }
+    let head = repo.find_reference("HEAD").ok()?;
+    let target = head.symbolic_target()?;
+    Some(
+        target
+            .strip_prefix("refs/heads/")
+            .unwrap_or(target)
+            .to_owned(),
+    )
+}
+
+/// Whether `name` can be used as a repository name, which is also the name of
+/// its directory on disk.
+pub fn is_valid_name(name: &str) -> bool {
+    !name.is_empty()
+        && name.len() <= 100
+        && name != "."
+        && name != ".."
+        && name
+            .chars()
+            .all(|inner| inner.is_ascii_alphanumeric() || matches!(inner, '-' | '_' | '.'))
+}
+
+#[derive(Template)]
+#[template(path = "repository/empty.html")]
+struct EmptyTemplate<'a> {
+    title: &'a str,
+    repository: &'a model::Repository,
+    username: &'a str,
+    name: &'a str,
+    user: &'a Option<User>,
+    identity: &'a Option<User>,
+    default_branch: &'a str,
+    /// `host:path` of the bare repository, to push to over SSH.
+    remote: &'a str,
 }
 
 #[derive(Template)]
@@ -56,19 +189,20 @@ struct Entry {
 struct RepositoryTemplate<'a> {
     title: &'a str,
     repository: &'a model::Repository,
-    branch: &'a str,
     username: &'a str,
     name: &'a str,
     user: &'a Option<User>,
     identity: &'a Option<User>,
     entries: &'a [Entry],
     commit: Commit,
-    readme: Option<(String, String)>,
+    readme: Option<markup::Readme>,
+    refs: &'a RefPicker,
 }
 
 #[get("/{name}")]
 pub async fn index(
     path: web::Path<(String, String)>,
+    req: HttpRequest,
     state: web::Data<State>,
     identity: Option<Identity>,
 ) -> Result<impl Responder> {
@@ -85,24 +219,51 @@ pub async fn index(
     let user = state.database.find_user(&username).await;
 
     let Some(repository) = state.database.find_repository(user.as_ref(), &name).await else {
-        return Ok(HttpResponse::Ok().finish());
+        return Ok(HttpResponse::NotFound().finish());
     };
 
     let Ok(repo) = git2::Repository::open(name.clone()) else {
         return Ok(HttpResponse::NotFound().finish());
     };
 
-    let head = repo.head().unwrap();
-
-    let mut branch = String::new();
-    if head.is_branch() {
-        let name = head
-            .name()
-            .map(|name| name.split('/').last().unwrap())
-            .unwrap();
-        branch.push_str(name);
-    }
-    let commit = head.peel_to_commit().unwrap();
+    let default_branch = default_branch(&repository, &repo).unwrap_or_default();
+    let start = repo
+        .find_branch(&default_branch, git2::BranchType::Local)
+        .ok()
+        .map(|inner| (default_branch.clone(), inner.into_reference()))
+        // Detached HEAD: show the commit without a branch.
+        .or_else(|| Some((String::new(), repo.head().ok()?)))
+        // The default branch was deleted: show any other branch.
+        .or_else(|| {
+            let (inner, _) = repo
+                .branches(Some(git2::BranchType::Local))
+                .ok()?
+                .flatten()
+                .next()?;
+            Some((inner.name().ok()??.to_owned(), inner.into_reference()))
+        });
+    let Some((branch, commit)) =
+        start.and_then(|(branch, head)| Some((branch, head.peel_to_commit().ok()?)))
+    else {
+        let host = req.connection_info().host().to_owned();
+        let remote = format!(
+            "{}:{}",
+            host.rsplit_once(':')
+                .map_or(host.as_str(), |(host, _)| host),
+            repo.path().display().to_string().trim_end_matches('/')
+        );
+        return Ok(EmptyTemplate {
+            title: &name,
+            repository: &repository,
+            username: &username,
+            name: &name,
+            user: &user,
+            identity: &identity,
+            default_branch: &default_branch,
+            remote: &remote,
+        }
+        .to_response());
+    };
 
     let message = commit.message().unwrap().to_string();
     let author_name = commit.author().name().unwrap().to_string();
@@ -127,44 +288,34 @@ pub async fn index(
     };
     let commit_tree = commit.tree().unwrap();
 
-    let mut readme: Option<(String, String)> = None;
-    let mut entries = vec![];
-    for entry in commit_tree.iter() {
-        let entry_name = entry.name().unwrap();
-        if entry.kind() == Some(git2::ObjectType::Blob) {
-            if entry_name.starts_with("README") {
-                let blob = repo.find_blob(entry.id()).unwrap();
-                let content = String::from_utf8_lossy(blob.content());
-                readme = Some((
-                    entry_name.to_owned(),
-                    markdown::to_html_with_options(&content, &markdown::Options::gfm()).unwrap(),
-                ));
-            }
-        }
-
-        let mut entry_kind = match entry.kind().unwrap() {
-            git2::ObjectType::Tree => Kind::Tree,
-            _ => Kind::File,
-        };
-
-        if repo.find_submodule(entry_name).is_ok() {
-            entry_kind = Kind::Submodule;
-        }
-
-        entries.push(Entry {
-            name: entry_name.to_string(),
-            kind: entry_kind,
-        });
-    }
+    let rev = if branch.is_empty() {
+        commit_.id.clone()
+    } else {
+        branch.clone()
+    };
+    let location = markup::Location {
+        username: &username,
+        name: &name,
+        rev: &rev,
+        dir: "",
+    };
+    let readme = markup::readme(&repo, &commit_tree, &commit_tree, &location);
 
-    entries.sort_by_key(|e| e.kind == Kind::File);
+    let host = req.connection_info().host().to_owned();
+    let entries = list_entries(&repo, &commit, &commit_tree, &location, &host);
 
     let title = &name;
 
+    let refs = RefPicker::new(
+        &repo,
+        &rev,
+        format!("/@{username}/{name}/tree"),
+        String::new(),
+    );
     Ok(RepositoryTemplate {
+        refs: &refs,
         title,
         repository: &repository,
-        branch: &branch,
         username: &username,
         name: &name,
         user: &user,
@@ -190,7 +341,8 @@ struct TreeTemplate<'a> {
     branch: &'a str,
     tail: &'a str,
     breadcrumb: &'a str,
-    readme: Option<(String, String)>,
+    readme: Option<markup::Readme>,
+    refs: &'a RefPicker,
 }
 
 #[derive(Template)]
@@ -201,16 +353,85 @@ struct FileTemplate<'a> {
     username: &'a str,
     name: &'a str,
     branch: &'a str,
+    tail: &'a str,
     breadcrumb: &'a str,
     user: &'a Option<User>,
     identity: &'a Option<User>,
     blob_name: &'a str,
     content: &'a [&'a str],
+    /// Highlighted HTML for each line of `content`.
+    highlighted: Option<&'a [String]>,
+    /// Name of the renderer registered for this file type, if any.
+    renderer: Option<&'a str>,
+    /// Rendered view, unless the source was requested.
+    view: Option<&'a View>,
+    symlink: Option<&'a Link>,
     size: &'a str,
+    refs: &'a RefPicker,
+}
+
+/// Serves the bytes of a blob. Only media types browsers display safely are
+/// passed through; everything else is sent as plain text or as a download.
+pub async fn raw(
+    path: web::Path<(String, String, String, String)>,
+    state: web::Data<State>,
+) -> Result<impl Responder> {
+    let (username, name, branch, tail) = path.into_inner();
+
+    let user = state.database.find_user(&username).await;
+    if state
+        .database
+        .find_repository(user.as_ref(), &name)
+        .await
+        .is_none()
+    {
+        return Ok(HttpResponse::NotFound().finish());
+    }
+
+    let repo = git2::Repository::open(name.clone()).unwrap();
+    let commit = match refs::resolve(&repo, &branch) {
+        Ok(commit) => commit,
+        Err(error) => return Ok(HttpResponse::NotFound().body(error)),
+    };
+    let Some(blob) = commit
+        .tree()
+        .unwrap()
+        .get_path(Path::new(&tail))
+        .ok()
+        .and_then(|entry| repo.find_blob(entry.id()).ok())
+    else {
+        let body = format!("the path '{tail}' does not exist in the given tree");
+        return Ok(HttpResponse::NotFound().body(body));
+    };
+
+    let mime = mime_guess::from_path(&tail).first();
+    let content_type = match mime {
+        Some(mime)
+            if matches!(
+                mime.type_(),
+                mime_guess::mime::IMAGE | mime_guess::mime::AUDIO | mime_guess::mime::VIDEO
+            ) || mime == mime_guess::mime::APPLICATION_PDF =>
+        {
+            mime.to_string()
+        }
+        _ if blob.is_binary() => "application/octet-stream".to_owned(),
+        _ => "text/plain; charset=utf-8".to_owned(),
+    };
+
+    Ok(HttpResponse::Ok()
+        .content_type(content_type)
+        .insert_header(("X-Content-Type-Options", "nosniff"))
+        // SVGs can carry scripts, so nothing served here may run any.
+        .insert_header((
+            "Content-Security-Policy",
+            "default-src 'none'; style-src 'unsafe-inline'; sandbox",
+        ))
+        .body(blob.content().to_vec()))
 }
 
 pub async fn tree(
     path: web::Path<(String, String, String)>,
+    req: HttpRequest,
     state: web::Data<State>,
     identity: Option<Identity>,
 ) -> Result<impl Responder> {
@@ -227,16 +448,13 @@ pub async fn tree(
     let user = state.database.find_user(&username).await;
 
     let Some(repository) = state.database.find_repository(user.as_ref(), &name).await else {
-        return Ok(HttpResponse::Ok().finish());
+        return Ok(HttpResponse::NotFound().finish());
     };
 
     let repo = git2::Repository::open(name.clone()).unwrap();
-    let commit = {
-        if let Ok(inner) = repo.find_branch(&branch, git2::BranchType::Local) {
-            inner.get().peel_to_commit().unwrap()
-        } else {
-            repo.find_commit(Oid::from_str(&branch).unwrap()).unwrap()
-        }
+    let commit = match refs::resolve(&repo, &branch) {
+        Ok(commit) => commit,
+        Err(error) => return Ok(HttpResponse::NotFound().body(error)),
     };
     let commit_tree = commit.tree().unwrap();
 
@@ -260,50 +478,27 @@ pub async fn tree(
         relative_time,
         datetime,
     };
-    let mut readme: Option<(String, String)> = None;
-    let mut entries = vec![];
-    for entry in commit_tree.iter() {
-        let entry_name = entry.name().unwrap();
-
-        if entry.kind() == Some(git2::ObjectType::Blob) {
-            let blob_path = Path::new(entry_name);
-
-            if let Some(stem) = blob_path.file_stem() {
-                let stem = stem.to_string_lossy();
-
-                if let Some(ext) = blob_path.extension() {
-                    if stem == "README" && (ext == "md" || ext == "markdown") {
-                        let blob = repo.find_blob(entry.id()).unwrap();
-                        let content = String::from_utf8_lossy(blob.content());
-                        let output =
-                            markdown::to_html_with_options(&content, &markdown::Options::gfm())
-                                .unwrap();
-                        readme = Some((stem.into_owned(), output));
-                    }
-                }
-            }
-        }
-
-        let mut entry_kind = match entry.kind().unwrap() {
-            git2::ObjectType::Tree => Kind::Tree,
-            _ => Kind::File,
-        };
-
-        if repo.find_submodule(entry_name).is_ok() {
-            entry_kind = Kind::Submodule;
-        }
-
-        entries.push(Entry {
-            name: entry_name.to_string(),
-            kind: entry_kind,
-        });
-    }
+    let location = markup::Location {
+        username: &username,
+        name: &name,
+        rev: &branch,
+        dir: "",
+    };
+    let readme = markup::readme(&repo, &commit_tree, &commit_tree, &location);
 
-    entries.sort_by_key(|e| e.kind == Kind::File);
+    let host = req.connection_info().host().to_owned();
+    let entries = list_entries(&repo, &commit, &commit_tree, &location, &host);
 
     let title = &format!("{name}/{branch}");
 
+    let refs = RefPicker::new(
+        &repo,
+        &branch,
+        format!("/@{username}/{name}/tree"),
+        String::new(),
+    );
     Ok(TreeTemplate {
+        refs: &refs,
         title,
         repository: &repository,
         username: &username,
@@ -323,10 +518,13 @@ pub async fn tree(
 #[derive(serde::Deserialize)]
 pub struct Query {
     raw: Option<bool>,
+    /// Show the text of a file that would otherwise be rendered.
+    source: Option<bool>,
 }
 
 pub async fn tree_(
     path: web::Path<(String, String, String, String)>,
+    req: HttpRequest,
     query: web::Query<Query>,
     state: web::Data<State>,
     identity: Option<Identity>,
@@ -344,16 +542,13 @@ pub async fn tree_(
     let user = state.database.find_user(&username).await;
 
     let Some(repository) = state.database.find_repository(user.as_ref(), &name).await else {
-        return Ok(HttpResponse::Ok().finish());
+        return Ok(HttpResponse::NotFound().finish());
     };
 
     let repo = git2::Repository::open(name.clone()).unwrap();
-    let commit = {
-        if let Ok(inner) = repo.find_branch(&branch, git2::BranchType::Local) {
-            inner.get().peel_to_commit().unwrap()
-        } else {
-            repo.find_commit(Oid::from_str(&branch).unwrap()).unwrap()
-        }
+    let commit = match refs::resolve(&repo, &branch) {
+        Ok(commit) => commit,
+        Err(error) => return Ok(HttpResponse::NotFound().body(error)),
     };
     let Ok(tree_entry) = commit.tree().unwrap().get_path(Path::new(&tail)) else {
         let file = {
@@ -363,15 +558,67 @@ pub async fn tree_(
         let body = format!("the path '{file}' does not exist in the given tree");
         return Ok(HttpResponse::NotFound().body(body));
     };
+    let location = markup::Location {
+        username: &username,
+        name: &name,
+        rev: &branch,
+        dir: tail
+            .trim_matches('/')
+            .rsplit_once('/')
+            .map_or("", |(dir, _)| dir),
+    };
+    if tree_entry.kind() == Some(git2::ObjectType::Commit) {
+        let submodules = Submodules::from_tree(&repo, &commit.tree().unwrap());
+        let link = submodule_link(
+            submodules.get(tail.trim_matches('/')),
+            tree_entry.id(),
+            &location,
+            req.connection_info().host(),
+        );
+        return Ok(match link.href {
+            Some(href) => HttpResponse::Found()
+                .insert_header(("Location", href))
+                .finish(),
+            None => HttpResponse::NotFound().body(format!(
+                "'{tail}' is a submodule pinned to {}",
+                tree_entry.id()
+            )),
+        });
+    }
     let object = tree_entry.to_object(&repo).unwrap();
 
     if let Some(blob) = object.as_blob() {
+        let symlink = (tree_entry.filemode() == FILEMODE_LINK)
+            .then(|| symlink_link(blob.content(), &commit.tree().unwrap(), &location));
         let blob_name = tail.split('/').last().unwrap();
         let size = humansize::format_size(blob.size(), humansize::DECIMAL.decimal_places(0));
 
         let content = String::from_utf8_lossy(blob.content());
 
-        if blob.is_binary() {
+        let renderer = Renderer::find(&tail);
+        let source = query.source.unwrap_or(false);
+        let mut view = renderer
+            .filter(|_| !source)
+            .and_then(|renderer| renderer.render(blob.content()));
+        match view.as_mut() {
+            Some(View::Markup(html)) => {
+                (*html, _) = markup::postprocess(html, &commit.tree().unwrap(), &location);
+            }
+            Some(View::Notebook(cells)) => {
+                let root = commit.tree().unwrap();
+                for cell in cells
+                    .iter_mut()
+                    .filter(|cell| cell.kind == CellKind::Markdown)
+                {
+                    if let Some(html) = cell.html.as_mut() {
+                        (*html, _) = markup::postprocess(html, &root, &location);
+                    }
+                }
+            }
+            _ => {}
+        }
+
+        if blob.is_binary() && view.is_none() {
             if let Some(raw) = query.raw {
                 if raw {
                     return Ok(HttpResponse::Ok()
@@ -385,10 +632,20 @@ pub async fn tree_(
             }
             return Ok(HttpResponse::Ok()
                 .content_type("text/html")
-                .body(format!("{blob_name} {size}\n<a href=\"/@{username}/{name}/tree/{branch}/{tail}/?raw=true\">view raw</a>")));
+                .body(format!("{blob_name} {size}\n<a href=\"/@{username}/{name}/raw/{branch}/{tail}\">view raw</a>")));
         }
 
-        let content: Vec<&str> = content.lines().collect();
+        let content: Vec<&str> = if blob.is_binary() {
+            Vec::new()
+        } else {
+            content.lines().collect()
+        };
+        let highlighted = if view.is_none() {
+            let attributes = Attributes::from_tree(&repo, &commit.tree().unwrap());
+            highlight::lines(blob.id(), blob.content(), &tail, &attributes)
+        } else {
+            None
+        };
 
         let mut breadcrumb = String::new();
         let mut buffer = String::new();
@@ -410,17 +667,29 @@ pub async fn tree_(
 
         let title = &format!("{name}/{branch}/{tail}");
 
+        let refs = RefPicker::new(
+            &repo,
+            &branch,
+            format!("/@{username}/{name}/blob"),
+            format!("/{tail}"),
+        );
         return Ok(FileTemplate {
+            refs: &refs,
             title,
             repository: &repository,
             username: &username,
             name: &name,
             branch: &branch,
+            tail: &tail,
             breadcrumb: &breadcrumb,
             user: &user,
             identity: &identity,
             blob_name,
             content: content.as_slice(),
+            highlighted: highlighted.as_ref().map(|lines| lines.as_slice()),
+            renderer: renderer.map(|renderer| renderer.name),
+            view: view.as_ref(),
+            symlink: symlink.as_ref(),
             size: &size,
         }
         .to_response());
@@ -448,42 +717,16 @@ pub async fn tree_(
         relative_time,
         datetime,
     };
-    let mut readme: Option<(String, String)> = None;
-    let mut entries = vec![];
-    for entry in tree.iter() {
-        let entry_name = entry.name().unwrap();
-
-        if entry.kind() == Some(git2::ObjectType::Blob) {
-            let blob_path = Path::new(entry_name);
-
-            if let Some(stem) = blob_path.file_stem() {
-                let stem = stem.to_string_lossy();
-
-                if let Some(ext) = blob_path.extension() {
-                    if stem == "README" && (ext == "md" || ext == "markdown") {
-                        let blob = repo.find_blob(entry.id()).unwrap();
-                        let content = String::from_utf8_lossy(blob.content());
-                        let output =
-                            markdown::to_html_with_options(&content, &markdown::Options::gfm())
-                                .unwrap();
-                        readme = Some((stem.into_owned(), output));
-                    }
-                }
-            }
-        }
-
-        let entry_kind = match entry.kind().unwrap() {
-            git2::ObjectType::Tree => Kind::Tree,
-            _ => Kind::File,
-        };
-
-        entries.push(Entry {
-            name: entry_name.to_string(),
-            kind: entry_kind,
-        });
-    }
+    let location = markup::Location {
+        username: &username,
+        name: &name,
+        rev: &branch,
+        dir: tail.trim_matches('/'),
+    };
+    let readme = markup::readme(&repo, &commit.tree().unwrap(), &tree, &location);
 
-    entries.sort_by_key(|e| e.kind == Kind::File);
+    let host = req.connection_info().host().to_owned();
+    let entries = list_entries(&repo, &commit, &tree, &location, &host);
 
     let mut breadcrumb = String::new();
     let mut buffer = String::new();
@@ -506,7 +749,14 @@ pub async fn tree_(
 
     let title = &format!("{name}/{branch}");
 
+    let refs = RefPicker::new(
+        &repo,
+        &branch,
+        format!("/@{username}/{name}/tree"),
+        format!("/{tail}"),
+    );
     Ok(TreeTemplate {
+        refs: &refs,
         title,
         repository: &repository,
         username: &username,
@@ -523,62 +773,6 @@ pub async fn tree_(
     .to_response())
 }
 
-#[derive(Template)]
-#[template(path = "repository/branches.html")]
-struct BranchesTemplate<'a> {
-    title: &'a str,
-    user: &'a Option<User>,
-    identity: &'a Option<User>,
-    username: &'a str,
-    name: &'a str,
-    branches: &'a [String],
-}
-
-pub async fn branches(
-    path: web::Path<(String, String)>,
-    state: web::Data<State>,
-    identity: Option<Identity>,
-) -> impl Responder {
-    let (username, name) = path.into_inner();
-
-    let identity = match identity {
-        Some(identity) => match identity.id() {
-            Ok(id) => state.database.find_user_from_id(&id).await,
-            Err(_) => todo!(),
-        },
-        None => None,
-    };
-
-    let user = state.database.find_user(&username).await;
-
-    let Ok(repo) = git2::Repository::open(name.clone()) else {
-        todo!()
-    };
-
-    let branches = {
-        let mut vec = Vec::new();
-        let branches = repo.branches(Some(git2::BranchType::Local)).unwrap();
-        for branch in branches {
-            let (branch, _) = branch.unwrap();
-            let name = branch.name().unwrap().unwrap();
-            vec.push(name.to_owned());
-        }
-        vec
-    };
-
-    let title = format!("@{username}/{name}/branches");
-
-    BranchesTemplate {
-        title: &title,
-        identity: &identity,
-        user: &user,
-        username: &username,
-        name: &name,
-        branches: &branches,
-    }
-    .to_response()
-}
-
 #[derive(Template)]
 #[template(path = "commits.html")]
 struct CommitsTemplate<'a> {
@@ -587,14 +781,55 @@ struct CommitsTemplate<'a> {
     identity: &'a Option<User>,
     username: &'a str,
     branch: &'a Option<&'a str>,
+    tail: &'a str,
     name: &'a str,
     commits: &'a [Commit],
-    parent_count: usize,
+    query: &'a CommitsQuery,
+    next: &'a Option<String>,
+    refs: &'a RefPicker,
 }
 
-#[derive(serde::Deserialize)]
+#[derive(Clone, Default, serde::Serialize, serde::Deserialize)]
 pub struct CommitsQuery {
-    from: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    after: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    per_page: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    order: Option<history::Order>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    author: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    committer: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    since: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    until: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    q: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    merges: Option<history::Merges>,
+}
+
+impl CommitsQuery {
+    fn field(value: &Option<String>) -> &str {
+        value.as_deref().unwrap_or_default()
+    }
+
+    fn filter(&self, path: &str) -> history::Filter {
+        let non_empty = |value: &Option<String>| value.clone().filter(|inner| !inner.is_empty());
+        history::Filter {
+            author: non_empty(&self.author),
+            committer: non_empty(&self.committer),
+            since: non_empty(&self.since).and_then(|date| time_utils::parse_date(&date)),
+            until: non_empty(&self.until)
+                .and_then(|date| time_utils::parse_date(&date))
+                .map(|start_of_day| start_of_day + 86_399),
+            message: non_empty(&self.q),
+            merges: self.merges.unwrap_or_default(),
+            path: Some(path.to_owned()).filter(|path| !path.is_empty()),
+        }
+    }
 }
 
 pub async fn commits(
@@ -609,88 +844,86 @@ pub async fn commits(
     } else {
         (&path[0], &path[1], Some(path[2].as_str()))
     };
-
-    let identity = match identity {
-        Some(identity) => match identity.id() {
-            Ok(id) => state.database.find_user_from_id(&id).await,
-            Err(_) => todo!(),
-        },
-        None => None,
+    let tail = path
+        .get(3)
+        .map(|tail| tail.trim_end_matches('/'))
+        .unwrap_or("");
+
+    let context = match Context::load(&state, username, name, identity).await {
+        Ok(context) => context,
+        Err(response) => return Ok(response),
     };
+    let repo = &context.repo;
 
-    let user = state.database.find_user(username).await;
-
-    let repo = git2::Repository::open(name).unwrap();
-
-    let mut commits = Vec::new();
+    let start = match refs::resolve(repo, branch.unwrap_or("HEAD")) {
+        Ok(commit) => commit,
+        Err(error) => return Ok(HttpResponse::NotFound().body(error)),
+    };
 
-    match branch {
-        Some(branch) => {
-            let commit = match query.from.as_ref() {
-                Some(from) => {
-                    let oid = Oid::from_str(from).unwrap();
-                    repo.find_commit(oid).unwrap()
-                }
-                None => {
-                    if let Ok(branch) = repo.find_branch(branch, git2::BranchType::Local) {
-                        branch.get().peel_to_commit().unwrap()
-                    } else {
-                        repo.find_commit(Oid::from_str(branch).unwrap()).unwrap()
-                    }
-                }
-            };
-            push_log(&commit, &mut commits, Some(MAX_COMMIT_LEN));
-        }
-        None => {
-            if let Some(from) = query.from.as_ref() {
-                let commit = repo.find_commit(Oid::from_str(from).unwrap()).unwrap();
-                push_log(&commit, &mut commits, Some(MAX_COMMIT_LEN));
-            } else {
-                let mut revwalk = repo.revwalk().unwrap();
-                revwalk.push_head().unwrap();
-                for (_, commit) in revwalk.enumerate().take_while(|(i, _)| *i < MAX_COMMIT_LEN) {
-                    let oid = commit.unwrap();
-                    let commit = repo.find_commit(oid).unwrap();
-                    let author = commit.author();
-                    let offset = commit.time().offset_minutes();
-                    let seconds = commit.time().seconds();
-                    let relative_time = time_utils::to_relative_time(seconds);
-                    let datetime = time_utils::to_datetime(
-                        OffsetDateTime::from_unix_timestamp(seconds).unwrap(),
-                        Some(offset),
-                    );
-                    commits.push(Commit {
-                        id: commit.id().to_string(),
-                        message: commit.message().unwrap().to_string(),
-                        author: Author {
-                            name: author.name().unwrap_or_default().to_owned(),
-                            email: author.email().unwrap_or_default().to_owned(),
-                        },
-                        relative_time,
-                        datetime,
-                    });
-                }
-            }
-        }
+    if !tail.is_empty() && history::path_entry(&start, tail).is_none() {
+        let body = format!("the path '{tail}' does not exist in the given tree");
+        return Ok(HttpResponse::NotFound().body(body));
     }
 
-    let parent_count = {
-        let last_id = commits.last().unwrap();
-        let last_commit = repo
-            .find_commit(Oid::from_str(&last_id.id).unwrap())
-            .unwrap();
-        last_commit.parent_count()
+    let after = match query.after.as_deref().map(Oid::from_str) {
+        Some(Ok(oid)) => Some(oid),
+        Some(Err(_)) => return Ok(HttpResponse::BadRequest().body("invalid cursor")),
+        None => None,
+    };
+    let per_page = query
+        .per_page
+        .unwrap_or(history::DEFAULT_PAGE_SIZE)
+        .clamp(1, history::MAX_PAGE_SIZE);
+
+    let page = history::walk(
+        repo,
+        start.id(),
+        query.order.unwrap_or_default(),
+        &query.filter(tail),
+        after,
+        per_page,
+    )
+    .unwrap();
+
+    let commits: Vec<_> = page.commits.iter().map(log_entry).collect();
+
+    let next = page.next.map(|oid| {
+        let next_query = CommitsQuery {
+            after: Some(oid.to_string()),
+            ..query.0.clone()
+        };
+        serde_urlencoded::to_string(&next_query).unwrap_or_default()
+    });
+
+    let title = if tail.is_empty() {
+        "commits".to_owned()
+    } else {
+        format!("history of {tail}")
     };
 
+    let suffix = if tail.is_empty() {
+        String::new()
+    } else {
+        format!("/{tail}")
+    };
+    let refs = RefPicker::new(
+        repo,
+        branch.unwrap_or("HEAD"),
+        format!("/@{username}/{name}/commits"),
+        suffix,
+    );
     Ok(CommitsTemplate {
-        title: "commits",
+        refs: &refs,
+        title: &title,
         name,
         username,
-        user: &user,
+        user: &context.user,
         branch: &branch,
-        identity: &identity,
+        tail,
+        identity: &context.identity,
         commits: &commits,
-        parent_count,
+        query: &query,
+        next: &next,
     }
     .to_response())
 }
@@ -710,14 +943,109 @@ pub struct CommitTemplate<'a> {
     username: &'a str,
     name: &'a str,
     commit: DiffCommit,
+    query: &'a DiffQuery,
+    split: bool,
+    diff: &'a Diff,
+}
+
+/// A single file of a commit or compare diff, requested with `?file=` to
+/// expand a collapsed file in place.
+#[derive(Template)]
+#[template(path = "diff_fragment.html")]
+struct DiffFragmentTemplate<'a> {
+    rev: &'a str,
+    username: &'a str,
+    name: &'a str,
+    query: &'a DiffQuery,
+    split: bool,
     diff: &'a Diff,
 }
 
-pub async fn diff(path: web::Path<(String, String, String)>) -> Result<impl Responder> {
+#[derive(Clone, Default, serde::Serialize, serde::Deserialize)]
+pub struct DiffQuery {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    similarity: Option<u16>,
+    #[serde(default)]
+    w: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    context: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    algorithm: Option<diff::Algorithm>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    view: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    file: Option<String>,
+    /// One-based parent of a commit to diff against.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    parent: Option<usize>,
+    #[serde(default)]
+    combined: bool,
+}
+
+impl DiffQuery {
+    fn options(&self) -> diff::Options {
+        diff::Options {
+            similarity: self.similarity.unwrap_or(diff::DEFAULT_SIMILARITY),
+            ignore_whitespace: self.w,
+            context_lines: self.context,
+            algorithm: self.algorithm.unwrap_or_default(),
+            file: self.file.clone(),
+        }
+    }
+
+    /// What to diff `commit` against, or `None` when the requested parent
+    /// doesn't exist.
+    fn against(&self, commit: &git2::Commit) -> Option<diff::Against> {
+        if self.combined {
+            return Some(diff::Against::Combined);
+        }
+        match self.parent {
+            Some(parent) if parent == 0 || parent > commit.parent_count() => None,
+            Some(parent) => Some(diff::Against::Parent(parent - 1)),
+            None => Some(diff::Against::Parent(0)),
+        }
+    }
+
+    /// Query string loading only the file with `hash`, keeping the other
+    /// options.
+    fn with_file(&self, hash: &str) -> String {
+        let query = DiffQuery {
+            file: Some(hash.to_owned()),
+            ..self.clone()
+        };
+        serde_urlencoded::to_string(query).unwrap_or_default()
+    }
+
+    /// Resolves the diff layout from `?view=`, falling back to the preference
+    /// signed-in users save in their settings.
+    fn split(&self, identity: Option<&User>) -> bool {
+        match self.view.as_deref() {
+            Some("split") => true,
+            Some("unified") => false,
+            _ => identity.is_some_and(|user| user.split_diff),
+        }
+    }
+}
+
+pub async fn diff(
+    path: web::Path<(String, String, String)>,
+    query: web::Query<DiffQuery>,
+    state: web::Data<State>,
+    identity: Option<Identity>,
+) -> Result<impl Responder> {
     let (username, name, id) = path.into_inner();
 
-    let repo = git2::Repository::open(&name).unwrap();
-    let commit = repo.find_commit(Oid::from_str(&id).unwrap()).unwrap();
+    let context = match Context::load(&state, &username, &name, identity).await {
+        Ok(context) => context,
+        Err(response) => return Ok(response),
+    };
+    let split = query.split(context.identity.as_ref());
+
+    let repo = &context.repo;
+    let commit = match refs::resolve(repo, &id) {
+        Ok(commit) => commit,
+        Err(error) => return Ok(HttpResponse::NotFound().body(error)),
+    };
     let summary = commit.summary().unwrap_or_default();
     let time = commit.time();
 
@@ -726,7 +1054,22 @@ pub async fn diff(path: web::Path<(String, String, String)>) -> Result<impl Resp
         .map(|parent_id| parent_id.to_string())
         .collect();
 
-    let diff = Diff::new(&repo, &id);
+    let Some(against) = query.against(&commit) else {
+        return Ok(HttpResponse::NotFound().body(format!("commit {id} has no such parent")));
+    };
+    let diff = Diff::new(repo, &commit, against, &query.options());
+
+    if query.file.is_some() {
+        return Ok(DiffFragmentTemplate {
+            rev: &id,
+            username: &username,
+            name: &name,
+            query: &query,
+            split,
+            diff: &diff,
+        }
+        .to_response());
+    }
 
     let author = Author {
         name: commit
@@ -758,69 +1101,403 @@ pub async fn diff(path: web::Path<(String, String, String)>) -> Result<impl Resp
             relative_time,
             datetime,
         },
+        query: &query,
+        split,
         diff: &diff,
     }
     .to_response())
 }
 
-fn push_log(commit: &git2::Commit, log: &mut Vec<Commit>, limit: Option<usize>) {
-    if let Some(limit) = limit {
-        if log.len() == limit {
-            return;
+/// Lists `tree`, the directory `location.dir` of `commit`, directories first,
+/// with the last commit of every entry and where submodules and symbolic links
+/// point. `host` is the host the page was requested from, used to recognise
+/// submodules hosted here.
+fn list_entries(
+    repo: &git2::Repository,
+    commit: &git2::Commit,
+    tree: &git2::Tree,
+    location: &markup::Location,
+    host: &str,
+) -> Vec<Entry> {
+    let root = commit.tree().unwrap();
+    let submodules = Submodules::from_tree(repo, &root);
+    let last_commits = history::last_commits(repo, commit, location.dir, tree).ok();
+
+    let mut entries: Vec<Entry> = tree
+        .iter()
+        .filter_map(|entry| {
+            let entry_name = entry.name()?;
+            let path = if location.dir.is_empty() {
+                entry_name.to_owned()
+            } else {
+                format!("{}/{entry_name}", location.dir)
+            };
+            let (kind, link) = match (entry.kind()?, entry.filemode()) {
+                (git2::ObjectType::Tree, _) => (Kind::Tree, None),
+                (git2::ObjectType::Commit, _) => (
+                    Kind::Submodule,
+                    Some(submodule_link(
+                        submodules.get(&path),
+                        entry.id(),
+                        location,
+                        host,
+                    )),
+                ),
+                (_, FILEMODE_LINK) => (
+                    Kind::Symlink,
+                    repo.find_blob(entry.id())
+                        .ok()
+                        .map(|blob| symlink_link(blob.content(), &root, location)),
+                ),
+                _ => (Kind::File, None),
+            };
+            let href = match kind {
+                Kind::Submodule => link.as_ref().and_then(|link| link.href.clone()),
+                Kind::Tree => Some(format!(
+                    "/@{}/{}/tree/{}/{path}",
+                    location.username, location.name, location.rev
+                )),
+                Kind::File | Kind::Symlink => Some(format!(
+                    "/@{}/{}/blob/{}/{path}",
+                    location.username, location.name, location.rev
+                )),
+            };
+            let last_commit = last_commits
+                .as_ref()
+                .and_then(|last_commits| last_commits.get(entry_name))
+                .and_then(|id| repo.find_commit(*id).ok())
+                .map(|commit| log_entry(&commit));
+            Some(Entry {
+                name: entry_name.to_owned(),
+                kind,
+                href,
+                link,
+                last_commit,
+            })
+        })
+        .collect();
+    entries.sort_by_key(|e| e.kind != Kind::Tree);
+    entries
+}
+
+const FILEMODE_LINK: i32 = 0o120000;
+
+fn submodule_link(
+    submodule: Option<&Submodule>,
+    id: Oid,
+    location: &markup::Location,
+    host: &str,
+) -> Link {
+    let commit = id.to_string();
+    let Some(submodule) = submodule else {
+        return Link {
+            target: String::new(),
+            commit: Some(commit),
+            href: None,
+        };
+    };
+    let href = match submodule.local_path(location.username, location.name, host) {
+        Some(path) => Some(format!("{path}/tree/{commit}")),
+        None if submodule.url.starts_with("https://") || submodule.url.starts_with("http://") => {
+            Some(submodule.url.clone())
+        }
+        None => None,
+    };
+    Link {
+        target: submodule.url.clone(),
+        commit: Some(commit),
+        href,
+    }
+}
+
+/// Links a symbolic link whose `target` stays inside the repository to the
+/// tree or blob it points at.
+fn symlink_link(target: &[u8], root: &git2::Tree, location: &markup::Location) -> Link {
+    let target = String::from_utf8_lossy(target).into_owned();
+    let href = resolve_symlink(location.dir, &target).and_then(|path| {
+        let page = match root.get_path(Path::new(&path)).ok()?.kind()? {
+            git2::ObjectType::Tree => "tree",
+            git2::ObjectType::Blob => "blob",
+            _ => return None,
+        };
+        let markup::Location {
+            username,
+            name,
+            rev,
+            ..
+        } = location;
+        Some(format!("/@{username}/{name}/{page}/{rev}/{path}"))
+    });
+    Link {
+        target,
+        commit: None,
+        href,
+    }
+}
+
+/// Resolves a symbolic link target relative to `dir`. Absolute targets and
+/// targets climbing out of the repository can't be followed.
+fn resolve_symlink(dir: &str, target: &str) -> Option<String> {
+    if target.starts_with('/') {
+        return None;
+    }
+    let mut parts: Vec<&str> = dir.split('/').filter(|part| !part.is_empty()).collect();
+    for part in target.split('/') {
+        match part {
+            "" | "." => {}
+            ".." => {
+                parts.pop()?;
+            }
+            part => parts.push(part),
         }
     }
+    (!parts.is_empty()).then(|| parts.join("/"))
+}
+
+fn log_entry(commit: &git2::Commit) -> Commit {
     let offset = commit.time().offset_minutes();
     let relative_time = time_utils::to_relative_time(commit.time().seconds());
     let datetime = time_utils::to_datetime(
         OffsetDateTime::from_unix_timestamp(commit.time().seconds()).unwrap(),
         Some(offset),
     );
-    log.push(Commit {
+    Commit {
         id: commit.id().to_string(),
-        message: commit.summary().unwrap().to_string(),
+        message: commit.summary().unwrap_or_default().to_string(),
         author: Author {
-            name: commit.author().name().unwrap().to_owned(),
-            email: commit.author().email().unwrap().to_owned(),
+            name: commit.author().name().unwrap_or_default().to_owned(),
+            email: commit.author().email().unwrap_or_default().to_owned(),
         },
         relative_time,
         datetime,
-    });
-    let Ok(parent) = commit.parent(0) else {
-        return;
+    }
+}
+
+const MAX_COMPARE_COMMITS: usize = 250;
+
+#[derive(Template)]
+#[template(path = "compare.html")]
+struct CompareTemplate<'a> {
+    username: &'a str,
+    name: &'a str,
+    base: &'a str,
+    head: &'a str,
+    three_dot: bool,
+    rev: &'a str,
+    commits: &'a [Commit],
+    truncated: bool,
+    query: &'a DiffQuery,
+    split: bool,
+    diff: &'a Diff,
+}
+
+/// Compares `{base}...{head}` (against their merge base, as with `git diff
+/// A...B`) or `{base}..{head}` (directly between the two revisions). A bare
+/// revision is compared with `HEAD`.
+pub async fn compare(
+    path: web::Path<(String, String, String)>,
+    query: web::Query<DiffQuery>,
+    state: web::Data<State>,
+    identity: Option<Identity>,
+) -> Result<impl Responder> {
+    let (username, name, spec) = path.into_inner();
+
+    let context = match Context::load(&state, &username, &name, identity).await {
+        Ok(context) => context,
+        Err(response) => return Ok(response),
+    };
+    let split = query.split(context.identity.as_ref());
+    let repo = &context.repo;
+
+    let range = match Range::parse(repo, &spec) {
+        Ok(range) => range,
+        Err(message) => return Ok(HttpResponse::NotFound().body(message)),
+    };
+
+    let oids = range.commits(repo);
+    let truncated = oids.len() > MAX_COMPARE_COMMITS;
+    let commits: Vec<_> = oids
+        .into_iter()
+        .take(MAX_COMPARE_COMMITS)
+        .filter_map(|oid| repo.find_commit(oid).ok())
+        .map(|commit| log_entry(&commit))
+        .collect();
+
+    let base_tree = range.diff_base.tree().unwrap();
+    let head_tree = range.head_commit.tree().unwrap();
+    let diff = Diff::from_trees(repo, Some(&base_tree), &head_tree, &query.options());
+
+    let rev = range.head_commit.id().to_string();
+
+    if query.file.is_some() {
+        return Ok(DiffFragmentTemplate {
+            rev: &rev,
+            username: &username,
+            name: &name,
+            query: &query,
+            split,
+            diff: &diff,
+        }
+        .to_response());
+    }
+
+    Ok(CompareTemplate {
+        username: &username,
+        name: &name,
+        base: range.base,
+        head: range.head,
+        three_dot: range.three_dot,
+        rev: &rev,
+        commits: &commits,
+        truncated,
+        query: &query,
+        split,
+        diff: &diff,
+    }
+    .to_response())
+}
+
+/// A `base...head` (from the merge base) or `base..head` (direct) range; a
+/// bare revision is compared against `HEAD`.
+struct Range<'r, 's> {
+    base: &'s str,
+    head: &'s str,
+    three_dot: bool,
+    base_commit: git2::Commit<'r>,
+    head_commit: git2::Commit<'r>,
+    /// Commit the diff starts from.
+    diff_base: git2::Commit<'r>,
+}
+
+impl<'r, 's> Range<'r, 's> {
+    fn parse(repo: &'r git2::Repository, spec: &'s str) -> Result<Self, String> {
+        let (base, head, three_dot) = if let Some((base, head)) = spec.split_once("...") {
+            (base, head, true)
+        } else if let Some((base, head)) = spec.split_once("..") {
+            (base, head, false)
+        } else {
+            ("HEAD", spec, true)
+        };
+
+        let base_commit = refs::resolve(repo, base)?;
+        let head_commit = refs::resolve(repo, head)?;
+
+        let diff_base = if three_dot {
+            repo.merge_base(base_commit.id(), head_commit.id())
+                .and_then(|oid| repo.find_commit(oid))
+                .map_err(|_| format!("'{base}' and '{head}' have no common ancestor"))?
+        } else {
+            base_commit.clone()
+        };
+
+        Ok(Self {
+            base,
+            head,
+            three_dot,
+            base_commit,
+            head_commit,
+            diff_base,
+        })
+    }
+
+    /// Commits reachable from the head but not from the base, oldest first.
+    fn commits(&self, repo: &git2::Repository) -> Vec<Oid> {
+        let mut revwalk = repo.revwalk().unwrap();
+        revwalk
+            .set_sorting(git2::Sort::TOPOLOGICAL | git2::Sort::TIME | git2::Sort::REVERSE)
+            .unwrap();
+        revwalk.push(self.head_commit.id()).unwrap();
+        revwalk.hide(self.base_commit.id()).unwrap();
+        revwalk.filter_map(|oid| oid.ok()).collect()
+    }
+}
+
+/// `/commit/{id}.patch` and `/commit/{id}.diff`.
+pub async fn diff_download(
+    path: web::Path<(String, String, String, diff::Format)>,
+    query: web::Query<DiffQuery>,
+    state: web::Data<State>,
+    identity: Option<Identity>,
+) -> Result<impl Responder> {
+    let (username, name, id, format) = path.into_inner();
+
+    let context = match Context::load(&state, &username, &name, identity).await {
+        Ok(context) => context,
+        Err(response) => return Ok(response),
+    };
+    let repo = &context.repo;
+    let commit = match refs::resolve(repo, &id) {
+        Ok(commit) => commit,
+        Err(error) => return Ok(HttpResponse::NotFound().body(error)),
     };
-    push_log(&parent, log, limit);
+
+    let body = match format {
+        diff::Format::Patch => diff::email(repo, &commit, 1, 1),
+        diff::Format::Diff => {
+            let parent = query.parent.unwrap_or(1);
+            if parent == 0 || parent > commit.parent_count().max(1) {
+                return Ok(HttpResponse::NotFound().body(format!("commit {id} has no such parent")));
+            }
+            let old_tree = commit
+                .parent(parent - 1)
+                .and_then(|parent| parent.tree())
+                .ok();
+            let new_tree = commit.tree().unwrap();
+            diff::raw(repo, old_tree.as_ref(), &new_tree, &query.options())
+        }
+    };
+    Ok(download(body))
 }
 
-#[get("/delete/{name}")]
-pub async fn delete(
-    path: web::Path<String>,
+/// `/compare/{spec}.patch` renders every non-merge commit of the range as a
+/// numbered series for `git am`; `.diff` is the combined change.
+pub async fn compare_download(
+    path: web::Path<(String, String, String, diff::Format)>,
+    query: web::Query<DiffQuery>,
     state: web::Data<State>,
     identity: Option<Identity>,
-) -> impl Responder {
-    let name = path.into_inner();
+) -> Result<impl Responder> {
+    let (username, name, spec, format) = path.into_inner();
 
-    let identity = match identity {
-        Some(identity) => match identity.id() {
-            Ok(id) => state.database.find_user_from_id(&id).await,
-            Err(_) => todo!(),
-        },
-        None => None,
+    let context = match Context::load(&state, &username, &name, identity).await {
+        Ok(context) => context,
+        Err(response) => return Ok(response),
+    };
+    let repo = &context.repo;
+    let range = match Range::parse(repo, &spec) {
+        Ok(range) => range,
+        Err(message) => return Ok(HttpResponse::NotFound().body(message)),
     };
 
-    let result = state.database.delete_repository(&identity, &name).await;
-    match result {
-        Ok(_) => {
-            let user = identity.unwrap();
-            state
-                .database
-                .add_user_log(&user, Event::RepositoryDelete, Some(name))
-                .await;
-            "Ok"
+    let body = match format {
+        diff::Format::Patch => {
+            let commits: Vec<_> = range
+                .commits(repo)
+                .into_iter()
+                .filter_map(|oid| repo.find_commit(oid).ok())
+                .filter(|commit| commit.parent_count() <= 1)
+                .collect();
+            commits
+                .iter()
+                .enumerate()
+                .map(|(position, commit)| diff::email(repo, commit, position + 1, commits.len()))
+                .collect::<Result<Vec<_>, _>>()
+                .map(|mails| mails.concat())
         }
-        Err(e) => match e {
-            crate::database::Error::Unauthorized => "Unauthorized",
-            crate::database::Error::NotFound => "Not Found",
-            _ => unreachable!(),
-        },
+        diff::Format::Diff => {
+            let old_tree = range.diff_base.tree().unwrap();
+            let new_tree = range.head_commit.tree().unwrap();
+            diff::raw(repo, Some(&old_tree), &new_tree, &query.options())
+        }
+    };
+    Ok(download(body))
+}
+
+fn download(body: Result<Vec<u8>, git2::Error>) -> HttpResponse {
+    match body {
+        Ok(body) => HttpResponse::Ok()
+            .content_type("text/plain; charset=utf-8")
+            .body(body),
+        Err(error) => HttpResponse::InternalServerError().body(error.message().to_owned()),
     }
 }
@@ -0,0 +1,481 @@
+use std::path::Path;
+
+use actix_identity::Identity;
+use actix_web::{web, HttpResponse, Responder};
+use askama::Template;
+use askama_actix::TemplateToResponse;
+use git2::BranchType;
+
+use crate::{
+    collaborators::{self, Member},
+    disk,
+    model::{self, Event, Role, User},
+    refs,
+    repository::{self, Context},
+    State,
+};
+
+/// Most topics a repository can be tagged with.
+const MAX_TOPICS: usize = 20;
+
+#[derive(Template)]
+#[template(path = "repository/settings.html")]
+struct SettingsTemplate<'a> {
+    title: &'a str,
+    identity: &'a Option<User>,
+    username: &'a str,
+    name: &'a str,
+    repository: &'a model::Repository,
+    default_branch: &'a str,
+    branches: &'a [String],
+    /// Topics as edited in a single field.
+    topics: &'a str,
+    /// Whether collaborators and the danger zone are shown.
+    can_admin: bool,
+    /// Whether the signed in user owns the repository and may transfer it.
+    can_transfer: bool,
+    members: &'a [Member],
+}
+
+/// Repository settings, for maintainers and admins.
+pub async fn index(
+    path: web::Path<(String, String)>,
+    state: web::Data<State>,
+    identity: Option<Identity>,
+) -> impl Responder {
+    let (username, name) = path.into_inner();
+    let context = match Context::load(&state, &username, &name, identity).await {
+        Ok(context) => context,
+        Err(response) => return response,
+    };
+    if let Err(response) = context.require(Role::Maintain) {
+        return response;
+    }
+
+    let branches: Vec<String> = context
+        .repo
+        .branches(Some(BranchType::Local))
+        .into_iter()
+        .flatten()
+        .flatten()
+        .filter_map(|(branch, _)| branch.name().ok().flatten().map(str::to_owned))
+        .collect();
+    let default_branch = context.default_branch().unwrap_or_default();
+    let topics = context.repository.topics.join(", ");
+    let members = collaborators::members(&state, &context.repository).await;
+    let title = format!("@{username}/{name}/settings");
+
+    SettingsTemplate {
+        title: &title,
+        identity: &context.identity,
+        username: &username,
+        name: &name,
+        repository: &context.repository,
+        default_branch: &default_branch,
+        branches: &branches,
+        topics: &topics,
+        can_admin: context.can(Role::Admin),
+        can_transfer: context
+            .identity
+            .as_ref()
+            .zip(context.user.as_ref())
+            .is_some_and(|(user, owner)| owns(user, owner)),
+        members: &members,
+    }
+    .to_response()
+}
+
+#[derive(Debug, serde::Deserialize)]
+pub struct DefaultBranchForm {
+    branch: String,
+}
+
+/// Stores the default branch and points the bare repository's HEAD at it, so
+/// clones check it out too. An empty repository may pick any valid name.
+pub async fn default_branch(
+    path: web::Path<(String, String)>,
+    state: web::Data<State>,
+    identity: Option<Identity>,
+    form: web::Form<DefaultBranchForm>,
+) -> impl Responder {
+    let (username, name) = path.into_inner();
+    let context = match Context::load(&state, &username, &name, identity).await {
+        Ok(context) => context,
+        Err(response) => return response,
+    };
+    let user = match context.require(Role::Maintain) {
+        Ok(user) => user,
+        Err(response) => return response,
+    };
+
+    let branch = form.branch.trim();
+    let reference = format!("refs/heads/{branch}");
+    if !git2::Reference::is_valid_name(&reference) {
+        return HttpResponse::BadRequest().body(format!("'{branch}' is not a valid branch name"));
+    }
+    let exists = context.repo.find_branch(branch, BranchType::Local).is_ok();
+    if !exists && !refs::is_empty(&context.repo) {
+        return HttpResponse::NotFound().body(format!("no branch named '{branch}'"));
+    }
+    if let Err(error) = context.repo.set_head(&reference) {
+        return HttpResponse::InternalServerError().body(error.message().to_owned());
+    }
+
+    state
+        .database
+        .set_default_branch(&context.repository, branch)
+        .await;
+    state
+        .database
+        .add_user_log(
+            user,
+            Event::RepositoryDefaultBranch,
+            Some(format!("{name}:{branch}")),
+        )
+        .await;
+    HttpResponse::SeeOther()
+        .insert_header(("Location", format!("/@{username}/{name}/settings")))
+        .finish()
+}
+
+#[derive(Debug, serde::Deserialize)]
+pub struct UpdateForm {
+    description: String,
+    website: String,
+    /// Separated by commas or whitespace.
+    topics: String,
+    visibility: String,
+}
+
+pub async fn update(
+    path: web::Path<(String, String)>,
+    state: web::Data<State>,
+    identity: Option<Identity>,
+    form: web::Form<UpdateForm>,
+) -> impl Responder {
+    let (username, name) = path.into_inner();
+    let context = match Context::load(&state, &username, &name, identity).await {
+        Ok(context) => context,
+        Err(response) => return response,
+    };
+    let user = match context.require(Role::Maintain) {
+        Ok(user) => user,
+        Err(response) => return response,
+    };
+
+    if !matches!(form.visibility.as_str(), "public" | "private") {
+        return HttpResponse::BadRequest().body("visibility must be public or private");
+    }
+    if form.visibility != context.repository.visibility && !context.can(Role::Admin) {
+        return HttpResponse::Forbidden().body("only admins can change the visibility");
+    }
+    let website = form.website.trim();
+    let is_http = website.starts_with("https://") || website.starts_with("http://");
+    if !website.is_empty() && !is_http {
+        return HttpResponse::BadRequest().body("the website must be an http or https URL");
+    }
+    let mut topics: Vec<String> = Vec::new();
+    for topic in form
+        .topics
+        .split(|inner: char| inner == ',' || inner.is_whitespace())
+        .filter(|inner| !inner.is_empty())
+    {
+        let topic = topic.to_lowercase();
+        if !is_valid_topic(&topic) {
+            return HttpResponse::BadRequest().body(format!(
+                "'{topic}' is not a valid topic; use letters, numbers and hyphens"
+            ));
+        }
+        if !topics.contains(&topic) {
+            topics.push(topic);
+        }
+    }
+    if topics.len() > MAX_TOPICS {
+        return HttpResponse::BadRequest().body(format!("at most {MAX_TOPICS} topics are allowed"));
+    }
+
+    state
+        .database
+        .update_repository(
+            &context.repository,
+            form.description.trim(),
+            website,
+            &topics,
+            &form.visibility,
+        )
+        .await;
+    state
+        .database
+        .add_user_log(user, Event::RepositoryUpdate, Some(name.clone()))
+        .await;
+    HttpResponse::SeeOther()
+        .insert_header(("Location", format!("/@{username}/{name}/settings")))
+        .finish()
+}
+
+fn is_valid_topic(topic: &str) -> bool {
+    topic.len() <= 35
+        && !topic.starts_with('-')
+        && topic
+            .chars()
+            .all(|inner| inner.is_ascii_lowercase() || inner.is_ascii_digit() || inner == '-')
+}
+
+#[derive(Debug, serde::Deserialize)]
+pub struct RenameForm {
+    name: String,
+}
+
+/// Renames the repository and its directory. The old name keeps working: web
+/// URLs are redirected and a symlink is left for clones and remotes.
+pub async fn rename(
+    path: web::Path<(String, String)>,
+    state: web::Data<State>,
+    identity: Option<Identity>,
+    form: web::Form<RenameForm>,
+) -> impl Responder {
+    let (username, name) = path.into_inner();
+    let context = match Context::load(&state, &username, &name, identity).await {
+        Ok(context) => context,
+        Err(response) => return response,
+    };
+    let user = match context.require(Role::Admin) {
+        Ok(user) => user,
+        Err(response) => return response,
+    };
+    let Some(owner) = context.user.as_ref() else {
+        return HttpResponse::NotFound().finish();
+    };
+
+    let new_name = form.name.trim();
+    if !repository::is_valid_name(new_name) {
+        return HttpResponse::BadRequest().body(format!(
+            "'{new_name}' is not a valid name; use letters, numbers, '-', '_' and '.'"
+        ));
+    }
+    if new_name == name {
+        return HttpResponse::SeeOther()
+            .insert_header(("Location", format!("/@{username}/{name}/settings")))
+            .finish();
+    }
+    // Only a symlink an earlier rename of this repository left behind can be
+    // replaced; anything else at the new name belongs to someone else.
+    let new_path = Path::new(new_name);
+    let redirects = state.database.find_redirects(&context.repository).await;
+    let own_link = new_path.is_symlink()
+        && redirects.iter().any(|redirect| redirect.name == new_name)
+        && std::fs::canonicalize(new_path).ok() == std::fs::canonicalize(&name).ok();
+    if new_path.symlink_metadata().is_ok() && !own_link {
+        return HttpResponse::Conflict().body(format!("the name '{new_name}' is already taken"));
+    }
+
+    // The directory moves first so that a failure leaves the database as it
+    // was, and is moved back if the database refuses the new name.
+    let link_target = own_link
+        .then(|| std::fs::read_link(new_path).ok())
+        .flatten();
+    let restore_link = || {
+        if let Some(target) = link_target.as_ref() {
+            let linked = std::os::unix::fs::symlink(target, new_path);
+            debug_assert!(linked.is_ok());
+        }
+    };
+    if own_link && std::fs::remove_file(new_path).is_err() {
+        return HttpResponse::InternalServerError().body("the repository could not be moved");
+    }
+    if std::fs::rename(&name, new_path).is_err() {
+        restore_link();
+        return HttpResponse::InternalServerError().body("the repository could not be moved");
+    }
+    if state
+        .database
+        .move_repository(&context.repository, owner, new_name)
+        .await
+        .is_err()
+    {
+        let moved_back = std::fs::rename(new_path, &name);
+        debug_assert!(moved_back.is_ok());
+        restore_link();
+        return HttpResponse::Conflict()
+            .body(format!("a repository named '{new_name}' already exists"));
+    }
+    let linked = std::os::unix::fs::symlink(new_name, &name);
+    debug_assert!(linked.is_ok());
+
+    state
+        .database
+        .add_user_log(
+            user,
+            Event::RepositoryRename,
+            Some(format!("{name}:{new_name}")),
+        )
+        .await;
+    HttpResponse::SeeOther()
+        .insert_header(("Location", format!("/@{username}/{new_name}/settings")))
+        .finish()
+}
+
+#[derive(Debug, serde::Deserialize)]
+pub struct TransferForm {
+    owner: String,
+    /// The repository name, typed to confirm.
+    confirm: String,
+}
+
+/// Whether `user` is `owner` or an owner of the organization `owner`.
+fn owns(user: &User, owner: &User) -> bool {
+    user._id == owner._id
+        || owner
+            .organization
+            .as_ref()
+            .is_some_and(|organization| organization.is_owner(Some(user)))
+}
+
+/// Hands the repository over to its owner's own account or one of their
+/// organizations, redirecting from the old owner. Only the owner can do this,
+/// not admin collaborators, and only to where they could have created it.
+pub async fn transfer(
+    path: web::Path<(String, String)>,
+    state: web::Data<State>,
+    identity: Option<Identity>,
+    form: web::Form<TransferForm>,
+) -> impl Responder {
+    let (username, name) = path.into_inner();
+    let context = match Context::load(&state, &username, &name, identity).await {
+        Ok(context) => context,
+        Err(response) => return response,
+    };
+    let user = match context.require(Role::Admin) {
+        Ok(user) => user,
+        Err(response) => return response,
+    };
+    if !context.user.as_ref().is_some_and(|owner| owns(user, owner)) {
+        return HttpResponse::Forbidden().body("only the owner can transfer this repository");
+    }
+
+    if form.confirm.trim() != name {
+        return HttpResponse::BadRequest().body("type the name of the repository to confirm");
+    }
+    let Some(owner) = state.database.find_user(form.owner.trim()).await else {
+        return HttpResponse::NotFound().body(format!("no user named '{}'", form.owner.trim()));
+    };
+    if !owns(user, &owner) {
+        return HttpResponse::Forbidden().body(format!(
+            "repositories can only be transferred to you or organizations you own, not @{}",
+            owner.username
+        ));
+    }
+    if owner._id == context.repository.user_id {
+        return HttpResponse::BadRequest()
+            .body(format!("@{} already owns this repository", owner.username));
+    }
+    if state
+        .database
+        .move_repository(&context.repository, &owner, &name)
+        .await
+        .is_err()
+    {
+        return HttpResponse::Conflict()
+            .body(format!("another repository is already named '{name}'"));
+    }
+
+    state
+        .database
+        .add_user_log(
+            user,
+            Event::RepositoryTransfer,
+            Some(format!("{name}:{}", owner.username)),
+        )
+        .await;
+    HttpResponse::SeeOther()
+        .insert_header(("Location", format!("/@{}/{name}", owner.username)))
+        .finish()
+}
+
+#[derive(Debug, serde::Deserialize)]
+pub struct ArchiveForm {
+    archived: bool,
+}
+
+pub async fn archive(
+    path: web::Path<(String, String)>,
+    state: web::Data<State>,
+    identity: Option<Identity>,
+    form: web::Form<ArchiveForm>,
+) -> impl Responder {
+    let (username, name) = path.into_inner();
+    let context = match Context::load(&state, &username, &name, identity).await {
+        Ok(context) => context,
+        Err(response) => return response,
+    };
+    let user = match context.require(Role::Admin) {
+        Ok(user) => user,
+        Err(response) => return response,
+    };
+
+    state
+        .database
+        .set_archived(&context.repository, form.archived)
+        .await;
+    let event = if form.archived {
+        Event::RepositoryArchive
+    } else {
+        Event::RepositoryUnarchive
+    };
+    state
+        .database
+        .add_user_log(user, event, Some(name.clone()))
+        .await;
+    HttpResponse::SeeOther()
+        .insert_header(("Location", format!("/@{username}/{name}/settings")))
+        .finish()
+}
+
+#[derive(Debug, serde::Deserialize)]
+pub struct DeleteForm {
+    /// The repository name, typed to confirm.
+    confirm: String,
+}
+
+/// Deletes the repository along with its directory and redirects.
+pub async fn delete(
+    path: web::Path<(String, String)>,
+    state: web::Data<State>,
+    identity: Option<Identity>,
+    form: web::Form<DeleteForm>,
+) -> impl Responder {
+    let (username, name) = path.into_inner();
+    let context = match Context::load(&state, &username, &name, identity).await {
+        Ok(context) => context,
+        Err(response) => return response,
+    };
+    let user = match context.require(Role::Admin) {
+        Ok(user) => user,
+        Err(response) => return response,
+    };
+
+    if form.confirm.trim() != name {
+        return HttpResponse::BadRequest().body("type the name of the repository to confirm");
+    }
+    let redirects = state.database.find_redirects(&context.repository).await;
+    if state
+        .database
+        .delete_repository(&context.user, &name)
+        .await
+        .is_err()
+    {
+        return HttpResponse::NotFound().finish();
+    }
+
+    if !state.database.directory_in_use(&name).await {
+        disk::remove_repository(&name, &redirects);
+    }
+
+    state
+        .database
+        .add_user_log(user, Event::RepositoryDelete, Some(name))
+        .await;
+    HttpResponse::SeeOther()
+        .insert_header(("Location", format!("/@{}", user.username)))
+        .finish()
+}
@@ -0,0 +1,120 @@
+use std::path::Path;
+
+#[derive(Debug)]
+pub struct Submodule {
+    pub path: String,
+    pub url: String,
+}
+
+/// Submodules declared in the `.gitmodules` file at the root of a tree.
+///
+/// `Repository::submodules` reads the file from the working directory, which
+/// bare repositories don't have, so it is parsed from the tree being browsed
+/// instead. Only the `path` and `url` keys are used.
+#[derive(Debug, Default)]
+pub struct Submodules {
+    submodules: Vec<Submodule>,
+}
+
+impl Submodules {
+    pub fn from_tree(repo: &git2::Repository, tree: &git2::Tree) -> Self {
+        let Ok(entry) = tree.get_path(Path::new(".gitmodules")) else {
+            return Self::default();
+        };
+        let Ok(blob) = repo.find_blob(entry.id()) else {
+            return Self::default();
+        };
+        Self::parse(&String::from_utf8_lossy(blob.content()))
+    }
+
+    pub fn parse(input: &str) -> Self {
+        let mut submodules: Vec<Submodule> = Vec::new();
+        let mut in_submodule = false;
+        for line in input.lines().map(str::trim) {
+            if line.is_empty() || line.starts_with(['#', ';']) {
+                continue;
+            }
+            if let Some(header) = line.strip_prefix('[') {
+                let header = header.trim_end_matches(']').trim();
+                let name = header
+                    .strip_prefix("submodule")
+                    .map(|name| name.trim().trim_matches('"'));
+                in_submodule = name.is_some();
+                if let Some(name) = name {
+                    submodules.push(Submodule {
+                        path: name.to_owned(),
+                        url: String::new(),
+                    });
+                }
+                continue;
+            }
+            let Some(submodule) = submodules.last_mut().filter(|_| in_submodule) else {
+                continue;
+            };
+            let Some((key, value)) = line.split_once('=') else {
+                continue;
+            };
+            let value = value.trim().trim_matches('"').to_owned();
+            match key.trim().to_ascii_lowercase().as_str() {
+                "path" => submodule.path = value.trim_matches('/').to_owned(),
+                "url" => submodule.url = value,
+                _ => {}
+            }
+        }
+        Self { submodules }
+    }
+
+    /// The submodule checked out at `path`; later declarations win like in git.
+    pub fn get(&self, path: &str) -> Option<&Submodule> {
+        self.submodules
+            .iter()
+            .rev()
+            .find(|submodule| submodule.path == path)
+    }
+}
+
+impl Submodule {
+    /// The `/@user/name` path of the submodule when it is hosted here: its URL
+    /// is relative to the superproject at `/@username/name`, or points at
+    /// `host`.
+    pub fn local_path(&self, username: &str, name: &str, host: &str) -> Option<String> {
+        let url = self.url.as_str();
+        let path = if url.starts_with("./") || url.starts_with("../") {
+            let mut segments = vec![format!("@{username}"), name.to_owned()];
+            for segment in url.split('/') {
+                match segment {
+                    "" | "." => {}
+                    ".." => {
+                        segments.pop()?;
+                    }
+                    segment => segments.push(segment.to_owned()),
+                }
+            }
+            segments.join("/")
+        } else {
+            let (url_host, path) = match url.split_once("://") {
+                Some((_, rest)) => rest.split_once('/')?,
+                // scp-like syntax, `git@host:path`.
+                None => url.split_once(':')?,
+            };
+            let url_host = url_host.rsplit('@').next()?;
+            if strip_port(url_host) != strip_port(host) {
+                return None;
+            }
+            path.to_owned()
+        };
+
+        let path = path.trim_matches('/');
+        let path = path.strip_suffix(".git").unwrap_or(path);
+        let (owner, repository) = path.split_once('/')?;
+        let owner = owner.strip_prefix('@').unwrap_or(owner);
+        if owner.is_empty() || repository.is_empty() || repository.contains('/') {
+            return None;
+        }
+        Some(format!("/@{owner}/{repository}"))
+    }
+}
+
+fn strip_port(host: &str) -> &str {
+    host.rsplit_once(':').map_or(host, |(host, _)| host)
+}
@@ -1,4 +1,4 @@
-use time::{format_description, OffsetDateTime, UtcOffset};
+use time::{format_description, Date, OffsetDateTime, UtcOffset};
 
 pub fn to_relative_time(input: i64) -> String {
     let now = time::OffsetDateTime::now_utc().unix_timestamp();
@@ -72,3 +72,10 @@ pub fn to_datetime_format(time: OffsetDateTime, offset: Option<i32>, format: &st
     };
     t.format(&format).unwrap()
 }
+
+/// Parses a `YYYY-MM-DD` date into the unix timestamp of its midnight in UTC.
+pub fn parse_date(input: &str) -> Option<i64> {
+    let format = format_description::parse_borrowed::<2>("[year]-[month]-[day]").unwrap();
+    let date = Date::parse(input, &format).ok()?;
+    Some(date.midnight().assume_utc().unix_timestamp())
+}
@@ -0,0 +1,567 @@
+use std::{
+    collections::HashMap,
+    io::{Cursor, Read},
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use actix_identity::Identity;
+use actix_multipart::Multipart;
+use actix_web::{web, HttpResponse, Responder};
+use askama::Template;
+use askama_actix::TemplateToResponse;
+use bson::oid::ObjectId;
+use futures::TryStreamExt;
+use git2::{BranchType, ObjectType, Oid, Tree};
+
+use crate::{
+    credentials,
+    editor::{self, Change, Target},
+    model::{self, Event, User},
+    refs,
+    repository::Context,
+    State,
+};
+
+/// Largest file that can be uploaded, also applied to files inside archives.
+const MAX_FILE_SIZE: usize = 10_000_000;
+/// Largest total size of the files committed by one upload.
+const MAX_UPLOAD_SIZE: usize = 50_000_000;
+/// Most files one upload may add, counting the contents of archives.
+const MAX_UPLOAD_FILES: usize = 1000;
+/// How long the files of a dry run wait for confirmation.
+const PENDING_TTL: Duration = Duration::from_secs(60 * 60);
+
+const FILEMODE_BLOB: i32 = 0o100644;
+
+/// Files of dry runs kept in memory until they are confirmed, so nothing is
+/// written to a repository for an upload that is abandoned. Each user has at
+/// most one waiting.
+#[derive(Clone, Default)]
+pub struct Pending(Arc<Mutex<HashMap<String, Stash>>>);
+
+struct Stash {
+    user: ObjectId,
+    repository: ObjectId,
+    created: Instant,
+    blobs: HashMap<Oid, Vec<u8>>,
+}
+
+impl Pending {
+    /// Keeps `stash` in place of the user's previous one, returning the token
+    /// the confirmation refers to it by.
+    fn insert(&self, stash: Stash) -> String {
+        let token = credentials::random_string(32);
+        let mut pending = self.0.lock().unwrap();
+        pending
+            .retain(|_, inner| inner.user != stash.user && inner.created.elapsed() < PENDING_TTL);
+        pending.insert(token.clone(), stash);
+        token
+    }
+
+    /// Removes the blobs stored under `token` if they were uploaded by `user`
+    /// to `repository`.
+    fn take(&self, token: &str, user: ObjectId, repository: ObjectId) -> HashMap<Oid, Vec<u8>> {
+        let mut pending = self.0.lock().unwrap();
+        pending.retain(|_, inner| inner.created.elapsed() < PENDING_TTL);
+        match pending.get(token) {
+            Some(stash) if stash.user == user && stash.repository == repository => pending
+                .remove(token)
+                .map(|stash| stash.blobs)
+                .unwrap_or_default(),
+            _ => HashMap::new(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Status {
+    Added,
+    Overwritten,
+    Unchanged,
+}
+
+impl Status {
+    fn as_str(&self) -> &str {
+        match self {
+            Status::Added => "added",
+            Status::Overwritten => "overwritten",
+            Status::Unchanged => "unchanged",
+        }
+    }
+}
+
+struct Uploaded {
+    path: String,
+    oid: Oid,
+    size: String,
+    status: Status,
+}
+
+#[derive(Template)]
+#[template(path = "upload.html")]
+struct UploadTemplate<'a> {
+    title: &'a str,
+    identity: &'a Option<User>,
+    repository: &'a model::Repository,
+    username: &'a str,
+    name: &'a str,
+    branch: &'a str,
+    /// Whether `branch` is a local branch that can be committed to directly.
+    is_branch: bool,
+    dir: &'a str,
+    base: &'a str,
+    /// Files of the dry run awaiting confirmation, empty when choosing files.
+    files: &'a [Uploaded],
+    /// `files` as submitted back with the confirmation, one `<oid> <path>` per
+    /// line.
+    listing: &'a str,
+    /// Token of the pending upload holding the contents of `files`.
+    token: &'a str,
+    max_file_size: &'a str,
+    max_upload_size: &'a str,
+}
+
+impl<'a> UploadTemplate<'a> {
+    fn count(&self, status: Status) -> usize {
+        self.files
+            .iter()
+            .filter(|inner| inner.status == status)
+            .count()
+    }
+}
+
+/// Loads the repository and the commit uploads start from, which is missing in
+/// a repository without commits.
+async fn load(
+    state: &State,
+    path: &[String],
+    identity: Option<Identity>,
+) -> Result<(Context, Option<Oid>), HttpResponse> {
+    let context = Context::load(state, &path[0], &path[1], identity).await?;
+    context.writer()?;
+    let branch = path[2].replace("%2F", "/").replace("%2f", "/");
+    let commit = match refs::resolve(&context.repo, &branch) {
+        Ok(commit) => Some(commit.id()),
+        Err(_) if refs::is_empty(&context.repo) => None,
+        Err(error) => return Err(HttpResponse::NotFound().body(error)),
+    };
+    Ok((context, commit))
+}
+
+/// `/upload/{branch}/{dir}`: chooses files to upload into a directory.
+pub async fn index(
+    path: web::Path<Vec<String>>,
+    state: web::Data<State>,
+    identity: Option<Identity>,
+) -> impl Responder {
+    let path = path.into_inner();
+    let (context, commit) = match load(&state, &path, identity).await {
+        Ok(loaded) => loaded,
+        Err(response) => return response,
+    };
+    render(&context, &path, commit, &[], "")
+}
+
+/// Receives the chosen files and shows what committing them would change. The
+/// files are kept as a pending upload and nothing is written to the repository
+/// until that summary is confirmed.
+pub async fn preview(
+    path: web::Path<Vec<String>>,
+    state: web::Data<State>,
+    identity: Option<Identity>,
+    mut payload: Multipart,
+) -> impl Responder {
+    let path = path.into_inner();
+    let (context, commit) = match load(&state, &path, identity).await {
+        Ok(loaded) => loaded,
+        Err(response) => return response,
+    };
+    let user = match context.writer() {
+        Ok(user) => user,
+        Err(response) => return response,
+    };
+    let repo = &context.repo;
+    let dir = dir(&path);
+    let incomplete = |error: actix_multipart::MultipartError| {
+        HttpResponse::BadRequest().body(format!("the upload is incomplete: {error}"))
+    };
+
+    let mut received: Vec<(String, Vec<u8>)> = Vec::new();
+    let mut extract = false;
+    let mut total = 0;
+    loop {
+        let mut field = match payload.try_next().await {
+            Ok(Some(field)) => field,
+            Ok(None) => break,
+            Err(error) => return incomplete(error),
+        };
+        let name = field.name().unwrap_or_default().to_owned();
+        let filename = field
+            .content_disposition()
+            .and_then(|inner| inner.get_filename())
+            .map(str::to_owned);
+        let mut data = Vec::new();
+        loop {
+            let chunk = match field.try_next().await {
+                Ok(Some(chunk)) => chunk,
+                Ok(None) => break,
+                Err(error) => return incomplete(error),
+            };
+            total += chunk.len();
+            if total > MAX_UPLOAD_SIZE {
+                return upload_too_large();
+            }
+            data.extend_from_slice(&chunk);
+        }
+        match (name.as_str(), filename) {
+            ("extract", _) => extract = data == b"true",
+            ("files", Some(filename)) if !filename.is_empty() => received.push((filename, data)),
+            _ => {}
+        }
+    }
+
+    let mut files: Vec<(String, Vec<u8>)> = Vec::new();
+    // Size of the files to commit, which differs from what was received once
+    // archives are extracted.
+    let mut size = 0;
+    for (filename, data) in received {
+        if extract && filename.to_ascii_lowercase().ends_with(".zip") {
+            if let Err(response) = unzip(&data, &mut files, &mut size) {
+                return response;
+            }
+        } else {
+            size += data.len();
+            files.push((filename, data));
+        }
+    }
+    if files.is_empty() {
+        return HttpResponse::BadRequest().body("no files were uploaded");
+    }
+    if let Err(response) =
+        check_limits(files.iter().map(|(name, data)| (name.as_str(), data.len())))
+    {
+        return response;
+    }
+
+    let base_tree = commit
+        .and_then(|commit| repo.find_commit(commit).ok())
+        .and_then(|commit| commit.tree().ok());
+    let mut uploaded: Vec<Uploaded> = Vec::new();
+    let mut blobs: HashMap<Oid, Vec<u8>> = HashMap::new();
+    for (filename, data) in files {
+        let Some(path) = editor::clean_path(&join(dir, &filename)) else {
+            return HttpResponse::BadRequest()
+                .body(format!("'{filename}' is not a valid file name"));
+        };
+        let oid = match Oid::hash_object(ObjectType::Blob, &data) {
+            Ok(oid) => oid,
+            Err(error) => {
+                return HttpResponse::InternalServerError().body(error.message().to_owned())
+            }
+        };
+        let status = match existing(base_tree.as_ref(), &path) {
+            Ok(None) => Status::Added,
+            Ok(Some(existing)) if existing == oid => Status::Unchanged,
+            Ok(Some(_)) => Status::Overwritten,
+            Err(response) => return response,
+        };
+        // A later file with the same path replaces an earlier one.
+        uploaded.retain(|inner| inner.path != path);
+        uploaded.push(Uploaded {
+            path,
+            oid,
+            size: format_size(data.len()),
+            status,
+        });
+        blobs.insert(oid, data);
+    }
+    uploaded.sort_by(|a, b| a.path.cmp(&b.path));
+    blobs.retain(|oid, _| uploaded.iter().any(|inner| inner.oid == *oid));
+
+    let token = state.upload.insert(Stash {
+        user: user._id,
+        repository: context.repository._id,
+        created: Instant::now(),
+        blobs,
+    });
+    render(&context, &path, commit, &uploaded, &token)
+}
+
+fn render(
+    context: &Context,
+    path: &[String],
+    commit: Option<Oid>,
+    files: &[Uploaded],
+    token: &str,
+) -> HttpResponse {
+    let (username, name) = (&path[0], &path[1]);
+    let branch = path[2].replace("%2F", "/").replace("%2f", "/");
+    let dir = dir(path);
+    let listing: Vec<String> = files
+        .iter()
+        .map(|inner| format!("{} {}", inner.oid, inner.path))
+        .collect();
+    let base = commit.map(|commit| commit.to_string()).unwrap_or_default();
+    let title = format!("upload to {name}/{dir}");
+
+    UploadTemplate {
+        title: &title,
+        identity: &context.identity,
+        repository: &context.repository,
+        username,
+        name,
+        branch: &branch,
+        is_branch: context.repo.find_branch(&branch, BranchType::Local).is_ok() || commit.is_none(),
+        dir,
+        base: &base,
+        files,
+        listing: &listing.join("\n"),
+        token,
+        max_file_size: &format_size(MAX_FILE_SIZE),
+        max_upload_size: &format_size(MAX_UPLOAD_SIZE),
+    }
+    .to_response()
+}
+
+#[derive(Debug, serde::Deserialize)]
+pub struct CommitForm {
+    branch: String,
+    #[serde(default)]
+    dir: String,
+    /// Files of the dry run, one `<oid> <path>` per line.
+    files: String,
+    /// Token of the pending upload holding the contents of `files`.
+    #[serde(default)]
+    upload: String,
+    message: String,
+    #[serde(default)]
+    new_branch: String,
+    base: String,
+}
+
+/// Commits the files of a confirmed dry run.
+pub async fn commit(
+    path: web::Path<(String, String)>,
+    state: web::Data<State>,
+    identity: Option<Identity>,
+    form: web::Form<CommitForm>,
+) -> impl Responder {
+    let (username, name) = path.into_inner();
+    let context = match Context::load(&state, &username, &name, identity).await {
+        Ok(context) => context,
+        Err(response) => return response,
+    };
+    let user = match context.writer() {
+        Ok(user) => user,
+        Err(response) => return response,
+    };
+    let repo = &context.repo;
+
+    let mut files: Vec<(String, Oid)> = Vec::new();
+    for line in form.files.lines().filter(|line| !line.trim().is_empty()) {
+        let parsed = line.split_once(' ').and_then(|(oid, path)| {
+            let oid = Oid::from_str(oid).ok()?;
+            Some((editor::clean_path(path)?, oid))
+        });
+        let Some((path, oid)) = parsed else {
+            return HttpResponse::BadRequest().body(format!("invalid upload entry '{line}'"));
+        };
+        files.retain(|(inner, _)| *inner != path);
+        files.push((path, oid));
+    }
+    if files.is_empty() {
+        return HttpResponse::BadRequest().body("no files were uploaded");
+    }
+    let blobs = state
+        .upload
+        .take(&form.upload, user._id, context.repository._id);
+    for (_, oid) in &files {
+        let Some(data) = blobs.get(oid) else {
+            continue;
+        };
+        if let Err(error) = repo.blob(data) {
+            return HttpResponse::InternalServerError().body(error.message().to_owned());
+        }
+    }
+    if let Some((path, _)) = files.iter().find(|(_, oid)| repo.find_blob(*oid).is_err()) {
+        return HttpResponse::BadRequest().body(format!(
+            "'{path}' is no longer available, upload the files again"
+        ));
+    }
+    // The listing comes back from the browser, so only blobs of acceptable
+    // size that are already in the repository are taken.
+    let sizes = files.iter().map(|(path, oid)| {
+        let size = repo
+            .odb()
+            .and_then(|odb| odb.read_header(*oid))
+            .ok()
+            .filter(|(_, kind)| *kind == ObjectType::Blob)
+            .map_or(usize::MAX, |(size, _)| size);
+        (path.as_str(), size)
+    });
+    if let Err(response) = check_limits(sizes) {
+        return response;
+    }
+
+    let target = Target {
+        branch: form.branch.trim(),
+        message: &form.message,
+        new_branch: &form.new_branch,
+        base: &form.base,
+    };
+    let touched: Vec<&str> = files.iter().map(|(path, _)| path.as_str()).collect();
+    let committed = editor::commit_changes(repo, user, &target, &touched, |parent_tree| {
+        files
+            .iter()
+            .map(|(path, oid)| {
+                existing(parent_tree, path)?;
+                let mode = parent_tree
+                    .and_then(|tree| tree.get_path(std::path::Path::new(path)).ok())
+                    .map_or(FILEMODE_BLOB, |entry| entry.filemode());
+                Ok::<Change, HttpResponse>((path.clone(), Some((*oid, mode))))
+            })
+            .collect()
+    });
+    let (target, _) = match committed {
+        Ok(committed) => committed,
+        Err(response) => return response,
+    };
+
+    let new_branch = form.new_branch.trim();
+    if !new_branch.is_empty() {
+        let description = format!("{name}:{new_branch}");
+        state
+            .database
+            .add_user_log(user, Event::BranchCreate, Some(description))
+            .await;
+    }
+    let rev = target.replace('/', "%2F");
+    let dir = form.dir.trim_matches('/');
+    HttpResponse::SeeOther()
+        .insert_header(("Location", format!("/@{username}/{name}/tree/{rev}/{dir}")))
+        .finish()
+}
+
+fn dir(path: &[String]) -> &str {
+    path.get(3).map(|tail| tail.trim_matches('/')).unwrap_or("")
+}
+
+fn join(dir: &str, path: &str) -> String {
+    if dir.is_empty() {
+        path.to_owned()
+    } else {
+        format!("{dir}/{path}")
+    }
+}
+
+/// The blob currently at `path`, failing if something other than a file is in
+/// the way.
+fn existing(tree: Option<&Tree>, path: &str) -> Result<Option<Oid>, HttpResponse> {
+    let Some(tree) = tree else {
+        return Ok(None);
+    };
+    let mut prefix = path;
+    while let Some((parent, _)) = prefix.rsplit_once('/') {
+        prefix = parent;
+        if let Ok(entry) = tree.get_path(std::path::Path::new(prefix)) {
+            if entry.kind() != Some(ObjectType::Tree) {
+                return Err(HttpResponse::Conflict().body(format!("'{prefix}' is not a directory")));
+            }
+        }
+    }
+    match tree.get_path(std::path::Path::new(path)) {
+        Ok(entry) if entry.kind() == Some(ObjectType::Blob) => Ok(Some(entry.id())),
+        Ok(_) => Err(HttpResponse::Conflict().body(format!("'{path}' is not a file"))),
+        Err(_) => Ok(None),
+    }
+}
+
+/// Adds the files in a zip archive to `files` with their paths inside it, and
+/// their sizes to `total`. Sizes are checked while reading since the ones
+/// recorded in the archive can't be trusted, and reading stops as soon as the
+/// upload is over its limits.
+fn unzip(
+    data: &[u8],
+    files: &mut Vec<(String, Vec<u8>)>,
+    total: &mut usize,
+) -> Result<(), HttpResponse> {
+    let invalid = |error: zip::result::ZipError| {
+        HttpResponse::BadRequest().body(format!("could not read the archive: {error}"))
+    };
+    let mut archive = zip::ZipArchive::new(Cursor::new(data)).map_err(invalid)?;
+    for index in 0..archive.len() {
+        let file = archive.by_index(index).map_err(invalid)?;
+        if file.is_dir() {
+            continue;
+        }
+        let Some(path) = file
+            .enclosed_name()
+            .and_then(|path| path.to_str())
+            .map(str::to_owned)
+        else {
+            return Err(HttpResponse::BadRequest()
+                .body(format!("'{}' is not a valid file name", file.name())));
+        };
+        let limit = MAX_FILE_SIZE.min(MAX_UPLOAD_SIZE.saturating_sub(*total));
+        let mut content = Vec::new();
+        file.take(limit as u64 + 1)
+            .read_to_end(&mut content)
+            .map_err(|error| {
+                HttpResponse::BadRequest().body(format!("could not read '{path}': {error}"))
+            })?;
+        if content.len() > MAX_FILE_SIZE {
+            return Err(file_too_large(&path));
+        }
+        *total += content.len();
+        if *total > MAX_UPLOAD_SIZE {
+            return Err(upload_too_large());
+        }
+        files.push((path, content));
+        if files.len() > MAX_UPLOAD_FILES {
+            return Err(too_many_files());
+        }
+    }
+    Ok(())
+}
+
+fn check_limits<'a>(files: impl IntoIterator<Item = (&'a str, usize)>) -> Result<(), HttpResponse> {
+    let mut count = 0;
+    let mut total: usize = 0;
+    for (path, size) in files {
+        if size > MAX_FILE_SIZE {
+            return Err(file_too_large(path));
+        }
+        count += 1;
+        total = total.saturating_add(size);
+    }
+    if total > MAX_UPLOAD_SIZE {
+        return Err(upload_too_large());
+    }
+    if count > MAX_UPLOAD_FILES {
+        return Err(too_many_files());
+    }
+    Ok(())
+}
+
+fn file_too_large(path: &str) -> HttpResponse {
+    HttpResponse::PayloadTooLarge().body(format!(
+        "'{path}' is larger than {}",
+        format_size(MAX_FILE_SIZE)
+    ))
+}
+
+fn upload_too_large() -> HttpResponse {
+    HttpResponse::PayloadTooLarge().body(format!(
+        "uploads are limited to {} in total",
+        format_size(MAX_UPLOAD_SIZE)
+    ))
+}
+
+fn too_many_files() -> HttpResponse {
+    HttpResponse::PayloadTooLarge().body(format!("uploads are limited to {MAX_UPLOAD_FILES} files"))
+}
+
+fn format_size(size: usize) -> String {
+    humansize::format_size(size, humansize::DECIMAL.decimal_places(0))
+}
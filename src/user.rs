@@ -1,21 +1,33 @@
 use std::str::FromStr;
 
 use crate::{
-    model::{Event, Log, Repository, User},
-    State,
+    credentials::{create_password, create_password_using_salt},
+    disk,
+    model::{Event, Log, Registration, Repository, Role, User},
+    organizations, repository, validation, State,
 };
 use actix_identity::Identity;
 use actix_web::{get, http::Method, web, HttpMessage, HttpRequest, HttpResponse, Responder};
 use askama::Template;
 use askama_actix::TemplateToResponse;
 use bson::oid::ObjectId;
-use rand::{distributions::Alphanumeric, thread_rng, Rng};
 use serde::{Deserialize, Serialize};
 
 #[derive(Template)]
 #[template(path = "signup.html")]
 struct SignupTemplate<'a> {
     title: &'a str,
+    registration: Registration,
+    errors: &'a [String],
+    email: &'a str,
+    username: &'a str,
+    invite: &'a str,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct SignupQuery {
+    #[serde(default)]
+    invite: String,
 }
 
 #[derive(Serialize, Deserialize)]
@@ -23,12 +35,16 @@ pub struct SignupForm {
     email: String,
     username: String,
     password: String,
+    /// The invite code, when registration is by invitation.
+    #[serde(default)]
+    invite: String,
 }
 
 pub async fn signup(
     req: HttpRequest,
     state: web::Data<State>,
     identity: Option<Identity>,
+    query: web::Query<SignupQuery>,
     params: Option<web::Form<SignupForm>>,
 ) -> impl Responder {
     if identity.is_some() {
@@ -36,32 +52,76 @@ pub async fn signup(
             .insert_header(("Location", "/"))
             .finish();
     }
-    match *req.method() {
-        Method::GET => SignupTemplate { title: "sign up" }.to_response(),
-        Method::POST => {
-            let params = params.unwrap();
-            let collection = state.db.collection::<User>("users");
-
-            let (password, salt) = create_password(&params.password);
-            let user = User {
-                _id: bson::oid::ObjectId::default(),
-                email: params.email.clone(),
-                username: params.username.clone(),
-                password,
-                salt,
-                created_at: unix_timestamp(),
-                updated_at: unix_timestamp(),
-                log: Vec::new(),
-            };
-            if collection.insert_one(&user, None).await.is_err() {
-                todo!();
+    let registration = state.database.site_settings().await.registration;
+    let params = match (req.method(), params) {
+        (&Method::POST, Some(params)) if registration != Registration::Closed => params,
+        _ => {
+            return SignupTemplate {
+                title: "sign up",
+                registration,
+                errors: &[],
+                email: "",
+                username: "",
+                invite: query.invite.trim(),
             }
-            HttpResponse::SeeOther()
-                .insert_header(("Location", "/login"))
-                .finish()
+            .to_response()
         }
-        _ => unimplemented!(),
+    };
+
+    let email = params.email.trim();
+    let username = params.username.trim();
+    let invite = params.invite.trim();
+    let mut errors: Vec<String> = [
+        validation::check_username(username),
+        validation::check_email(email),
+        validation::check_password(&params.password),
+    ]
+    .into_iter()
+    .filter_map(Result::err)
+    .collect();
+    if errors.is_empty() {
+        if state.database.username_taken(username, None).await {
+            errors.push(format!("The username '{username}' is taken"));
+        }
+        if state.database.email_taken(email, None).await {
+            errors.push(format!("There is already an account for {email}"));
+        }
+    }
+    let invited = registration == Registration::Invite;
+    if errors.is_empty() && invited && !state.database.claim_invite(invite, username).await {
+        errors.push("This invite code is unknown or was used already".to_owned());
+    }
+
+    if errors.is_empty() {
+        let result = state
+            .database
+            .new_user(email, username, &params.password, false)
+            .await;
+        match result {
+            Ok(_) => {
+                return HttpResponse::SeeOther()
+                    .insert_header(("Location", "/login"))
+                    .finish()
+            }
+            // Someone signed up with the same name or email in the meantime.
+            Err(_) => {
+                if invited {
+                    state.database.release_invite(invite).await;
+                }
+                errors.push(format!("The username '{username}' or the email is taken"));
+            }
+        }
+    }
+
+    SignupTemplate {
+        title: "sign up",
+        registration,
+        errors: &errors,
+        email,
+        username,
+        invite,
     }
+    .to_response()
 }
 
 #[derive(Template)]
@@ -93,11 +153,16 @@ pub async fn login(
             let Some(params) = params else {
                 return HttpResponse::SeeOther()
                     .insert_header(("Location", "/login"))
-                    .finish()
+                    .finish();
             };
             let username = params.username.clone();
             let password = params.password.clone();
-            let Some(user) = state.database.login(&username, &password).await else {
+            let user = state
+                .database
+                .login(&username, &password)
+                .await
+                .filter(|user| !user.suspended);
+            let Some(user) = user else {
                 return HttpResponse::SeeOther()
                     .insert_header(("Location", "/login"))
                     .finish();
@@ -140,6 +205,7 @@ struct IndexTemplate<'a> {
     user: &'a User,
     identity: &'a Option<User>,
     repositories: &'a [Repository],
+    organizations: &'a [User],
 }
 
 #[get("/@{username}")]
@@ -161,18 +227,20 @@ async fn index(
     let Some(user) = state.database.find_user(&username).await else {
         return Ok(HttpResponse::NotFound().finish());
     };
+    if user.is_organization() {
+        return Ok(organizations::index(&state, user, identity).await);
+    }
 
     let Some(repositories) = state.database.find_user_repositories(user._id).await else {
         return Ok(HttpResponse::NotFound().finish());
     };
 
-    let repositories: Vec<_> = match identity.as_ref() {
-        Some(identity) if identity._id == user._id => repositories.into_iter().collect(),
-        _ => repositories
-            .into_iter()
-            .filter(|inner| inner.visibility == "public")
-            .collect(),
-    };
+    let owner = Some(user.clone());
+    let repositories: Vec<_> = repositories
+        .into_iter()
+        .filter(|inner| inner.can(&owner, &identity, Role::Read))
+        .collect();
+    let organizations = state.database.find_organizations(&user).await;
 
     let title = &username;
 
@@ -181,6 +249,7 @@ async fn index(
         user: &user,
         identity: &identity,
         repositories: &repositories,
+        organizations: &organizations,
     }
     .to_response())
 }
@@ -189,6 +258,9 @@ async fn index(
 #[template(path = "new.html")]
 struct NewRepositoryTemplate<'a> {
     title: &'a str,
+    username: &'a str,
+    /// Organizations the user can create repositories for.
+    organizations: &'a [User],
 }
 
 #[derive(Serialize, Deserialize)]
@@ -196,6 +268,10 @@ pub struct NewRepositoryForm {
     name: String,
     description: String,
     visibility: String,
+    /// User or organization the repository belongs to, the signed in user if
+    /// empty.
+    #[serde(default)]
+    owner: String,
 }
 
 pub async fn new(
@@ -212,22 +288,63 @@ pub async fn new(
 
     let id = identity.id().unwrap();
 
-    if state.database.find_user_from_id(&id).await.is_none() {
+    let Some(user) = state.database.find_user_from_id(&id).await else {
         return HttpResponse::SeeOther()
             .insert_header(("Location", "/"))
             .finish();
-    }
+    };
+    // Only owners create repositories in an organization.
+    let organizations: Vec<_> = state
+        .database
+        .find_organizations(&user)
+        .await
+        .into_iter()
+        .filter(|inner| {
+            inner
+                .organization
+                .as_ref()
+                .is_some_and(|organization| organization.is_owner(Some(&user)))
+        })
+        .collect();
 
     match *req.method() {
-        Method::GET => NewRepositoryTemplate { title: "" }.to_response(),
+        Method::GET => NewRepositoryTemplate {
+            title: "",
+            username: &user.username,
+            organizations: &organizations,
+        }
+        .to_response(),
         Method::POST => {
             let form = form.unwrap();
 
-            let id = identity.id().unwrap();
-            let user = state.database.find_user_from_id(&id).await.unwrap();
-            let username = &user.username;
+            let owner = if form.owner.is_empty() || form.owner == user.username {
+                &user
+            } else {
+                match organizations
+                    .iter()
+                    .find(|inner| inner.username == form.owner)
+                {
+                    Some(organization) => organization,
+                    None => return HttpResponse::Forbidden().body("Unauthorized"),
+                }
+            };
+            let username = &owner.username;
 
-            let repository_name = form.name.clone();
+            let repository_name = form.name.trim().to_owned();
+            if !repository::is_valid_name(&repository_name) {
+                return HttpResponse::BadRequest().body(format!(
+                    "'{repository_name}' is not a valid name; use letters, numbers, '-', '_' and '.'"
+                ));
+            }
+            // Directories are named after repositories alone, so a name used
+            // by any owner, or left behind by a rename, is taken.
+            if std::path::Path::new(&repository_name)
+                .symlink_metadata()
+                .is_ok()
+            {
+                return HttpResponse::Conflict()
+                    .body(format!("the name '{repository_name}' is already taken"));
+            }
             let description = if !form.description.is_empty() {
                 Some(form.description.clone())
             } else {
@@ -235,23 +352,42 @@ pub async fn new(
             };
             let result = state
                 .database
-                .new_repository(Some(&user), &repository_name, description, &form.visibility)
+                .new_repository(
+                    Some(owner),
+                    &repository_name,
+                    description,
+                    &form.visibility,
+                    disk::INITIAL_BRANCH,
+                )
                 .await;
-            if result.eq(&Err(crate::database::Error::Found)) {
-                return HttpResponse::SeeOther()
-                    .insert_header(("Location", "/new"))
-                    .finish();
+            if result.is_err() {
+                return HttpResponse::Conflict().body(format!(
+                    "a repository named '{repository_name}' already exists"
+                ));
             }
 
-            if result.is_ok() {
-                state
+            if let Err(error) = disk::init_repository(&repository_name, disk::INITIAL_BRANCH) {
+                let deleted = state
                     .database
-                    .add_user_log(&user, Event::RepositoryCreate, Some(repository_name))
+                    .delete_repository(&Some(owner.clone()), &repository_name)
                     .await;
+                debug_assert!(deleted.is_ok());
+                return HttpResponse::InternalServerError().body(format!(
+                    "the repository could not be created: {}",
+                    error.message()
+                ));
             }
+            state
+                .database
+                .add_user_log(
+                    &user,
+                    Event::RepositoryCreate,
+                    Some(repository_name.clone()),
+                )
+                .await;
 
             HttpResponse::SeeOther()
-                .insert_header(("Location", format!("/@{username}/{}", form.name)))
+                .insert_header(("Location", format!("/@{username}/{repository_name}")))
                 .finish()
         }
         _ => HttpResponse::NotFound().finish(),
@@ -368,8 +504,23 @@ pub async fn update(
     };
 
     let id = identity.id().unwrap();
+    let Some(user) = state.database.find_user_from_id(&id).await else {
+        return HttpResponse::NotFound().finish();
+    };
     let form = form.into_inner();
-    let username = form.username;
+    let username = form.username.trim().to_owned();
+    let email = form.email.trim();
+    if let Err(error) =
+        validation::check_username(&username).and_then(|_| validation::check_email(email))
+    {
+        return HttpResponse::BadRequest().body(error);
+    }
+    if state.database.username_taken(&username, Some(&user)).await {
+        return HttpResponse::Conflict().body(format!("the name '{username}' is already taken"));
+    }
+    if state.database.email_taken(email, Some(&user)).await {
+        return HttpResponse::Conflict().body(format!("there is already an account for {email}"));
+    }
 
     let users = state.db.collection::<User>("users");
     let result = users
@@ -377,7 +528,7 @@ pub async fn update(
             bson::doc! { "_id": ObjectId::from_str(&id).unwrap() },
             bson::doc! {
                 "$set": {
-                    "email": &form.email,
+                    "email": email,
                     "username": &username,
                     "updated_at": unix_timestamp(),
                 },
@@ -398,6 +549,38 @@ pub async fn update(
     }
 }
 
+#[derive(Serialize, Deserialize)]
+pub struct DiffLayoutForm {
+    view: String,
+}
+
+/// Saves the diff layout used when a diff URL doesn't ask for one.
+pub async fn update_diff_layout(
+    state: web::Data<State>,
+    identity: Option<Identity>,
+    form: web::Form<DiffLayoutForm>,
+) -> impl Responder {
+    let user = match identity.map(|identity| identity.id()) {
+        Some(Ok(id)) => state.database.find_user_from_id(&id).await,
+        _ => None,
+    };
+    let Some(user) = user else {
+        return HttpResponse::SeeOther()
+            .insert_header(("Location", "/login"))
+            .finish();
+    };
+    let split = match form.view.as_str() {
+        "split" => true,
+        "unified" => false,
+        view => return HttpResponse::BadRequest().body(format!("unknown layout '{view}'")),
+    };
+
+    state.database.set_split_diff(&user, split).await;
+    HttpResponse::SeeOther()
+        .insert_header(("Location", "/settings/profile"))
+        .finish()
+}
+
 #[derive(Serialize, Deserialize)]
 pub struct UpdatePasswordForm {
     password0: String,
@@ -474,18 +657,3 @@ pub fn unix_timestamp() -> i64 {
     let now = time::OffsetDateTime::now_utc();
     now.unix_timestamp()
 }
-
-fn create_password_using_salt(password: &str, salt: &str) -> String {
-    blake3::hash(format!("{}{}", password, salt).as_bytes()).to_string()
-}
-
-fn create_password(password: &str) -> (String, String) {
-    let random_values: String = thread_rng()
-        .sample_iter(&Alphanumeric)
-        .take(40)
-        .map(char::from)
-        .collect();
-    let salt = blake3::hash(format!("{random_values}{}", unix_timestamp()).as_bytes()).to_string();
-    let password = blake3::hash(format!("{password}{salt}").as_bytes()).to_string();
-    (password, salt)
-}
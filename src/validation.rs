@@ -0,0 +1,79 @@
+//! Rules for the names, emails and passwords of new accounts.
+
+/// Names users and organizations can't take because they are, or may become,
+/// paths of the site.
+pub const RESERVED_NAMES: &[&str] = &[
+    "about",
+    "admin",
+    "api",
+    "delete",
+    "explore",
+    "help",
+    "invitations",
+    "login",
+    "logout",
+    "new",
+    "organizations",
+    "settings",
+    "signup",
+    "static",
+];
+
+pub const MAX_USERNAME_LENGTH: usize = 39;
+pub const MIN_PASSWORD_LENGTH: usize = 8;
+
+/// Checks the name of a new user or organization: letters, digits, `-` and
+/// `_`, starting with a letter or a digit, and not reserved.
+pub fn check_username(username: &str) -> Result<(), String> {
+    if username.is_empty() {
+        return Err("Username can't be empty".to_owned());
+    }
+    if username.len() > MAX_USERNAME_LENGTH {
+        return Err(format!(
+            "Username can't be longer than {MAX_USERNAME_LENGTH} characters"
+        ));
+    }
+    if !username
+        .chars()
+        .all(|inner| inner.is_ascii_alphanumeric() || matches!(inner, '-' | '_'))
+    {
+        return Err("Username may only contain letters, digits, '-' and '_'".to_owned());
+    }
+    if !username.starts_with(|inner: char| inner.is_ascii_alphanumeric()) {
+        return Err("Username must start with a letter or a digit".to_owned());
+    }
+    if RESERVED_NAMES
+        .iter()
+        .any(|reserved| reserved.eq_ignore_ascii_case(username))
+    {
+        return Err(format!("'{username}' is reserved"));
+    }
+    Ok(())
+}
+
+/// A loose check that `email` looks like an address; confirming it is left to
+/// whoever reads it.
+pub fn check_email(email: &str) -> Result<(), String> {
+    let valid = email.len() <= 254
+        && !email.contains(char::is_whitespace)
+        && email.split_once('@').is_some_and(|(local, domain)| {
+            !local.is_empty()
+                && !domain.contains('@')
+                && domain.contains('.')
+                && !domain.starts_with('.')
+                && !domain.ends_with('.')
+        });
+    if !valid {
+        return Err(format!("'{email}' is not a valid email address"));
+    }
+    Ok(())
+}
+
+pub fn check_password(password: &str) -> Result<(), String> {
+    if password.chars().count() < MIN_PASSWORD_LENGTH {
+        return Err(format!(
+            "Password must be at least {MIN_PASSWORD_LENGTH} characters long"
+        ));
+    }
+    Ok(())
+}